@@ -0,0 +1,79 @@
+//! Which account each task runs as.
+//!
+//! Tasks are approved one by one, and not always for the same account.
+//! [`ACCOUNTS`] maps task names to the token file of the account a task should
+//! use, e.g. `{"peer_review": "./token-deadbeefbot-ii.secret"}`. Tasks not
+//! listed use the default token. Optional.
+//!
+//! Wikis can need grants of their own too. [`WIKI_TOKENS`] maps API URLs to
+//! the token file used there by tasks without an account of their own, e.g.
+//! `{"https://zh.wikipedia.org/w/api.php": "./token-zhwiki.secret"}`. Wikis
+//! not listed use the default token. Optional.
+//!
+//! Any of these files can hold a BotPassword instead of an OAuth token, see
+//! [`credentials`](crate::credentials).
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use color_eyre::eyre::{bail, eyre};
+
+use crate::client::Client;
+use crate::edit::TaskFlags;
+use crate::{checkpoint, secrets, site_with_token, Result};
+
+pub const ACCOUNTS: &str = "accounts.json";
+
+pub const WIKI_TOKENS: &str = "wiki-tokens.json";
+
+static TOKEN_FILES: LazyLock<Result<HashMap<String, String>, String>> =
+    LazyLock::new(|| match checkpoint::read_config(ACCOUNTS) {
+        Ok(Some(s)) => serde_json::from_str(&s).map_err(|e| format!("invalid {ACCOUNTS}: {e}")),
+        Ok(None) => Ok(HashMap::new()),
+        Err(e) => Err(format!("{e:#}")),
+    });
+
+static WIKI_TOKEN_FILES: LazyLock<Result<HashMap<String, String>, String>> =
+    LazyLock::new(|| match checkpoint::read_config(WIKI_TOKENS) {
+        Ok(Some(s)) => serde_json::from_str(&s).map_err(|e| format!("invalid {WIKI_TOKENS}: {e}")),
+        Ok(None) => Ok(HashMap::new()),
+        Err(e) => Err(format!("{e:#}")),
+    });
+
+/// The token file configured for `task`, if any. Unlike other token files,
+/// it has to exist: falling back to the default account would edit as an
+/// account the task isn't approved for.
+pub fn token_file(task: &TaskFlags) -> Result<Option<&'static str>> {
+    let files = TOKEN_FILES.as_ref().map_err(|e| eyre!("{e}"))?;
+    let Some(path) = files.get(task.task) else {
+        return Ok(None);
+    };
+    if secrets::find(path).is_none() {
+        bail!("{path}, the token for {}, doesn't exist", task.task);
+    }
+    Ok(Some(path))
+}
+
+/// The token file configured for the wiki at `api_url`, if any. Like a
+/// task's, it has to exist, since the default token may lack the grants the
+/// wiki needs.
+pub fn wiki_token_file(api_url: &str) -> Result<Option<&'static str>> {
+    let files = WIKI_TOKEN_FILES.as_ref().map_err(|e| eyre!("{e}"))?;
+    let Some(path) = files.get(api_url) else {
+        return Ok(None);
+    };
+    if secrets::find(path).is_none() {
+        bail!("{path}, the token for {api_url}, doesn't exist");
+    }
+    Ok(Some(path))
+}
+
+/// A session on `api_url` as the account for `task`.
+pub async fn bot_for(api_url: &str, task: &TaskFlags) -> Result<wiki::Bot> {
+    site_with_token(api_url, token_file(task)?).await
+}
+
+/// Like [`bot_for`], logging in again when the session is lost.
+pub async fn client_for(api_url: &str, task: &TaskFlags) -> Result<Client> {
+    Client::with_token_file(api_url, token_file(task)?).await
+}
@@ -1,22 +1,29 @@
 //! Merge `{{On this day}}` templates into `{{article history}}` if exists.
 
-use std::fs::{File, OpenOptions};
 use std::io::stdin;
 use std::process;
+use std::time::Duration;
 
 use color_eyre::eyre::bail;
-use colored_diff::PrettyDifference;
 use extractors::ExtractContext;
-use parsoid::{Template, WikiMultinode, WikinodeIterator};
+use parsoid::map::IndexMap;
+use parsoid::{Template, WikiMultinode, Wikicode, WikinodeIterator};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::rng;
-use tracing::{debug, info, trace, warn};
-use wiki::api::RequestBuilderExt;
-use wiki::req::parse::{Parse, ParseProp};
-use wiki::req::{self, PageSpec};
+use rand::{rng, Rng, SeedableRng};
+use tracing::{debug, info, trace};
+use wiki::req::PageSpec;
 
+use crate::accounts::bot_for;
+use crate::articlehistory::builder::{without_substs, ParamBuilder};
 use crate::articlehistory::extractors::{ArticleHistoryExtractor, Extractor};
-use crate::{check_nobots, enwiki_bot, enwiki_parsoid, Result};
+use crate::edit::{after_edit, compare, save, EditFlags, TaskFlags};
+use crate::siteinfo::SiteInfo;
+use crate::source::{PageSource, PetScanSource};
+use crate::{
+    check_nobots, diff, enwiki_parsoid, fetch_content, http, maxlag, policy, run_lock, runs,
+    selection, Parsoid, Result, ENWIKI_API,
+};
 #[allow(unused_imports)]
 use crate::{parsoid_from_url, site_from_url};
 
@@ -27,14 +34,17 @@ mod types;
 
 pub use types::*;
 
-pub async fn treat_inner(
+const FLAGS: TaskFlags = TaskFlags::new("articlehistory", EditFlags::MINOR_BOT);
+
+/// Merges the templates on `wikicode`, the talk page `title`, into its
+/// `{{article history}}`, mounting one above the banner shell if there is
+/// none. Returns `false` without merging anything if the bot is excluded.
+async fn merge(
     client: &wiki::Bot,
-    parsoid: &parsoid::Client,
+    site: &SiteInfo,
     title: &str,
-    prompt: bool,
-) -> Result<()> {
-    let wikicode = parsoid.get(title).await?.into_mutable();
-    let rev = wikicode.revision_id().unwrap();
+    wikicode: &Wikicode,
+) -> Result<bool> {
     let templates = wikicode.filter_templates()?;
     let ah_created;
 
@@ -77,16 +87,16 @@ pub async fn treat_inner(
     let cx = ExtractContext {
         client,
     //    parsoid,
+        site,
         title,
         allow_interactive: false,
     };
 
-    info!("Extracting [[{title}]], rev: {rev}");
     trace!("AH: {ah:#?}");
 
     for template in &templates {
         if check_nobots(template) {
-            return Ok(());
+            return Ok(false);
         }
 
         extractors::extract_all(cx, template, &mut ah).await?;
@@ -95,6 +105,87 @@ pub async fn treat_inner(
     trace!("extraction complete, AH: {ah:#?}");
 
     ah.into_template(&mut article_history.clone())?;
+    Ok(true)
+}
+
+/// Converts `wikitext`, the header of the talk page `title` pasted by hand,
+/// the same way the task converts pages, so that conversions by hand and by
+/// the bot can't diverge.
+pub async fn convert(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    site: &SiteInfo,
+    title: &str,
+    wikitext: &str,
+) -> Result<String> {
+    let wikicode = parsoid.transform_to_html(wikitext).await?.into_mutable();
+    if !merge(client, site, title, &wikicode).await? {
+        bail!("the bot is excluded from [[{title}]]");
+    }
+    let text = parsoid.transform_to_wikitext(&wikicode).await?;
+    Ok(text.trim_start().to_owned())
+}
+
+/// The main page appearances parameters of `{{article history}}` after
+/// merging the templates in `wikitext` from the talk page `title`, one per
+/// line. They include those of the `{{article history}}` in `wikitext` if
+/// there is one, so that they can replace its own.
+pub async fn convert_params(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    site: &SiteInfo,
+    title: &str,
+    wikitext: &str,
+) -> Result<String> {
+    let wikicode = parsoid.transform_to_html(wikitext).await?.into_mutable();
+    let templates = wikicode.filter_templates()?;
+    let empty = Template::new_simple("Article history");
+    let article_history = templates
+        .iter()
+        .find(|t| ArticleHistoryExtractor.is_extractable(t))
+        .unwrap_or(&empty);
+    let mut ah = ArticleHistoryExtractor.extract(article_history)?;
+    let cx = ExtractContext {
+        client,
+        site,
+        title,
+        allow_interactive: false,
+    };
+    for template in &templates {
+        extractors::extract_all(cx, template, &mut ah).await?;
+    }
+
+    let mut params = IndexMap::new();
+    ParamBuilder::new(&mut params)
+        .add_all(ah.itns)
+        .add_all(ah.dyks)
+        .add_all(ah.otds);
+    Ok(params
+        .iter()
+        .map(|(k, v)| format!("|{}={}\n", without_substs(k), without_substs(v)))
+        .collect())
+}
+
+/// Reads `t`, an `{{article history}}`, the way the task does before merging
+/// into it, failing wherever the task would.
+pub fn validate(t: &Template) -> Result<()> {
+    ArticleHistoryExtractor.extract(t)?.sort_and_update_status()
+}
+
+pub async fn treat(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    site: &SiteInfo,
+    title: &str,
+    prompt: bool,
+) -> Result<()> {
+    info!("Treating [[{title}]]");
+    let wikicode = parsoid.get(title).await?.into_mutable();
+    let rev = wikicode.revision_id().unwrap();
+    info!("Extracting [[{title}]], rev: {rev}");
+    if !merge(client, site, title, &wikicode).await? {
+        return Ok(());
+    }
 
     let text = parsoid.transform_to_wikitext(&wikicode).await?;
     // we sometimes get newlines leftover at the beginning. We need to clean that up
@@ -102,24 +193,30 @@ pub async fn treat_inner(
 
     if prompt {
         // do a pst
-        let val = client
-            .post(req::Action::Parse(Parse {
-                text: Some(text.into()),
-                title: Some(title.into()),
-                onlypst: true,
-                prop: ParseProp::empty(),
-                ..Default::default()
-            }))
-            .send_and_report_err()
-            .await?["parse"]["text"]
-            .take();
-        let val = val.as_str().unwrap();
-        let prev_text = client.fetch_content(title).await?;
-        let diff = PrettyDifference {
-            expected: &prev_text,
-            actual: val,
-        };
-        println!("{diff}");
+        let res = maxlag::api(client.client.post(ENWIKI_API).form(&[
+            ("action", "parse"),
+            ("format", "json"),
+            ("formatversion", "2"),
+            ("title", title),
+            ("text", text),
+            ("onlypst", "1"),
+            ("prop", ""),
+        ]))
+        .await?;
+        if let Some(e) = res.get("error") {
+            bail!("api error: {e}");
+        }
+        let val = res["parse"]["text"].as_str().unwrap();
+        let prev_text = fetch_content(client, ENWIKI_API, title).await?;
+        println!("{}", diff::render(title, &prev_text, val));
+        // the local diff can miss what pre-save transform does to the rest
+        // of the page
+        if std::env::var_os("SERVER_DIFF").is_some() {
+            println!(
+                "{}",
+                compare(client, ENWIKI_API, title, rev as u32, text).await?
+            );
+        }
         println!("Make edit? [y/N/q(uit)]");
         match &*stdin()
             .lines()
@@ -129,15 +226,17 @@ pub async fn treat_inner(
             .to_ascii_lowercase()
         {
             "y" => {
-                client
-                    .build_edit(PageSpec::Title(title.to_owned()))
-                    .text(text)
-                    .summary("implementing {{article history}} ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 3|BRFA]])")
-                    .baserevid(rev as u32)
-                    .minor()
-                    .bot()
-                    .send()
-                    .await?;
+                save(
+                    client,
+                    PageSpec::Title(title.to_owned()),
+                    Some(&prev_text),
+                    text,
+                    "implementing {{article history}} ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 3|BRFA]])",
+                    Some(rev as u32),
+                    &FLAGS,
+                )
+                .await?;
+                after_edit(client, ENWIKI_API, title).await;
             }
             "q" | "quit" => {
                 process::exit(0);
@@ -145,74 +244,55 @@ pub async fn treat_inner(
             _ => {}
         }
     } else {
-        client
-            .build_edit(PageSpec::Title(title.to_owned()))
-            .text(text)
-            .summary("implementing {{article history}} ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 3|BRFA]])")
-            .baserevid(rev as u32)
-            .minor()
-            .bot()
-            .send()
-            .await?;
+        save(
+            client,
+            PageSpec::Title(title.to_owned()),
+            None,
+            text,
+            "implementing {{article history}} ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 3|BRFA]])",
+            Some(rev as u32),
+            &FLAGS,
+        )
+        .await?;
+        after_edit(client, ENWIKI_API, title).await;
     }
 
     Ok(())
 }
 
-pub async fn treat(
-    client: &wiki::Bot,
-    parsoid: &parsoid::Client,
-    title: &str,
-    prompt: bool,
-    cnt: &mut u64,
-    f: &mut File,
-) -> Result<()> {
-    use std::io::Write;
-    info!("Treating [[{title}]]");
-
-    if let Err(e) = treat_inner(client, parsoid, title, prompt).await {
-        warn!(?e);
-        writeln!(f, "Error while treating [[{title}]]: {e}")?;
-    } else {
-        *cnt += 1;
-    }
+/// Treats the pages from `petscan` in random order. Passing the `seed` of an
+/// earlier run, as recorded in its report, treats them in the same order.
+pub async fn main(petscan: &str, seed: Option<u64>) -> Result<()> {
+    // let client = site_from_url("https://test.wikipedia.org/w/api.php").await?;
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
 
-    Ok(())
-}
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let petscan = PetScanSource::new(&http()?.wikimedia, petscan)
+            .cache(Duration::from_secs(24 * 60 * 60))
+            .collect_all();
+        let mut pages = scope.titles(petscan).await?;
+        // let pages = std::fs::read_to_string("ptemp3.txt")?;
+        // let mut pages: Vec<_> = pages.lines().map(str::to_owned).collect();
+        debug!("got {} pages from petscan", pages.len());
 
-pub async fn main(petscan: &str) -> Result<()> {
-    let pages = reqwest::get(petscan)
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-    // let pages: Vec<_> = pages.lines().collect();
-    // let pages = std::fs::read_to_string("ptemp3.txt")?;
-    let mut pages: Vec<_> = pages.lines().collect();
-    debug!("got {} pages from petscan", pages.len());
+        let seed = seed.unwrap_or_else(|| rng().random());
+        info!("shuffling with seed {seed}");
+        runs::note("seed", seed);
+        pages.shuffle(&mut StdRng::seed_from_u64(seed));
+        // let pages = pages.choose_multiple(&mut thread_rng(), 10);
+        // let pages = vec!["Talk:Warsaw Uprising (1794)"];
 
-    pages.shuffle(&mut rng());
-    // let pages = pages.choose_multiple(&mut thread_rng(), 10);
-    // let pages = vec!["Talk:Warsaw Uprising (1794)"];
+        // let parsoid = parsoid_from_url("https://test.wikipedia.org/api/rest_v1")?;
+        let parsoid = enwiki_parsoid()?;
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
 
-    // let client = site_from_url("https://test.wikipedia.org/w/api.php").await?;
-    let client = enwiki_bot().await?;
-
-    // let parsoid = parsoid_from_url("https://test.wikipedia.org/api/rest_v1")?;
-    let parsoid = enwiki_parsoid()?;
-
-    let mut count = 0;
-    let mut f = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open("./logs.txt")?;
-    for page in pages {
-        treat(&client, &parsoid, page, false, &mut count, &mut f).await?;
-        /* if count >= 1 {
-            return Ok(())
-        } */
-        tokio::time::sleep(tokio::time::Duration::from_secs(6)).await;
-    }
+        for page in pages {
+            let treated = || treat(&client, &parsoid, &site, &page, false);
+            policy::page(FLAGS.task, &page, treated).await?;
+        }
 
-    Ok(())
+        Ok(())
+    })
+    .await
 }
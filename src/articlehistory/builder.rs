@@ -6,6 +6,13 @@ pub trait AddToParams {
     fn add_to_params(self, i: NonZeroUsize, params: &mut ParamBuilder<'_>);
 }
 
+/// `s` without the substitutions that keep the saved template formatted, for
+/// showing parameters to people.
+pub fn without_substs(s: &str) -> String {
+    s.replace("{{subst:User:0xDeadbeef/newline}}", "")
+        .replace("{{subst:null}}", "")
+}
+
 pub struct ParamBuilder<'a> {
     params: &'a mut IndexMap<String, String>,
 }
@@ -5,7 +5,8 @@ use tracing::{debug, trace};
 use wiki::Bot;
 
 use crate::articlehistory::ArticleHistory;
-use crate::Result;
+use crate::siteinfo::SiteInfo;
+use crate::{template_name, Result};
 
 mod articlehistory;
 mod dyk;
@@ -21,6 +22,7 @@ pub use articlehistory::ArticleHistoryExtractor;
 pub struct ExtractContext<'cx> {
     pub client: &'cx Bot,
     // pub parsoid: &'cx parsoid::Client,
+    pub site: &'cx SiteInfo,
     pub title: &'cx str,
     pub allow_interactive: bool,
 }
@@ -34,12 +36,6 @@ pub fn simple_extract<T: DeserializeOwned>(t: &Template) -> Result<T> {
     Ok(serde_json::from_value(Value::Object(x))?)
 }
 
-pub fn template_name(t: &Template) -> String {
-    t.name()
-        .trim_start_matches("Template:")
-        .to_ascii_lowercase()
-}
-
 pub fn super_extract<T: Extractor + ?Sized>(t: &Template) -> Result<T::Value> {
     Ok(serde_json::from_value(simple_extract(t)?)?)
 }
@@ -6,6 +6,7 @@ use serde::Deserialize;
 // use serde_json::Value;
 use super::{ExtractContext, Extractor};
 use crate::articlehistory::{Action, ActionKind, ArticleHistory, PreserveDate};
+use crate::siteinfo::PROJECT;
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -38,20 +39,23 @@ impl Extractor for OldPrExtractor {
         value: OldPeerReview,
         into: &mut ArticleHistory,
     ) -> crate::Result<()> {
-        let title = cx.title.strip_prefix("Talk:").unwrap();
+        let subject = cx.site.subject_page(cx.title);
         let link = if let Some(link) = value.archivelink {
             link
         } else {
             format!(
-                "Wikipedia:Peer review/{}/archive{}",
-                value.reviewedname.as_deref().unwrap_or(title),
+                "{}Peer review/{}/archive{}",
+                cx.site.prefix(PROJECT),
+                value.reviewedname.as_deref().unwrap_or(&subject),
                 value.archive.unwrap_or_else(|| "1".into())
             )
         };
         let normalized_link = link.replace(' ', "_");
         let title = urlencoding::encode(&normalized_link);
-        let url =
-            format!("https://en.wikipedia.org/w/rest.php/v1/page/{title}/history/counts/edits");
+        let url = format!(
+            "{}/v1/page/{title}/history/counts/edits",
+            cx.site.rest_url()
+        );
         let res = cx
             .client
             .client
@@ -64,7 +68,8 @@ impl Extractor for OldPrExtractor {
         let result = if res.count < 7 {
             if cx.allow_interactive {
                 println!(
-                    "is this peer review reviewed? (https://en.wikipedia.org/wiki/{title}) [y/n/q]"
+                    "is this peer review reviewed? ({}) [y/n/q]",
+                    cx.site.page_url(&link)
                 );
                 match stdin()
                     .lines()
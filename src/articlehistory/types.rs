@@ -32,7 +32,8 @@ impl<'de> Deserialize<'de> for PreserveDate {
     where
         D: serde::Deserializer<'de>,
     {
-        Self::try_from_string(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+        Self::try_from_string(String::deserialize(deserializer)?)
+            .map_err(|e| serde::de::Error::custom(format!("bad date: {e}")))
     }
 }
 
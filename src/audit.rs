@@ -0,0 +1,95 @@
+//! A record of every edit in the [`state`] store, so that a task's edits can
+//! be reverted en masse and "why did the bot change X" has a precise answer.
+//!
+//! Records are written by [`edit::save`](crate::edit::save) and
+//! [`Client::edit_section`](crate::client::Client::edit_section). The changes
+//! are only known when the task passed the wikitext it replaced; otherwise
+//! the revids still identify the diff on the wiki.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::TextDiff;
+use tracing::warn;
+
+use crate::{state, throttle, Result};
+
+const EDITS: &str = "edits.jsonl";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EditRecord {
+    pub at: DateTime<Utc>,
+    /// The host of the wiki, e.g. `en.wikipedia.org`. Records from before it
+    /// was kept have none.
+    #[serde(default)]
+    pub wiki: Option<String>,
+    pub task: String,
+    pub title: String,
+    pub pageid: Option<u64>,
+    /// The revision the edit was made on, if there was one.
+    pub base_revid: Option<u64>,
+    /// The revision the edit saved, if it changed anything.
+    pub revid: Option<u64>,
+    pub summary: String,
+    pub changes: Option<Vec<Hunk>>,
+}
+
+/// A run of changed lines.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// The first changed line in the old text, from 1.
+    pub line: usize,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+/// The hunks that turn `old` into `new`.
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.grouped_ops(0)
+        .into_iter()
+        .map(|group| {
+            let mut hunk = Hunk {
+                line: group.first().map_or(0, |op| op.old_range().start) + 1,
+                removed: Vec::new(),
+                added: Vec::new(),
+            };
+            for op in group {
+                let lines = |s: &[&str]| s.iter().map(|l| l.trim_end_matches('\n').to_owned());
+                hunk.removed
+                    .extend(lines(&diff.old_slices()[op.old_range()]));
+                hunk.added.extend(lines(&diff.new_slices()[op.new_range()]));
+            }
+            hunk
+        })
+        .collect()
+}
+
+/// Records an edit by `task` from the API's response to it.
+pub fn record(
+    task: &str,
+    res: &Value,
+    baserevid: Option<u32>,
+    summary: &str,
+    changes: Option<Vec<Hunk>>,
+) {
+    let edit = &res["edit"];
+    let record = EditRecord {
+        at: Utc::now(),
+        wiki: throttle::wiki(),
+        task: task.to_owned(),
+        title: edit["title"].as_str().unwrap_or_default().to_owned(),
+        pageid: edit["pageid"].as_u64(),
+        base_revid: edit["oldrevid"].as_u64().or(baserevid.map(u64::from)),
+        revid: edit["newrevid"].as_u64(),
+        summary: summary.to_owned(),
+        changes,
+    };
+    if let Err(e) = state::append(EDITS, &record) {
+        warn!("failed to record the edit to [[{}]]: {e}", record.title);
+    }
+}
+
+pub fn history() -> Result<Vec<EditRecord>> {
+    state::read_all(EDITS)
+}
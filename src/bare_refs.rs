@@ -0,0 +1,183 @@
+//! Converts bare URL references into `{{cite web}}`.
+//!
+//! The title is taken from the page's `<title>`. References whose target
+//! doesn't return a usable title are left alone.
+
+use std::sync::LazyLock;
+
+use chrono::Utc;
+use fancy_regex::Regex;
+use futures_util::StreamExt;
+use kuchiki::traits::TendrilSink;
+use tracing::{debug, info, warn};
+use url::Url;
+use wiki::req::PageSpec;
+
+use crate::accounts::client_for;
+use crate::client::Client;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::{
+    check_nobots_wikitext, http, policy, reverts, run_lock, runs, search_with_rev_ids, selection,
+    skip_list, Page, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("bare_refs", EditFlags::BOT);
+
+const SEARCH: &str = r#"insource:/\<ref[^>]*\>\s*https?:\/\/[^ <\]]+\s*\<\/ref\>/"#;
+
+pub static RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(<ref(?:\s+[^>/]*)?>)\s*(https?://[^\s<>\[\]{}|"]+)\s*(</ref>)"#).unwrap()
+});
+
+/// Titles that sites serve for errors, paywalls or bot checks.
+const BAD_TITLES: &[&str] = &[
+    "404",
+    "not found",
+    "page not found",
+    "access denied",
+    "forbidden",
+    "error",
+    "just a moment",
+    "attention required",
+    "log in",
+    "sign in",
+    "subscribe to read",
+    "home",
+    "untitled",
+];
+
+fn clean_title(title: &str) -> Option<String> {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let lower = title.to_lowercase();
+    if title.chars().count() < 4 || BAD_TITLES.iter().any(|b| lower.starts_with(b)) {
+        return None;
+    }
+    Some(
+        title
+            .replace('|', "{{!}}")
+            .replace('[', "&#91;")
+            .replace(']', "&#93;"),
+    )
+}
+
+async fn fetch_title(client: &reqwest::Client, url: &str) -> Result<Option<String>> {
+    let resp = client.get(url).send().await?.error_for_status()?;
+    let is_html = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    if !is_html {
+        return Ok(None);
+    }
+    let html = kuchiki::parse_html().one(resp.text().await?);
+    Ok(html
+        .select_first("title")
+        .ok()
+        .and_then(|t| clean_title(&t.text_contents())))
+}
+
+/// Builds the citation for `url`, if we can find a title for it.
+pub async fn cite(client: &reqwest::Client, url: &str) -> Result<Option<String>> {
+    let parsed = Url::parse(url)?;
+    let Some(title) = fetch_title(client, url).await? else {
+        return Ok(None);
+    };
+    let website = parsed
+        .host_str()
+        .map(|h| h.trim_start_matches("www.").to_owned());
+    let mut cite = format!("{{{{cite web |url={url} |title={title}");
+    if let Some(website) = website {
+        cite.push_str(&format!(" |website={website}"));
+    }
+    cite.push_str(&format!(
+        " |access-date={}}}}}",
+        Utc::now().format("%Y-%m-%d")
+    ));
+    Ok(Some(cite))
+}
+
+async fn treat(client: &reqwest::Client, wiki_client: &Client, page: &Page) -> Result<()> {
+    let rev = page.revisions.last().unwrap();
+    // saved by page ID, which neither the skip list nor reverts can be checked against
+    if skip_list::skips(FLAGS.task, &page.title) {
+        runs::skipped("skip list");
+        return Ok(());
+    }
+    if reverts::reverted(&page.title) {
+        runs::skipped("reverted");
+        return Ok(());
+    }
+    let text = wiki_client.fetch_content(&page.title).await?;
+    if check_nobots_wikitext(&text) {
+        return Ok(());
+    }
+
+    let mut newtext = text.clone();
+    let mut filled = 0;
+    let captures: Vec<_> = RE.captures_iter(&text).collect::<Result<_, _>>()?;
+    for c in captures.into_iter().rev() {
+        let url = &c[2];
+        match cite(client, url).await {
+            Ok(Some(cite)) => {
+                let whole = c.get(0).unwrap();
+                newtext.replace_range(whole.range(), &format!("{}{cite}{}", &c[1], &c[3]));
+                filled += 1;
+            }
+            Ok(None) => debug!("no usable title for {url}"),
+            Err(e) => debug!("failed to fetch {url}: {e}"),
+        }
+    }
+
+    if filled == 0 {
+        return Ok(());
+    }
+
+    let s = if filled == 1 { "" } else { "s" };
+    info!("filling {filled} reference{s} on [[{}]]", page.title);
+    let (page_id, rev_id) = (page.pageid, rev.revid);
+    wiki_client
+        .call(|bot| {
+            let old = text.as_str();
+            let text = newtext.clone();
+            async move {
+                save(
+                    &bot,
+                    PageSpec::PageId(page_id),
+                    Some(old),
+                    text,
+                    format!("Filling {filled} bare URL reference{s}"),
+                    Some(rev_id),
+                    &FLAGS,
+                )
+                .await?;
+                Ok(())
+            }
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn main() -> Result<()> {
+    let wiki_client = client_for(ENWIKI_API, &FLAGS).await?;
+
+    let bot = wiki_client.bot().await;
+    run_lock::locked(&bot, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&bot, ENWIKI_API).await?;
+        let client = http()?.external.clone();
+
+        let search = search_with_rev_ids(&bot, ENWIKI_API, SEARCH, "0", 20);
+        let mut stream = scope.pages(&bot, ENWIKI_API, search);
+
+        while let Some(page) = stream.next().await {
+            let page = page?;
+            policy::page(FLAGS.task, &page.title, || {
+                treat(&client, &wiki_client, &page)
+            })
+            .await?;
+        }
+
+        Ok(())
+    })
+    .await
+}
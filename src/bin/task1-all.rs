@@ -0,0 +1,5 @@
+use deadbeefbot::remove_twitter_trackers::{main_all, sites};
+
+fn main() -> color_eyre::Result<()> {
+    deadbeefbot::setup(|| async { main_all(&sites()?).await })
+}
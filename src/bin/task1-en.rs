@@ -1,5 +1,5 @@
-use deadbeefbot::remove_twitter_trackers::ENWIKI;
+use deadbeefbot::remove_twitter_trackers::site;
 
 fn main() -> color_eyre::Result<()> {
-    deadbeefbot::setup(|| deadbeefbot::remove_twitter_trackers::main(&ENWIKI))
+    deadbeefbot::setup(|| async { deadbeefbot::remove_twitter_trackers::main(&site("en")?).await })
 }
@@ -1,5 +1,5 @@
-use deadbeefbot::remove_twitter_trackers::ZHWIKI;
+use deadbeefbot::remove_twitter_trackers::site;
 
 fn main() -> color_eyre::Result<()> {
-    deadbeefbot::setup(|| deadbeefbot::remove_twitter_trackers::main(&ZHWIKI))
+    deadbeefbot::setup(|| async { deadbeefbot::remove_twitter_trackers::main(&site("zh")?).await })
 }
@@ -0,0 +1,3 @@
+fn main() -> color_eyre::Result<()> {
+    deadbeefbot::setup(deadbeefbot::bare_refs::main)
+}
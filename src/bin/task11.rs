@@ -0,0 +1,9 @@
+use color_eyre::eyre::ContextCompat;
+
+fn main() -> color_eyre::Result<()> {
+    let search = deadbeefbot::positional_args()
+        .into_iter()
+        .next()
+        .context("usage: task11 <search query>")?;
+    deadbeefbot::setup(|| async move { deadbeefbot::typos::main(&search).await })
+}
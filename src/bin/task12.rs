@@ -0,0 +1,9 @@
+use color_eyre::eyre::ContextCompat;
+
+fn main() -> color_eyre::Result<()> {
+    let category = deadbeefbot::positional_args()
+        .into_iter()
+        .next()
+        .context("usage: task12 <category>")?;
+    deadbeefbot::setup(|| async move { deadbeefbot::sitelinks::main(&category).await })
+}
@@ -0,0 +1,9 @@
+use color_eyre::eyre::ContextCompat;
+
+fn main() -> color_eyre::Result<()> {
+    let allowlist = deadbeefbot::positional_args()
+        .into_iter()
+        .next()
+        .context("usage: task16 <allowlist.txt>")?;
+    deadbeefbot::setup(|| async move { deadbeefbot::https_upgrade::main(&allowlist).await })
+}
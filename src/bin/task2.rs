@@ -1,6 +1,17 @@
+use color_eyre::eyre::ContextCompat;
+
 fn main() -> color_eyre::Result<()> {
+    let args: Vec<_> = std::env::args().collect();
+    let seed = match args.iter().position(|arg| arg == "--seed") {
+        Some(at) => Some(
+            args.get(at + 1)
+                .and_then(|seed| seed.parse().ok())
+                .context("usage: task2 [--seed <number>]")?,
+        ),
+        None => None,
+    };
     // existing AH, can fold in other info.
-    deadbeefbot::setup(|| {
-        deadbeefbot::articlehistory::main("https://petscan.wmflabs.org/?psid=26656482&format=plain")
+    deadbeefbot::setup_with(&["--seed"], || {
+        deadbeefbot::articlehistory::main("https://petscan.wmflabs.org/?psid=26656482", seed)
     })
 }
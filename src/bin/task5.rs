@@ -0,0 +1,4 @@
+fn main() -> color_eyre::Result<()> {
+    let tag = std::env::args().any(|arg| arg == "--tag");
+    deadbeefbot::setup_with(&["--tag"], || deadbeefbot::dab_redirects::main(tag))
+}
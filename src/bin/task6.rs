@@ -0,0 +1,6 @@
+fn main() -> color_eyre::Result<()> {
+    let supervised = std::env::args().any(|arg| arg == "--supervised");
+    deadbeefbot::setup_with(&["--supervised"], || {
+        deadbeefbot::short_description::main(supervised)
+    })
+}
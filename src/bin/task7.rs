@@ -0,0 +1,9 @@
+use color_eyre::eyre::ContextCompat;
+
+fn main() -> color_eyre::Result<()> {
+    let mapping = deadbeefbot::positional_args()
+        .into_iter()
+        .next()
+        .context("usage: task7 <mapping.json>")?;
+    deadbeefbot::setup(|| async move { deadbeefbot::param_migration::main(&mapping).await })
+}
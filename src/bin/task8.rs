@@ -0,0 +1,3 @@
+fn main() -> color_eyre::Result<()> {
+    deadbeefbot::setup(deadbeefbot::category_redirects::main)
+}
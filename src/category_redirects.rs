@@ -0,0 +1,195 @@
+//! Moves pages out of soft-redirected categories into their targets.
+//!
+//! Categories are only emptied once their `{{Category redirect}}` has been in
+//! place for [`GRACE_DAYS`]. Pages that are categorized through a template
+//! can't be fixed here and are left alone.
+
+use chrono::{Duration, Utc};
+use color_eyre::eyre::{bail, ContextCompat};
+use fancy_regex::Regex;
+use parsoid::WikinodeIterator;
+use tracing::{debug, info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::{
+    check_nobots_wikitext, enwiki_parsoid, fetch_content, last_edited, policy, query_raw, run_lock,
+    runs, selection, title_pattern, Parsoid, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("category_redirects", EditFlags::BOT);
+
+const GRACE_DAYS: i64 = 7;
+
+const REDIRECTED: &str = "Category:Wikipedia soft redirected categories";
+
+/// https://en.wikipedia.org/wiki/Special:WhatLinksHere?target=Template%3ACategory+redirect&namespace=&hidetrans=1&hidelinks=1
+const ALIAS: &[&str] = &[
+    "category redirect",
+    "categoryredirect",
+    "catredirect",
+    "cat redirect",
+];
+
+fn members(batches: &[serde_json::Value], list: &str) -> Vec<String> {
+    batches
+        .iter()
+        .flat_map(|b| b[list].as_array().into_iter().flatten())
+        .filter_map(|m| Some(m["title"].as_str()?.to_owned()))
+        .collect()
+}
+
+/// The target of a redirected category, with the `Category:` prefix.
+async fn target(parsoid: &Parsoid, category: &str) -> Result<String> {
+    let code = parsoid.get(category).await?.into_mutable();
+    let t = code
+        .filter_templates()?
+        .into_iter()
+        .find(|t| {
+            let name = t
+                .name()
+                .trim_start_matches("Template:")
+                .to_ascii_lowercase();
+            ALIAS.contains(&&*name)
+        })
+        .context("no category redirect template")?;
+    let target = t.param("1").context("category redirect has no target")?;
+    let target = target.trim().trim_start_matches(':');
+    Ok(if target.starts_with("Category:") {
+        target.to_owned()
+    } else {
+        format!("Category:{target}")
+    })
+}
+
+fn category_regex(category: &str) -> Result<Regex> {
+    let name = title_pattern(category.trim_start_matches("Category:"));
+    Ok(Regex::new(&format!(
+        r"\[\[\s*[Cc]ategory\s*:\s*{name}\s*(\|[^\]]*)?\]\]"
+    ))?)
+}
+
+/// Returns whether the page was changed.
+pub async fn treat(
+    client: &wiki::Bot,
+    title: &str,
+    from: &str,
+    to: &str,
+    summary: &str,
+) -> Result<bool> {
+    let text = fetch_content(client, ENWIKI_API, title).await?;
+    if check_nobots_wikitext(&text) {
+        return Ok(false);
+    }
+    let old = category_regex(from)?;
+    let already = category_regex(to)?.is_match(&text)?;
+
+    let mut newtext = text.clone();
+    let matches: Vec<_> = old.captures_iter(&text).collect::<Result<_, _>>()?;
+    if matches.is_empty() {
+        debug!("[[{title}]] is categorized through a template");
+        return Ok(false);
+    }
+    for (i, c) in matches.iter().enumerate().rev() {
+        let m = c.get(0).unwrap();
+        // keep the first occurrence's sort key, drop any duplicates
+        let replacement = if already || i > 0 {
+            String::new()
+        } else {
+            let sort = c.get(1).map_or("", |s| s.as_str());
+            format!("[[{to}{sort}]]")
+        };
+        newtext.replace_range(m.range(), &replacement);
+    }
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        Some(&text),
+        newtext,
+        summary,
+        None,
+        &FLAGS,
+    )
+    .await?;
+    Ok(true)
+}
+
+async fn empty_category(client: &wiki::Bot, from: &str, to: &str) -> Result<()> {
+    let batches = query_raw(
+        client,
+        ENWIKI_API,
+        &[
+            ("list", "categorymembers"),
+            ("cmtitle", from),
+            ("cmlimit", "max"),
+        ],
+    )
+    .await?;
+    let pages = members(&batches, "categorymembers");
+    if pages.is_empty() {
+        return Ok(());
+    }
+    info!("moving {} pages from [[{from}]] to [[{to}]]", pages.len());
+
+    let total = pages.len();
+    let mut moved = 0;
+    for title in pages {
+        let summary = format!(
+            "Moving from [[:{from}]] to [[:{to}]] per [[WP:CATRED|category redirect]] \
+            ({} of {total})",
+            moved + 1
+        );
+        let treated = || treat(client, &title, from, to, &summary);
+        if policy::page(FLAGS.task, &title, treated).await? == Some(true) {
+            moved += 1;
+        }
+    }
+    info!("moved {moved} of {total} pages out of [[{from}]]");
+    Ok(())
+}
+
+pub async fn main() -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+
+        let batches = query_raw(
+            &client,
+            ENWIKI_API,
+            &[
+                ("list", "categorymembers"),
+                ("cmtitle", REDIRECTED),
+                ("cmnamespace", "14"),
+                ("cmlimit", "max"),
+            ],
+        )
+        .await?;
+
+        // only redirected categories can be emptied
+        for from in scope.filter(members(&batches, "categorymembers")) {
+            let res = async {
+                let edited = last_edited(&client, ENWIKI_API, &from).await?;
+                if Utc::now() - edited < Duration::days(GRACE_DAYS) {
+                    debug!("[[{from}]] is still within the grace period");
+                    return Ok(());
+                }
+                let to = target(&parsoid, &from).await?;
+                if to == from {
+                    bail!("redirects to itself");
+                }
+                empty_category(&client, &from, &to).await
+            }
+            .await;
+            if let Err(e) = res {
+                runs::page_failed();
+                warn!("skipping [[{from}]]: {e}");
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,305 @@
+//! Surveys how a talk page template is used, to triage unusual parameters
+//! before extending an extractor, and reports instances of
+//! `{{article history}}` the articlehistory task couldn't read.
+//!
+//! Run as `deadbeefbot check-template`. Pages are checked a few at a time,
+//! and an interrupted run can be picked up with `deadbeefbot resume`.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::{env, fs};
+
+use color_eyre::eyre::{bail, Context};
+use dashmap::DashMap;
+use futures_util::{stream, StreamExt};
+use parsoid::WikinodeIterator;
+use serde_json::{json, Value};
+use tracing::warn;
+use wiki::req::PageSpec;
+
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::{
+    articlehistory, checkpoint, enwiki_bot, enwiki_parsoid, runs, search_titles, selection, state,
+    template_name, timing, Parsoid, Result, ENWIKI_API,
+};
+
+/// taken from [here](https://en.wikipedia.org/wiki/Special:WhatLinksHere?target=Template%3AArticle+history&namespace=&hidetrans=1&hidelinks=1).
+///
+/// This is case insensitive. Let's hope that people don't use the other capitalizations for a different thing on article talk pages.
+const AH: &[&str] = &[
+    "article history",
+    "article milestones",
+    "articlemilestones",
+    "articlehistory",
+];
+
+/// Where the parameter statistics go unless `--out` is given. A path ending
+/// in `.json` gets JSON, anything else CSV.
+const OUT: &str = "params.csv";
+
+/// How many pages using each parameter are kept as examples.
+const EXAMPLES: usize = 5;
+
+/// How many pages are checked at once.
+const CONCURRENCY: usize = 8;
+
+const FLAGS: TaskFlags = TaskFlags::new("check", EditFlags::BOT);
+
+const REPORT: &str = "User:DeadbeefBot/Broken article history";
+
+/// How often a parameter is used, and some of the pages using it.
+#[derive(Default)]
+struct Usage {
+    count: u64,
+    examples: Vec<String>,
+}
+
+/// `name` with numbers in it replaced by `N`, so that `action12date` and
+/// `action3date` count as the same parameter.
+fn pattern(name: &str) -> String {
+    let mut pattern = String::new();
+    for c in name.trim().chars() {
+        if !c.is_ascii_digit() {
+            pattern.push(c);
+        } else if !pattern.ends_with('N') {
+            pattern.push('N');
+        }
+    }
+    pattern
+}
+
+/// The kind of problem behind `error`, from reading an `{{article history}}`.
+fn classify(error: &str) -> &'static str {
+    if error.contains("bad date") {
+        "bad date"
+    } else if error.contains("unknown field") {
+        "unknown parameter"
+    } else if error.contains("duplicate") {
+        "duplicate index"
+    } else if error.contains("number") {
+        "bad index"
+    } else if error.contains("status") {
+        "bad status"
+    } else {
+        "other"
+    }
+}
+
+fn render_report(broken: &[(String, String)]) -> String {
+    let mut s = format!(
+        "Instances of {{{{article history}}}} that couldn't be read, as of ~~~~~. \
+        {} entries.\n\n{{| class=\"wikitable sortable\"\n! Page !! Problem !! Error\n",
+        broken.len()
+    );
+    for (title, error) in broken {
+        let _ = write!(
+            s,
+            "|-\n| [[{title}]] || {} || <nowiki>{error}</nowiki>\n",
+            classify(error)
+        );
+    }
+    s.push_str("|}\n");
+    s
+}
+
+/// What was found on a page: the parameters of the templates checked, and
+/// why instances of `{{article history}}` couldn't be read.
+async fn check_page(parsoid: &Parsoid, title: &str, aliases: &[String]) -> Result<Value> {
+    let wikicode = parsoid.get(title).await?;
+    let (mut params, mut errors) = (Vec::new(), Vec::new());
+    for template in wikicode.into_mutable().filter_templates()? {
+        let name = template_name(&template);
+        if !aliases.contains(&name) {
+            continue;
+        }
+        params.extend(template.params().into_keys());
+        if !AH.contains(&&*name) {
+            continue;
+        }
+        if let Err(e) = articlehistory::validate(&template) {
+            errors.push(e.to_string().replace('\n', " "));
+        }
+    }
+    Ok(json!({"title": title, "params": params, "errors": errors}))
+}
+
+/// Adds what [`check_page`] found on a page to the totals.
+fn add(record: &Value, usage: &DashMap<String, Usage>, broken: &Mutex<Vec<(String, String)>>) {
+    let title = record["title"].as_str().unwrap_or_default();
+    let strings = |key| {
+        record[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+    };
+    for name in strings("params") {
+        let mut usage = usage.entry(pattern(name)).or_default();
+        usage.count += 1;
+        if usage.examples.len() < EXAMPLES && !usage.examples.iter().any(|t| t == title) {
+            usage.examples.push(title.to_owned());
+        }
+    }
+    for error in strings("errors") {
+        broken
+            .lock()
+            .unwrap()
+            .push((title.to_owned(), error.to_owned()));
+    }
+}
+
+/// Checks `titles`, a few at a time, saving what was found on each to `file`
+/// as it goes.
+async fn check_all(
+    parsoid: &Parsoid,
+    titles: Vec<String>,
+    aliases: &[String],
+    file: &str,
+    usage: &DashMap<String, Usage>,
+    broken: &Mutex<Vec<(String, String)>>,
+) {
+    stream::iter(titles)
+        .for_each_concurrent(CONCURRENCY, |title| async move {
+            let check = async {
+                let record = check_page(parsoid, &title, aliases).await?;
+                state::append(file, &record)?;
+                add(&record, usage, broken);
+                Ok::<_, color_eyre::Report>(())
+            };
+            if let Err(e) = timing::page(&title, check).await {
+                runs::page_failed();
+                warn!("failed to check [[{title}]]: {e}");
+            }
+        })
+        .await;
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// The statistics as CSV or JSON, most used parameters first.
+fn render(usage: Vec<(String, Usage)>, json: bool) -> Result<String> {
+    if json {
+        let params: Vec<_> = usage
+            .into_iter()
+            .map(|(name, u)| json!({"param": name, "count": u.count, "examples": u.examples}))
+            .collect();
+        return Ok(serde_json::to_string_pretty(&params)?);
+    }
+    let mut csv = String::from("param,count,examples\n");
+    for (name, u) in usage {
+        let examples = csv_field(&u.examples.join("; "));
+        writeln!(csv, "{},{},{examples}", csv_field(&name), u.count)?;
+    }
+    Ok(csv)
+}
+
+/// What to check, from the command line.
+struct Args {
+    /// Where the parameter statistics go.
+    out: String,
+    /// Names of the template checked, lowercased as by [`template_name`].
+    aliases: Vec<String>,
+    search: String,
+}
+
+/// The flags [`main`] takes, which all take a value.
+pub const FLAGS: &[&str] = &["--out", "--template", "--search"];
+
+/// Parses `--out <path>`, `--template <name>`, which can be repeated for its
+/// aliases, and `--search <query>`, which defaults to the pages using the
+/// first template given.
+fn parse_args() -> Result<Args> {
+    let (mut out, mut templates, mut search) = (OUT.to_owned(), Vec::new(), None);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        if !FLAGS.contains(&flag) {
+            continue;
+        }
+        let Some(value) = args.next() else {
+            bail!("{flag} needs a value");
+        };
+        match flag {
+            "--out" => out = value,
+            "--template" => templates.push(value),
+            _ => search = Some(value),
+        }
+    }
+    let aliases = if templates.is_empty() {
+        AH.iter().map(|a| a.to_string()).collect()
+    } else {
+        let name = |t: &String| t.trim_start_matches("Template:").replace('_', " ");
+        templates.iter().map(|t| name(t).to_lowercase()).collect()
+    };
+    let search = search.unwrap_or_else(|| match templates.first() {
+        Some(t) => format!("hastemplate:\"{}\"", t.trim_start_matches("Template:")),
+        None => "hastemplate:\"Article history\"".to_owned(),
+    });
+    Ok(Args {
+        out,
+        aliases,
+        search,
+    })
+}
+
+pub async fn main() -> Result<()> {
+    let Args {
+        out,
+        aliases,
+        search,
+    } = parse_args()?;
+    let checks_ah = aliases.iter().any(|a| AH.contains(&a.as_str()));
+    let client = enwiki_bot().await?;
+    let parsoid = enwiki_parsoid()?;
+
+    // pages already checked by a run that is resumed are in its file, and
+    // left out of the search below
+    let Some(id) = checkpoint::current() else {
+        bail!("check only runs as a saved run");
+    };
+    let file = format!("check-{id}.jsonl");
+    let map = DashMap::<String, Usage>::new();
+    let broken = Mutex::new(Vec::new());
+    for record in state::read_all::<Value>(&file)? {
+        add(&record, &map, &broken);
+    }
+    let scope = selection::get().scope(&client, ENWIKI_API).await?;
+
+    let mut titles = search_titles(&client, ENWIKI_API, &search, "1")
+        .await
+        .wrap_err("searching")?;
+    titles.retain(|title| scope.todo(title));
+    println!("{} page(s) to check", titles.len());
+    check_all(&parsoid, titles, &aliases, &file, &map, &broken).await;
+
+    let mut usage: Vec<_> = map.into_iter().collect();
+    usage.sort_by(|(a, x), (b, y)| y.count.cmp(&x.count).then_with(|| a.cmp(b)));
+    let n = usage.len();
+    fs::write(&out, render(usage, out.ends_with(".json"))?)?;
+    println!("{n} parameter(s), written to {out}");
+
+    if !checks_ah {
+        return Ok(());
+    }
+    let mut broken = broken.into_inner().unwrap();
+    broken.sort();
+    println!("{} broken instance(s), see [[{REPORT}]]", broken.len());
+    save(
+        &client,
+        PageSpec::Title(REPORT.to_owned()),
+        None,
+        render_report(&broken),
+        format!("Updating report ({} entries)", broken.len()),
+        None,
+        &FLAGS,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Progress of runs started from the command line, so that an interrupted run
+//! can be picked up with `deadbeefbot resume <run-id>`.
+//!
+//! A run saves its command line and the configuration files it read, the
+//! ones in [`CONFIG`] and any other read with [`read_config`] before its first
+//! page, like `--skip-file`, then every page it has treated. Resuming runs the
+//! same command again with that configuration, leaves out the pages already
+//! treated through the [`selection`](crate::selection), and records the rest
+//! of the run under the same ID in the [`runs`](crate::runs) history. Runs
+//! started by the daemon aren't saved.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::{env, fs};
+
+use color_eyre::eyre::{bail, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{accounts, config, edit, policy, state, throttle, trial, Result};
+
+/// Set for a process that resumes a run, to its ID.
+const RESUME: &str = "RESUME_RUN";
+
+/// The configuration files saved with every run, whether or not it read
+/// them before its first page.
+const CONFIG: &[&str] = &[
+    throttle::RATE_PROFILES,
+    edit::EDIT_FLAGS,
+    policy::ERROR_POLICY,
+    trial::TRIALS,
+    config::SITES,
+    accounts::ACCOUNTS,
+    accounts::WIKI_TOKENS,
+];
+
+/// What each configuration file had when it was last read.
+static READ: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// What each configuration file had when the run being resumed started.
+static RESTORED: OnceLock<BTreeMap<String, Option<String>>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Record {
+    Started {
+        command: Vec<String>,
+        /// The contents of each configuration file, or `None` if it didn't
+        /// exist.
+        config: BTreeMap<String, Option<String>>,
+    },
+    Done {
+        title: String,
+    },
+}
+
+fn file(id: &str) -> String {
+    format!("checkpoint-{id}.jsonl")
+}
+
+struct Checkpoint {
+    id: String,
+    /// The command line, saved along with the first page, so that runs that
+    /// treat none leave nothing behind, and so that the configuration the run
+    /// read as it started is saved with it.
+    start: RefCell<Option<Vec<String>>>,
+}
+
+tokio::task_local! {
+    static RUN: Option<Checkpoint>;
+}
+
+/// Reads the configuration file `path`, or `None` if it doesn't exist, and
+/// keeps what it had to save with the run. A process resuming a run gets what
+/// the file had when the run started instead.
+pub fn read_config(path: &str) -> Result<Option<String>> {
+    if let Some(text) = RESTORED.get().and_then(|config| config.get(path)) {
+        return Ok(text.clone());
+    }
+    let text = match fs::read_to_string(path) {
+        Ok(s) => Some(s),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("reading {path}")),
+    };
+    READ.lock().unwrap().insert(path.to_owned(), text.clone());
+    Ok(text)
+}
+
+/// Like [`read_config`], for a file that has to exist.
+pub fn read_required(path: &str) -> Result<String> {
+    read_config(path)?.with_context(|| format!("{path} doesn't exist"))
+}
+
+/// The configuration to save with a run: each of [`CONFIG`], and what else it
+/// has read.
+fn snapshot() -> Result<BTreeMap<String, Option<String>>> {
+    for path in CONFIG {
+        if !READ.lock().unwrap().contains_key(*path) {
+            read_config(path)?;
+        }
+    }
+    Ok(READ.lock().unwrap().clone())
+}
+
+/// The ID of the run this process resumes, if it does.
+pub fn resuming() -> Option<String> {
+    env::var(RESUME).ok()
+}
+
+/// The ID of the current run, if it is saved.
+pub fn current() -> Option<String> {
+    RUN.try_with(|run| run.as_ref().map(|c| c.id.clone()))
+        .ok()
+        .flatten()
+}
+
+/// What a run saved.
+pub struct Progress {
+    pub command: Vec<String>,
+    config: BTreeMap<String, Option<String>>,
+    /// Pages treated, in order.
+    pub done: Vec<String>,
+}
+
+impl Progress {
+    pub fn load(id: &str) -> Result<Progress> {
+        let mut records = state::read_all::<Record>(&file(id))?.into_iter();
+        let Some(Record::Started { command, config }) = records.next() else {
+            bail!("no progress saved for run {id}");
+        };
+        let done = records
+            .filter_map(|r| match r {
+                Record::Done { title } => Some(title),
+                Record::Started { .. } => None,
+            })
+            .collect();
+        Ok(Progress {
+            command,
+            config,
+            done,
+        })
+    }
+
+    /// Uses the configuration the run started with, which has to be done
+    /// before anything reads it.
+    pub fn restore_config(&self) {
+        let _ = RESTORED.set(self.config.clone());
+    }
+}
+
+/// Runs `run` as the run `id`, saving its progress. `command` is `None` when
+/// resuming it.
+pub async fn saved<T>(
+    id: String,
+    command: Option<Vec<String>>,
+    run: impl Future<Output = T>,
+) -> Result<T> {
+    let checkpoint = Checkpoint {
+        id,
+        start: RefCell::new(command),
+    };
+    Ok(RUN.scope(Some(checkpoint), run).await)
+}
+
+/// Runs `run` without saving its progress, even inside a saved run.
+pub async fn unsaved<T>(run: impl Future<Output = T>) -> T {
+    RUN.scope(None, run).await
+}
+
+/// Records that `title` was treated, if the current run is saved.
+pub fn done(title: &str) {
+    let _ = RUN.try_with(|run| {
+        let Some(Checkpoint { id, start }) = run else {
+            return;
+        };
+        let done = Record::Done {
+            title: title.to_owned(),
+        };
+        let res = match start.borrow_mut().take() {
+            Some(command) => snapshot()
+                .and_then(|config| state::append(&file(id), &Record::Started { command, config })),
+            None => Ok(()),
+        };
+        if let Err(e) = res.and_then(|()| state::append(&file(id), &done)) {
+            warn!("failed to save progress on [[{title}]]: {e}");
+        }
+    });
+}
+
+/// Runs the command of the run `id` again, as a resumption of it.
+pub fn resume(id: &str) -> Result<()> {
+    let progress = Progress::load(id)?;
+    let [program, args @ ..] = &progress.command[..] else {
+        bail!("run {id} has no command");
+    };
+    info!(
+        "resuming {id}, {} page(s) already done",
+        progress.done.len()
+    );
+    let status = Command::new(program)
+        .args(args)
+        .env(RESUME, id)
+        .status()
+        .with_context(|| format!("running {program}"))?;
+    if !status.success() {
+        bail!("{program} {status}");
+    }
+    Ok(())
+}
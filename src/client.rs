@@ -0,0 +1,372 @@
+//! A wrapper around [`wiki::Bot`] that logs in again when the session is lost.
+//!
+//! Requests made through [`Client::call`] that fail because of an expired or
+//! revoked session are retried once with a fresh session, built from the
+//! current token (so a rotated `token.secret` gets picked up too). Streams
+//! of query batches can't be replayed halfway and are not covered.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use color_eyre::eyre::{bail, ContextCompat};
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tracing::warn;
+use wiki::req::PageSpec;
+
+use crate::edit::{assertions, pre_save, saved, Change, TaskFlags};
+use crate::timing::{self, Phase};
+use crate::{
+    audit, credentials, fetch_content, maxlag, query_raw, ratelimit, site_with_token, Result,
+    CONTENT_BATCH, ENWIKI_API,
+};
+
+/// Error codes and messages meaning that we are no longer logged in.
+const AUTH_ERRORS: &[&str] = &[
+    "assertuserfailed",
+    "assertbotfailed",
+    "badtoken",
+    "notloggedin",
+    "mwoauth-invalid-authorization",
+    "401 Unauthorized",
+];
+
+pub fn is_auth_error(e: &color_eyre::Report) -> bool {
+    e.chain().any(|cause| {
+        let msg = cause.to_string();
+        AUTH_ERRORS.iter().any(|code| msg.contains(code))
+    })
+}
+
+/// A section of a page, numbered the way `section=` editing expects.
+#[derive(Debug)]
+pub struct Section {
+    pub index: u32,
+    pub heading: String,
+    pub level: u32,
+}
+
+/// The wikitext of one section, with the revision it was taken from.
+#[derive(Debug)]
+pub struct SectionContent {
+    pub revid: u32,
+    pub text: String,
+}
+
+tokio::task_local! {
+    /// The account the session of the current [`Client::call`] is logged in
+    /// as, if known.
+    static USER: Option<String>;
+}
+
+/// The account the session of the current [`Client::call`] is logged in as:
+/// `None` outside of one, and `Some(None)` if that isn't known.
+pub fn session_user() -> Option<Option<String>> {
+    USER.try_with(Clone::clone).ok()
+}
+
+pub struct Client {
+    api_url: String,
+    token_file: Option<String>,
+    /// See [`credentials::user_for`].
+    user: Option<String>,
+    bot: RwLock<Arc<wiki::Bot>>,
+}
+
+impl Client {
+    pub async fn new(api_url: &str) -> Result<Client> {
+        Client::with_token_file(api_url, None).await
+    }
+
+    /// A client logged in with the token in `token_file`, if it exists. See
+    /// [`site_with_token`].
+    pub async fn with_token_file(api_url: &str, token_file: Option<&str>) -> Result<Client> {
+        Ok(Client {
+            api_url: api_url.to_owned(),
+            token_file: token_file.map(str::to_owned),
+            user: credentials::user_for(api_url, token_file)?,
+            bot: RwLock::new(Arc::new(site_with_token(api_url, token_file).await?)),
+        })
+    }
+
+    pub async fn enwiki() -> Result<Client> {
+        Client::new(ENWIKI_API).await
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// The current session.
+    pub async fn bot(&self) -> Arc<wiki::Bot> {
+        self.bot.read().await.clone()
+    }
+
+    /// Replaces `stale` with a new session, unless someone already did.
+    async fn relogin(&self, stale: &Arc<wiki::Bot>) -> Result<()> {
+        let mut bot = self.bot.write().await;
+        if Arc::ptr_eq(&bot, stale) {
+            warn!("session lost, logging in again");
+            *bot = Arc::new(site_with_token(&self.api_url, self.token_file.as_deref()).await?);
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with the current session, logging in again and retrying
+    /// once if it fails with an authentication error. Edits made in `f` are
+    /// asserted to be by the account the session is logged in as.
+    pub async fn call<T, F, Fut>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut(Arc<wiki::Bot>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let bot = self.bot().await;
+        match USER.scope(self.user.clone(), f(bot.clone())).await {
+            Err(e) if is_auth_error(&e) => {
+                warn!("request failed: {e}");
+                self.relogin(&bot).await?;
+                USER.scope(self.user.clone(), f(self.bot().await)).await
+            }
+            res => res,
+        }
+    }
+
+    /// The [`assertions`] for an edit by `task` with this client's session.
+    fn assertions(&self, task: &TaskFlags, bot: bool) -> Result<Vec<(&'static str, String)>> {
+        USER.sync_scope(self.user.clone(), || assertions(task, &self.api_url, bot))
+    }
+
+    pub async fn fetch_content(&self, title: &str) -> Result<String> {
+        self.call(|bot| async move { fetch_content(&bot, &self.api_url, title).await })
+            .await
+    }
+
+    pub async fn query_raw(&self, params: &[(&str, &str)]) -> Result<Vec<Value>> {
+        self.call(|bot| async move { query_raw(&bot, &self.api_url, params).await })
+            .await
+    }
+
+    /// Sections of `title`. Sections transcluded from other pages are left
+    /// out, since they can't be edited here.
+    pub async fn sections(&self, title: &str) -> Result<Vec<Section>> {
+        self.call(|bot| async move {
+            let res: Value = maxlag::api(bot.client.get(&self.api_url).query(&[
+                ("action", "parse"),
+                ("page", title),
+                ("prop", "sections"),
+                ("format", "json"),
+                ("formatversion", "2"),
+            ]))
+            .await?;
+            if let Some(e) = res.get("error") {
+                bail!("api error: {e}");
+            }
+            Ok(res["parse"]["sections"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|s| {
+                    Some(Section {
+                        index: s["index"].as_str()?.parse().ok()?,
+                        heading: s["line"].as_str()?.to_owned(),
+                        level: s["level"].as_str()?.parse().ok()?,
+                    })
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// The index of the first section headed `heading`.
+    pub async fn find_section(&self, title: &str, heading: &str) -> Result<Option<u32>> {
+        Ok(self
+            .sections(title)
+            .await?
+            .into_iter()
+            .find(|s| s.heading.trim() == heading.trim())
+            .map(|s| s.index))
+    }
+
+    /// The wikitext of one section of `title`, or `None` if the page doesn't
+    /// exist. Section 0 is the lead.
+    pub async fn fetch_section(&self, title: &str, section: u32) -> Result<Option<SectionContent>> {
+        let section = section.to_string();
+        let batches = self
+            .query_raw(&[
+                ("prop", "revisions"),
+                ("titles", title),
+                ("rvprop", "ids|content"),
+                ("rvslots", "main"),
+                ("rvsection", &section),
+            ])
+            .await?;
+        let page = batches
+            .first()
+            .map(|b| &b["pages"][0])
+            .with_context(|| format!("no response for [[{title}]]"))?;
+        if page.get("missing").is_some() {
+            return Ok(None);
+        }
+        let rev = &page["revisions"][0];
+        Ok(Some(SectionContent {
+            revid: rev["revid"].as_u64().context("no revision id")? as u32,
+            text: rev["slots"]["main"]["content"]
+                .as_str()
+                .context("no content")?
+                .to_owned(),
+        }))
+    }
+
+    /// Replaces one section of an existing page, `current` as fetched, with
+    /// `text`, once the edit is through [`pre_save`].
+    pub async fn edit_section(
+        &self,
+        title: &str,
+        section: u32,
+        current: &SectionContent,
+        text: &str,
+        summary: &str,
+        task: &TaskFlags,
+    ) -> Result<()> {
+        let (old, baserevid) = (current.text.as_str(), current.revid);
+        let flags = task.get()?;
+        let page = PageSpec::Title(title.to_owned());
+        let bot = self.bot().await;
+        let change = Change::Text {
+            old: Some(old),
+            new: text,
+        };
+        let saving = pre_save(&bot, &self.api_url, &page, change, summary.into(), task);
+        let Some(summary) = saving.await? else {
+            return Ok(());
+        };
+        let assertions = self.assertions(task, flags.bot)?;
+        let section = section.to_string();
+        let base = baserevid.to_string();
+        let save = self.call(|bot| {
+            let (section, baserevid, assertions) = (&section, &base, &assertions);
+            let summary = &summary;
+            async move {
+                let mut form = vec![
+                    ("title", title),
+                    ("section", section),
+                    ("text", text),
+                    ("summary", summary),
+                    ("baserevid", baserevid),
+                    ("nocreate", "1"),
+                ];
+                if flags.minor {
+                    form.push(("minor", "1"));
+                }
+                if flags.bot {
+                    form.push(("bot", "1"));
+                }
+                form.extend(assertions.iter().map(|(k, v)| (*k, v.as_str())));
+                post_edit(&bot, &self.api_url, &form).await
+            }
+        });
+        let res = timing::phase(Phase::Save, save).await?;
+        let changes = Some(audit::hunks(old, text));
+        saved(task, &res, Some(baserevid), &summary, changes);
+        Ok(())
+    }
+
+    /// Saves `title` without changes for `task`, once the edit is through
+    /// [`pre_save`], so that everything its templates pull in (categories,
+    /// links) is brought up to date. Does nothing if the page doesn't exist.
+    pub async fn null_edit(&self, title: &str, task: &TaskFlags) -> Result<()> {
+        let page = PageSpec::Title(title.to_owned());
+        let bot = self.bot().await;
+        let saving = pre_save(
+            &bot,
+            &self.api_url,
+            &page,
+            Change::Null,
+            String::new(),
+            task,
+        );
+        if saving.await?.is_none() {
+            return Ok(());
+        }
+        let assertions = self.assertions(task, task.get()?.bot)?;
+        self.call(|bot| {
+            let assertions = &assertions;
+            async move {
+                let mut form = vec![("title", title), ("appendtext", ""), ("nocreate", "1")];
+                form.extend(assertions.iter().map(|(k, v)| (*k, v.as_str())));
+                match post_edit(&bot, &self.api_url, &form).await {
+                    Err(e) if e.to_string().contains("missingtitle") => Ok(()),
+                    res => res.map(drop),
+                }
+            }
+        })
+        .await
+    }
+
+    /// Purges `titles` with `forcelinkupdate`, which has the same effect as
+    /// [`null_edit`](Client::null_edit) without going through the edit rate
+    /// limits. Better suited for refreshing many pages at once.
+    pub async fn purge(&self, titles: &[&str]) -> Result<()> {
+        for chunk in titles.chunks(CONTENT_BATCH) {
+            let titles = chunk.join("|");
+            self.call(|bot| {
+                let titles = &titles;
+                async move {
+                    post(
+                        &bot,
+                        &self.api_url,
+                        &[
+                            ("action", "purge"),
+                            ("format", "json"),
+                            ("formatversion", "2"),
+                            ("forcelinkupdate", "1"),
+                            ("titles", titles),
+                        ],
+                    )
+                    .await
+                }
+            })
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+async fn csrf_token(bot: &wiki::Bot, api_url: &str) -> Result<String> {
+    let tokens = query_raw(bot, api_url, &[("meta", "tokens")]).await?;
+    Ok(tokens
+        .first()
+        .and_then(|b| b["tokens"]["csrftoken"].as_str())
+        .context("no csrf token")?
+        .to_owned())
+}
+
+/// Makes the edit `form` describes, with a fresh token. Fails unless it went
+/// through.
+pub async fn post_edit(bot: &wiki::Bot, api_url: &str, form: &[(&str, &str)]) -> Result<Value> {
+    let token = csrf_token(bot, api_url).await?;
+    let mut form = form.to_vec();
+    form.extend([
+        ("action", "edit"),
+        ("format", "json"),
+        ("formatversion", "2"),
+        ("token", &token),
+    ]);
+    let res = post(bot, api_url, &form).await?;
+    if res["edit"]["result"] != "Success" {
+        bail!("edit failed: {}", res["edit"]);
+    }
+    Ok(res)
+}
+
+/// POSTs `form` to the API, failing on an API error.
+async fn post(bot: &wiki::Bot, api_url: &str, form: &[(&str, &str)]) -> Result<Value> {
+    ratelimit::retry_ratelimited(|| async {
+        let res = maxlag::api(bot.client.post(api_url).form(form)).await?;
+        if let Some(e) = res.get("error") {
+            bail!("api error: {e}");
+        }
+        Ok(res)
+    })
+    .await
+}
@@ -0,0 +1,89 @@
+//! Wikis the bot runs on, configured in [`SITES`] rather than compiled in,
+//! e.g.
+//!
+//! ```json
+//! {
+//!     "de": {
+//!         "name": "German Wikipedia",
+//!         "api_url": "https://de.wikipedia.org/w/api.php",
+//!         "parsoid": "rest",
+//!         "token_file": "./token-dewiki.secret",
+//!         "tasks": {
+//!             "remove_twitter_trackers": {
+//!                 "summary": "Bot: {links} Twitter-Links ohne Tracker{wayback}",
+//!                 "wayback": ", {wayback_links} Archivlinks"
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Sites are keyed by the short names they are picked by on the command line.
+//! `parsoid` is `restbase`, the default, `rest` or `action`, as for
+//! `PARSOID_BACKEND`, and `token_file` a token or
+//! [BotPassword](crate::credentials) to use instead of the default one if it
+//! exists. `tasks` holds the options of each task on the site, as the task
+//! reads them; a task runs on the sites that have options for it. Optional.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{checkpoint, ParsoidBackend, Result};
+
+pub const SITES: &str = "sites.json";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Site {
+    pub name: String,
+    pub api_url: String,
+    #[serde(default = "default_parsoid")]
+    pub parsoid: ParsoidBackend,
+    pub token_file: Option<String>,
+    #[serde(default)]
+    tasks: HashMap<String, Value>,
+}
+
+fn default_parsoid() -> ParsoidBackend {
+    ParsoidBackend::RestBase
+}
+
+impl Site {
+    /// The options of `task` on this site, if it has any.
+    pub fn task<T: DeserializeOwned>(&self, task: &str) -> Result<Option<T>> {
+        let Some(options) = self.tasks.get(task) else {
+            return Ok(None);
+        };
+        let options = T::deserialize(options)
+            .with_context(|| format!("invalid options for {task} on {}", self.name))?;
+        Ok(Some(options))
+    }
+}
+
+static SITE_LIST: LazyLock<Result<BTreeMap<String, Site>, String>> =
+    LazyLock::new(|| match checkpoint::read_config(SITES) {
+        Ok(Some(s)) => serde_json::from_str(&s).map_err(|e| format!("invalid {SITES}: {e}")),
+        Ok(None) => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("{e:#}")),
+    });
+
+/// The configured sites, by key.
+pub fn sites() -> Result<&'static BTreeMap<String, Site>> {
+    SITE_LIST.as_ref().map_err(|e| eyre!("{e}"))
+}
+
+/// Fills in an edit summary template, replacing `{name}` with each count in
+/// `counts`, and `{name_s}` with an `s` unless the count is 1.
+pub fn fill(template: &str, counts: &[(&str, usize)]) -> String {
+    let mut s = template.to_owned();
+    for &(name, n) in counts {
+        s = s.replace(&format!("{{{name}_s}}"), if n == 1 { "" } else { "s" });
+        s = s.replace(&format!("{{{name}}}"), &n.to_string());
+    }
+    s
+}
@@ -0,0 +1,52 @@
+//! Converts a talk page header into one with `{{article history}}`, the way
+//! the articlehistory task would, for converting pages by hand.
+//!
+//! Run as `deadbeefbot conv <title>`. The header is read from stdin until
+//! EOF, so it can be pasted whole or piped in, and the result is printed and
+//! copied to the clipboard.
+//!
+//! With `--params`, only the parameters for appearances on the main page are
+//! given, from `{{ITN talk}}`, `{{DYK talk}}` and `{{On this day}}` as well as
+//! any `{{article history}}` pasted with them, to replace those of an
+//! existing `{{article history}}`.
+
+use std::io::{self, Read};
+
+use copypasta::{ClipboardContext, ClipboardProvider};
+use tracing::warn;
+
+use crate::siteinfo::SiteInfo;
+use crate::{articlehistory, enwiki_bot, enwiki_parsoid, Result, ENWIKI_API};
+
+/// Converts the header of `title` read from stdin, or only its parameters
+/// for appearances on the main page with `params`.
+pub async fn main(title: &str, params: bool) -> Result<()> {
+    // the platform's clipboard, if there is one; headless servers only get
+    // the output printed
+    let mut clipboard = match ClipboardContext::new() {
+        Ok(clipboard) => Some(clipboard),
+        Err(e) => {
+            warn!("no clipboard, only printing the output: {e}");
+            None
+        }
+    };
+    let mut wikitext = String::new();
+    io::stdin().read_to_string(&mut wikitext)?;
+
+    let client = enwiki_bot().await?;
+    let parsoid = enwiki_parsoid()?;
+    let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+    let out = if params {
+        articlehistory::convert_params(&client, &parsoid, &site, title, &wikitext).await?
+    } else {
+        articlehistory::convert(&client, &parsoid, &site, title, &wikitext).await?
+    };
+
+    println!("{out}");
+    if let Some(clipboard) = &mut clipboard {
+        if let Err(e) = clipboard.set_contents(out) {
+            warn!("failed to copy the output: {e}");
+        }
+    }
+    Ok(())
+}
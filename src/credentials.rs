@@ -0,0 +1,170 @@
+//! What the bot logs in with: an OAuth 2 token, or on wikis without OAuth,
+//! like third-party wikis and local test instances, a
+//! [BotPassword](https://www.mediawiki.org/wiki/Manual:Bot_passwords).
+//!
+//! Both are kept in [secret](secrets) files. A file holding a BotPassword has
+//! it as JSON, e.g. `{"username": "DeadbeefBot@tasks", "password": "..."}`,
+//! and any other file holds a token. A BotPassword file can go wherever a
+//! token file does, in [`accounts`] or a [site](crate::config)'s
+//! `token_file`.
+//!
+//! Where nothing else is configured, the default token is used. It is looked
+//! for in [`TOKEN_VAR`], then in the file given with `--token-file <path>`,
+//! then, with the `keyring` feature, in the system keyring under
+//! [`KEYRING_SERVICE`], and last in [`TOKEN_FILE`].
+
+use std::env;
+use std::sync::OnceLock;
+
+use color_eyre::eyre::{bail, Context};
+use serde::Deserialize;
+#[cfg(feature = "keyring")]
+use tracing::debug;
+use wiki::ClientBuilder;
+
+use crate::{accounts, secrets, Result};
+
+/// The default token, for containers that get secrets in their environment.
+pub const TOKEN_VAR: &str = "DEADBEEFBOT_OAUTH_TOKEN";
+
+/// The name [`TOKEN_VAR`] had before, still read.
+const OLD_TOKEN_VAR: &str = "BOT_TOKEN";
+
+/// The service and user the default token is stored as in the keyring.
+pub const KEYRING_SERVICE: (&str, &str) = ("deadbeefbot", "oauth-token");
+
+/// The default token if there is none elsewhere.
+pub const TOKEN_FILE: &str = "./token.secret";
+
+static TOKEN_FILE_ARG: OnceLock<Option<String>> = OnceLock::new();
+
+pub enum Credential {
+    OAuth(String),
+    BotPassword(BotPassword),
+}
+
+/// A login made on `Special:BotPasswords`. `username` is the account's name
+/// and the BotPassword's, joined with an `@`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BotPassword {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credential {
+    /// The credential in `text`, the contents of a secret file.
+    pub fn parse(text: &str) -> Result<Credential> {
+        let text = text.trim();
+        if text.starts_with('{') {
+            let password = serde_json::from_str(text).context("invalid BotPassword")?;
+            return Ok(Credential::BotPassword(password));
+        }
+        Ok(Credential::OAuth(text.to_owned()))
+    }
+
+    /// The credential in the secret file `path`.
+    pub fn read(path: &str) -> Result<Credential> {
+        Credential::parse(&secrets::read(path)?).with_context(|| format!("reading {path}"))
+    }
+
+    /// Has `builder` log in with this credential.
+    pub fn login(&self, builder: ClientBuilder) -> ClientBuilder {
+        match self {
+            Credential::OAuth(token) => builder.oauth(token),
+            Credential::BotPassword(BotPassword { username, password }) => {
+                builder.password(username, password)
+            }
+        }
+    }
+}
+
+/// Reads `--token-file` out of a command line, ignoring the rest. Only the
+/// first call has an effect.
+pub fn from_args(args: &[String]) -> Result<()> {
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--token-file" {
+            let Some(arg) = args.next() else {
+                bail!("--token-file needs a path");
+            };
+            path = Some(arg.clone());
+        }
+    }
+    let _ = TOKEN_FILE_ARG.set(path);
+    Ok(())
+}
+
+/// The default token in the keyring, if it can be reached and has one.
+#[cfg(feature = "keyring")]
+fn from_keyring() -> Result<Option<Credential>> {
+    let (service, user) = KEYRING_SERVICE;
+    let entry = keyring::Entry::new(service, user)?;
+    match entry.get_password() {
+        Ok(text) => Credential::parse(&text).map(Some),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => {
+            // no keyring in containers, where the token is elsewhere
+            debug!("not using the keyring: {e}");
+            Ok(None)
+        }
+    }
+}
+
+fn default() -> Result<Credential> {
+    for var in [TOKEN_VAR, OLD_TOKEN_VAR] {
+        if let Ok(token) = env::var(var) {
+            return Ok(Credential::OAuth(token.trim().to_owned()));
+        }
+    }
+    // given explicitly, so it has to exist
+    if let Some(Some(path)) = TOKEN_FILE_ARG.get() {
+        return Credential::read(path);
+    }
+    #[cfg(feature = "keyring")]
+    if let Some(credential) = from_keyring()? {
+        return Ok(credential);
+    }
+
+    Credential::read(TOKEN_FILE).with_context(|| {
+        format!("no oauth2 token: set {TOKEN_VAR}, pass --token-file or put it in {TOKEN_FILE}")
+    })
+}
+
+/// The credential for the wiki at `url`: the one in `token_file` if there is
+/// one, for running as a different account on some wikis, then the wiki's
+/// own from [`accounts::WIKI_TOKENS`], then the default token.
+pub fn for_wiki(url: &str, token_file: Option<&str>) -> Result<Credential> {
+    Ok(resolve(url, token_file)?.0)
+}
+
+/// The account [`for_wiki`] logs in as, where that is known: a BotPassword
+/// names it, and `BOT_USER` names the default token's. Any other token could
+/// be any account's.
+pub fn user_for(url: &str, token_file: Option<&str>) -> Result<Option<String>> {
+    Ok(match resolve(url, token_file)? {
+        (Credential::BotPassword(password), _) => {
+            password.username.split('@').next().map(str::to_owned)
+        }
+        (Credential::OAuth(_), true) => env::var("BOT_USER").ok(),
+        (Credential::OAuth(_), false) => None,
+    })
+}
+
+/// Like [`for_wiki`], with whether the credential is the default one.
+fn resolve(url: &str, token_file: Option<&str>) -> Result<(Credential, bool)> {
+    if let Some(path) = token_file.filter(|path| secrets::find(path).is_some()) {
+        return Ok((Credential::read(path)?, false));
+    }
+    if let Some(path) = accounts::wiki_token_file(url)? {
+        return Ok((Credential::read(path)?, false));
+    }
+    let credential = default().with_context(|| {
+        format!(
+            "no token for {url}, neither the default nor one in {}",
+            accounts::WIKI_TOKENS
+        )
+    })?;
+    Ok((credential, true))
+}
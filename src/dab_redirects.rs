@@ -0,0 +1,181 @@
+//! Reports article links that reach disambiguation pages through redirects.
+//!
+//! Links through `(disambiguation)` redirects are intentional and excluded.
+//! With tagging enabled, links through the worst offenders also get
+//! `{{Disambiguation needed}}`.
+
+use std::fmt::Write;
+
+use chrono::Utc;
+use fancy_regex::Regex;
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::siteinfo::{SiteInfo, MAIN};
+use crate::source::replica;
+use crate::{
+    check_nobots_wikitext, fetch_content, query_raw, run_lock, runs, selection, title_pattern,
+    Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("dab_redirects", EditFlags::BOT);
+
+const REPORT: &str = "User:DeadbeefBot/Redirects to disambiguation pages";
+
+/// Only links through this many of the top redirects are tagged per run.
+const TAG_TOP: usize = 20;
+
+const SQL: &str = "
+SELECT r.page_title, rd.rd_title, COUNT(*) AS links
+FROM page r
+JOIN redirect rd ON rd.rd_from = r.page_id AND rd.rd_namespace = 0
+JOIN page d ON d.page_namespace = 0 AND d.page_title = rd.rd_title
+JOIN page_props pp ON pp.pp_page = d.page_id AND pp.pp_propname = 'disambiguation'
+JOIN linktarget lt ON lt.lt_namespace = 0 AND lt.lt_title = r.page_title
+JOIN pagelinks pl ON pl.pl_target_id = lt.lt_id AND pl.pl_from_namespace = 0
+WHERE r.page_namespace = 0
+  AND r.page_is_redirect = 1
+  AND r.page_title NOT LIKE '%\\_(disambiguation)'
+GROUP BY r.page_id, rd.rd_title
+ORDER BY links DESC
+LIMIT 1000";
+
+pub struct Entry {
+    pub redirect: String,
+    pub target: String,
+    pub links: u64,
+}
+
+pub async fn entries(site: &SiteInfo) -> Result<Vec<Entry>> {
+    let rows: Vec<(Vec<u8>, Vec<u8>, u64)> = replica::query("enwiki", SQL).await?;
+    Ok(rows
+        .into_iter()
+        .map(|(redirect, target, links)| Entry {
+            redirect: replica::title(site, MAIN, &redirect),
+            target: replica::title(site, MAIN, &target),
+            links,
+        })
+        .collect())
+}
+
+pub fn render(entries: &[Entry]) -> String {
+    let mut s = String::from(
+        "Article links to disambiguation pages via redirects, as of ~~~~~. \
+        Updated by [[User:DeadbeefBot|DeadbeefBot]].\n\n\
+        {| class=\"wikitable sortable\"\n! # !! Redirect !! Target !! Links\n",
+    );
+    for (i, e) in entries.iter().enumerate() {
+        let _ = write!(
+            s,
+            "|-\n| {} || [[Special:WhatLinksHere/{r}|{r}]] || [[{}]] || {}\n",
+            i + 1,
+            e.target,
+            e.links,
+            r = e.redirect,
+        );
+    }
+    s.push_str("|}\n");
+    s
+}
+
+/// Matches a link to `title`, unless it's already tagged.
+fn link_regex(title: &str) -> Result<Regex> {
+    let title = title_pattern(title);
+    Ok(Regex::new(&format!(
+        r"\[\[\s*{title}\s*(?:\|[^\]]*)?\]\](?!\s*\{{\{{\s*(?i:dn|disambiguation needed))"
+    ))?)
+}
+
+async fn tag(client: &wiki::Bot, e: &Entry) -> Result<()> {
+    let batches = query_raw(
+        client,
+        ENWIKI_API,
+        &[
+            ("list", "backlinks"),
+            ("bltitle", &e.redirect),
+            ("blnamespace", "0"),
+            ("bllimit", "max"),
+        ],
+    )
+    .await?;
+    let re = link_regex(&e.redirect)?;
+    let dn = format!(
+        "{{{{Disambiguation needed|date={}}}}}",
+        Utc::now().format("%B %Y")
+    );
+
+    for page in batches
+        .iter()
+        .flat_map(|b| b["backlinks"].as_array().into_iter().flatten())
+        .filter_map(|l| l["title"].as_str())
+    {
+        let text = fetch_content(client, ENWIKI_API, page).await?;
+        if check_nobots_wikitext(&text) {
+            continue;
+        }
+        let mut newtext = text.clone();
+        let matches: Vec<_> = re.find_iter(&text).collect::<Result<_, _>>()?;
+        if matches.is_empty() {
+            continue;
+        }
+        for m in matches.iter().rev() {
+            newtext.insert_str(m.end(), &dn);
+        }
+        info!("tagging {} link(s) on [[{page}]]", matches.len());
+        save(
+            client,
+            PageSpec::Title(page.to_owned()),
+            Some(&text),
+            newtext,
+            format!(
+                "Tagging link to [[{}]], which redirects to a disambiguation page",
+                e.redirect
+            ),
+            None,
+            &FLAGS,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn main(tag_links: bool) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+        let entries = entries(&site).await?;
+        info!("{} redirects to disambiguation pages", entries.len());
+
+        if scope.is_narrowed() {
+            info!("not updating [[{REPORT}]] from a narrowed run");
+        } else {
+            save(
+                &client,
+                PageSpec::Title(REPORT.to_owned()),
+                None,
+                render(&entries),
+                format!("Updating report ({} entries)", entries.len()),
+                None,
+                &FLAGS,
+            )
+            .await?;
+        }
+
+        if tag_links {
+            let selected = entries.iter().filter(|e| scope.selects(&e.redirect));
+            for e in scope.window(selected).take(TAG_TOP) {
+                if let Err(err) = tag(&client, e).await {
+                    runs::page_failed();
+                    warn!("failed to tag links to [[{}]]: {err}", e.redirect);
+                }
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,294 @@
+//! Runs tasks on a schedule, instead of cron and wrapper scripts.
+//!
+//! [`SCHEDULE`] maps task names (see [`tasks::NAMES`]) to a cron expression in
+//! UTC, with five fields or six with seconds first, and the arguments the task
+//! would take on the command line, e.g.
+//!
+//! ```json
+//! {
+//!     "peer_review": {"cron": "0 3 * * *"},
+//!     "typos": {"cron": "0 0 * * Sun", "args": ["insource:/recieve/"]}
+//! }
+//! ```
+//!
+//! A task is never started while its previous run is still going, and times
+//! missed that way are skipped. A failed run is retried with a growing wait,
+//! until it succeeds or its next scheduled time comes. Every run is recorded in
+//! the [`runs`] history, reported to the [`webhook`] and shown on the
+//! [`status_page`]. What runs can be watched live on the [`dashboard`].
+//!
+//! On `SIGHUP`, the schedule, [`throttle`] profiles and [`edit`] flag
+//! overrides are read again. Tasks whose entry changed or was removed stop
+//! after their current run, if any, and new entries start. Invalid files are
+//! reported and the configuration from before is kept. Where there is no
+//! `SIGHUP`, they are only read at startup.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::{bail, Context};
+use cron::Schedule;
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
+use serde::Deserialize;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tracing::{info, warn};
+
+use crate::health::{self, TaskStatus};
+use crate::{checkpoint, dashboard, edit, runs, status_page, tasks, throttle, webhook, Result};
+
+const SCHEDULE: &str = "schedule.json";
+
+/// Wait before retrying a failed run, doubled on each retry.
+const FIRST_RETRY: Duration = Duration::from_secs(60);
+
+const MAX_RETRY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    cron: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+fn parse_cron(expr: &str) -> Result<Schedule> {
+    // the cron crate wants seconds, which crontabs don't have
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {expr}")
+    } else {
+        expr.to_owned()
+    };
+    Ok(Schedule::from_str(&expr)?)
+}
+
+/// Reads and checks [`SCHEDULE`].
+fn load() -> Result<BTreeMap<String, (Entry, Schedule)>> {
+    let entries: BTreeMap<String, Entry> = serde_json::from_str(
+        &fs::read_to_string(SCHEDULE).with_context(|| format!("reading {SCHEDULE}"))?,
+    )
+    .with_context(|| format!("invalid {SCHEDULE}"))?;
+    if entries.is_empty() {
+        bail!("nothing scheduled in {SCHEDULE}");
+    }
+
+    let mut schedules = BTreeMap::new();
+    for (task, entry) in entries {
+        if !tasks::NAMES.contains(&task.as_str()) {
+            bail!("no task named {task}, in {SCHEDULE}");
+        }
+        let schedule =
+            parse_cron(&entry.cron).with_context(|| format!("invalid cron for {task}"))?;
+        schedules.insert(task, (entry, schedule));
+    }
+    Ok(schedules)
+}
+
+/// A task running on its schedule.
+struct Handle {
+    /// Dropped to stop the task, once it is done with its current run.
+    stop: oneshot::Sender<()>,
+    /// Closed once the task has stopped.
+    done: oneshot::Receiver<()>,
+}
+
+/// Whether the task was asked to stop.
+fn stopped(stop: &mut oneshot::Receiver<()>) -> bool {
+    !matches!(stop.try_recv(), Err(TryRecvError::Empty))
+}
+
+async fn run_on_schedule(
+    task: String,
+    schedule: Schedule,
+    args: Vec<String>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let task = task.as_str();
+    loop {
+        let Some(next) = schedule.upcoming(Utc).next() else {
+            warn!("{task} has no more scheduled runs");
+            return;
+        };
+        info!("next run of {task} at {next}");
+        health::set_task(task, TaskStatus::Waiting { next });
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = &mut stop => break,
+        }
+
+        let mut wait = FIRST_RETRY;
+        loop {
+            info!("starting {task}");
+            health::set_task(task, TaskStatus::Running { since: Utc::now() });
+            let run = checkpoint::unsaved(dashboard::watched(task, tasks::run(task, &args)));
+            let (report, res) = runs::recorded(task, run).await;
+            webhook::report(&report).await;
+            status_page::update(&report, schedule.upcoming(Utc).next()).await;
+            let Err(e) = res else {
+                info!("{task} finished");
+                break;
+            };
+            let error = e.to_string();
+            health::set_task(
+                task,
+                TaskStatus::Failed {
+                    at: Utc::now(),
+                    error,
+                },
+            );
+            let retry_at = Utc::now() + wait;
+            if schedule.upcoming(Utc).next().is_some_and(|n| n <= retry_at) {
+                warn!("{task} failed, leaving it to the next scheduled run: {e}");
+                break;
+            }
+            warn!("{task} failed, retrying in {wait:?}: {e}");
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = &mut stop => break,
+            }
+            wait = (wait * 2).min(MAX_RETRY);
+        }
+        if stopped(&mut stop) {
+            break;
+        }
+    }
+    info!("stopped {task}");
+    health::remove_task(task);
+}
+
+/// Runs `task` on `schedule`, once `after` is closed if given.
+fn start(
+    task: &str,
+    entry: &Entry,
+    schedule: &Schedule,
+    after: Option<oneshot::Receiver<()>>,
+) -> (Handle, LocalBoxFuture<'static, ()>) {
+    let (stop, stop_rx) = oneshot::channel();
+    let (done_tx, done) = oneshot::channel::<()>();
+    let run = run_on_schedule(
+        task.to_owned(),
+        schedule.clone(),
+        entry.args.clone(),
+        stop_rx,
+    );
+    let run = async move {
+        if let Some(after) = after {
+            let _ = after.await;
+        }
+        run.await;
+        drop(done_tx);
+    };
+    (Handle { stop, done }, run.boxed_local())
+}
+
+/// `SIGHUP`, which asks for the configuration to be read again.
+#[cfg(unix)]
+struct Hangup(Signal);
+
+#[cfg(unix)]
+impl Hangup {
+    fn new() -> Result<Hangup> {
+        Ok(Hangup(signal(SignalKind::hangup())?))
+    }
+
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
+
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+impl Hangup {
+    fn new() -> Result<Hangup> {
+        Ok(Hangup)
+    }
+
+    /// Never comes.
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+pub async fn main() -> Result<()> {
+    let mut entries = load()?;
+    let mut hangup = Hangup::new()?;
+
+    health::set_ready();
+    let server = tokio::spawn(async {
+        if let Err(e) = health::serve().await {
+            warn!("health checks are down: {e}");
+        }
+    });
+    let dashboard = tokio::spawn(async {
+        if let Err(e) = dashboard::serve().await {
+            warn!("the dashboard is down: {e}");
+        }
+    });
+
+    // task futures aren't Send, so they share this task rather than being
+    // spawned
+    let mut running = FuturesUnordered::new();
+    let mut handles = BTreeMap::new();
+    for (task, (entry, schedule)) in &entries {
+        let (handle, run) = start(task, entry, schedule, None);
+        handles.insert(task.clone(), handle);
+        running.push(run);
+    }
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {}
+            next = running.next() => match next {
+                Some(()) => continue,
+                None => break,
+            },
+        }
+        info!("reloading configuration");
+        if let Err(e) = throttle::reload() {
+            warn!("keeping the previous rate profiles: {e}");
+        }
+        if let Err(e) = edit::reload_flags() {
+            warn!("keeping the previous edit flags: {e}");
+        }
+        let new = match load() {
+            Ok(new) => new,
+            Err(e) => {
+                warn!("keeping the previous schedule: {e:#}");
+                continue;
+            }
+        };
+        // a changed task starts again once its current run is over, so that
+        // runs never overlap
+        let mut stopping = BTreeMap::new();
+        for (task, (entry, _)) in &entries {
+            if new.get(task).map(|(e, _)| e) == Some(entry) {
+                continue;
+            }
+            info!("stopping {task}");
+            if let Some(Handle { stop, done }) = handles.remove(task) {
+                drop(stop);
+                stopping.insert(task.clone(), done);
+            }
+        }
+        for (task, (entry, schedule)) in &new {
+            if !handles.contains_key(task) {
+                let (handle, run) = start(task, entry, schedule, stopping.remove(task));
+                handles.insert(task.clone(), handle);
+                running.push(run);
+            }
+        }
+        entries = new;
+    }
+    server.abort();
+    dashboard.abort();
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! A web page showing what the daemon is doing, for watching it during trials
+//! without a shell on the host.
+//!
+//! When `DASHBOARD_ADDR` is set, e.g. to `127.0.0.1:8080`, the daemon serves
+//! the pages being treated, the status of each scheduled task, the last
+//! [`KEEP_EDITS`] edits with links to their diffs and the warnings and errors
+//! among the recent logs on `/`, and the same as JSON on `/state.json`.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+
+use axum::response::{Html, Json};
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::health::{self, TaskStatus};
+use crate::{email, throttle, Result};
+
+const KEEP_EDITS: usize = 50;
+
+/// The wiki diffs link to when the task's isn't known.
+const DEFAULT_WIKI: &str = "en.wikipedia.org";
+
+#[derive(Serialize, Clone, Debug)]
+struct Page {
+    task: Option<String>,
+    title: String,
+    since: DateTime<Utc>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct Edit {
+    at: DateTime<Utc>,
+    task: String,
+    title: String,
+    diff: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    pages: Vec<Page>,
+    edits: VecDeque<Edit>,
+}
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(Default::default);
+
+tokio::task_local! {
+    /// The scheduled task running.
+    static TASK: String;
+}
+
+/// Runs `run` as a run of the scheduled task `task`.
+pub async fn watched<T>(task: &str, run: impl Future<Output = T>) -> T {
+    TASK.scope(task.to_owned(), run).await
+}
+
+/// Records that `title` is being treated.
+pub fn started(title: &str) {
+    STATE.lock().unwrap().pages.push(Page {
+        task: TASK.try_with(Clone::clone).ok(),
+        title: title.to_owned(),
+        since: Utc::now(),
+    });
+}
+
+/// Records that `title` is no longer being treated.
+pub fn finished(title: &str) {
+    let mut state = STATE.lock().unwrap();
+    if let Some(at) = state.pages.iter().position(|p| p.title == title) {
+        state.pages.remove(at);
+    }
+}
+
+/// Records an edit by `task` from the API's response to it.
+pub fn edited(task: &str, res: &Value) {
+    let edit = &res["edit"];
+    let wiki = throttle::wiki().unwrap_or_else(|| DEFAULT_WIKI.to_owned());
+    let edit = Edit {
+        at: Utc::now(),
+        task: task.to_owned(),
+        title: edit["title"].as_str().unwrap_or_default().to_owned(),
+        diff: edit["newrevid"]
+            .as_u64()
+            .map(|revid| format!("https://{wiki}/wiki/Special:Diff/{revid}")),
+    };
+    let mut state = STATE.lock().unwrap();
+    if state.edits.len() == KEEP_EDITS {
+        state.edits.pop_back();
+    }
+    state.edits.push_front(edit);
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    pages: Vec<Page>,
+    tasks: serde_json::Map<String, Value>,
+    /// Newest first.
+    edits: Vec<Edit>,
+    /// Newest first.
+    errors: Vec<String>,
+}
+
+fn snapshot() -> Snapshot {
+    let state = STATE.lock().unwrap();
+    let errors = email::recent_logs()
+        .into_iter()
+        .rev()
+        .filter(|line| matches!(line.split_whitespace().nth(1), Some("WARN" | "ERROR")))
+        .collect();
+    Snapshot {
+        pages: state.pages.clone(),
+        tasks: health::tasks()
+            .iter()
+            .map(|(task, status)| (task.clone(), status.to_json()))
+            .collect(),
+        edits: state.edits.iter().cloned().collect(),
+        errors,
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn time(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn render(snapshot: &Snapshot) -> String {
+    let mut s = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+        <meta http-equiv=\"refresh\" content=\"10\"><title>DeadbeefBot</title></head><body>\n",
+    );
+    s.push_str("<h2>Treating</h2>\n<ul>\n");
+    for page in &snapshot.pages {
+        let task = page.task.as_deref().unwrap_or("?");
+        let _ = writeln!(
+            s,
+            "<li>{}: {} since {}</li>",
+            escape(task),
+            escape(&page.title),
+            time(page.since)
+        );
+    }
+    s.push_str("</ul>\n<h2>Tasks</h2>\n<ul>\n");
+    for (task, status) in health::tasks() {
+        let status = match status {
+            TaskStatus::Waiting { next } => format!("next run at {}", time(next)),
+            TaskStatus::Running { since } => format!("running since {}", time(since)),
+            TaskStatus::Failed { at, error } => {
+                format!("failed at {}: {}", time(at), escape(&error))
+            }
+        };
+        let _ = writeln!(s, "<li>{}: {status}</li>", escape(&task));
+    }
+    s.push_str("</ul>\n<h2>Recent edits</h2>\n<ul>\n");
+    for edit in &snapshot.edits {
+        let title = escape(&edit.title);
+        let title = match &edit.diff {
+            Some(diff) => format!("<a href=\"{}\">{title}</a>", escape(diff)),
+            None => format!("{title} (no change)"),
+        };
+        let _ = writeln!(
+            s,
+            "<li>{} {}: {title}</li>",
+            time(edit.at),
+            escape(&edit.task)
+        );
+    }
+    s.push_str("</ul>\n<h2>Warnings and errors</h2>\n<pre>\n");
+    for line in &snapshot.errors {
+        let _ = writeln!(s, "{}", escape(line));
+    }
+    s.push_str("</pre>\n</body></html>\n");
+    s
+}
+
+async fn index() -> Html<String> {
+    Html(render(&snapshot()))
+}
+
+async fn state() -> Json<Snapshot> {
+    Json(snapshot())
+}
+
+/// Serves the dashboard on `DASHBOARD_ADDR`, if it is set.
+pub async fn serve() -> Result<()> {
+    let Ok(addr) = env::var("DASHBOARD_ADDR") else {
+        return Ok(());
+    };
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/state.json", get(state));
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("binding {addr}"))?;
+    info!("serving the dashboard on {addr}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Notifies WikiProjects of deletion discussions about pages in their scope.
+//!
+//! Projects subscribe on [`SUBSCRIPTIONS`], a JSON object mapping a project
+//! banner name to the page that alerts should be posted to:
+//!
+//! ```json
+//! { "WikiProject Birds": "Wikipedia:WikiProject Birds/Deletion alerts" }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use parsoid::{Template, WikinodeIterator};
+use tracing::{debug, info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::siteinfo::SiteInfo;
+use crate::source::{CategorySource, PageSource};
+use crate::{
+    enwiki_parsoid, fetch_content, run_lock, runs, selection, Parsoid, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("deletion_alerts", EditFlags::BOT);
+
+const SUBSCRIPTIONS: &str = "User:DeadbeefBot/Deletion alerts";
+
+/// A deletion venue along with how to find its open nominations.
+struct Venue {
+    name: &'static str,
+    /// Tracking category populated by the nomination notice.
+    category: &'static str,
+    namespace: &'static str,
+    /// Notice templates placed on nominated pages.
+    notices: &'static [&'static str],
+    /// Builds the discussion link from a notice.
+    link: fn(&Template) -> Option<String>,
+}
+
+static VENUES: &[Venue] = &[
+    Venue {
+        name: "AfD",
+        category: "Category:Articles for deletion",
+        namespace: "0",
+        notices: &["article for deletion/dated", "afdm"],
+        link: |t| {
+            let page = t.param("page")?;
+            Some(format!("Wikipedia:Articles for deletion/{}", page.trim()))
+        },
+    },
+    Venue {
+        name: "TfD",
+        category: "Category:Templates for discussion",
+        namespace: "10|828",
+        notices: &["template for discussion/dated", "tfd", "tfm"],
+        link: |t| Some(t.param("link")?.trim().to_owned()),
+    },
+    Venue {
+        name: "CfD",
+        category: "Category:Categories for discussion",
+        namespace: "14",
+        notices: &[
+            "cfd full", "cfr full", "cfm full", "cfs full", "cfd", "cfr", "cfm", "cfs",
+        ],
+        link: |t| {
+            let mut link = format!(
+                "Wikipedia:Categories for discussion/Log/{} {} {}",
+                t.param("year")?.trim(),
+                t.param("month")?.trim(),
+                t.param("day")?.trim()
+            );
+            if let Some(section) = t.param("section") {
+                link.push('#');
+                link.push_str(section.trim());
+            }
+            Some(link)
+        },
+    },
+];
+
+fn normalize(name: &str) -> String {
+    name.trim()
+        .trim_start_matches("Template:")
+        .replace('_', " ")
+        .to_lowercase()
+}
+
+#[derive(Clone)]
+struct Nomination {
+    venue: &'static str,
+    title: String,
+    discussion: String,
+}
+
+async fn nomination(
+    parsoid: &Parsoid,
+    venue: &'static Venue,
+    title: &str,
+) -> Result<Option<Nomination>> {
+    let code = parsoid.get(title).await?.into_mutable();
+    let discussion = code.filter_templates()?.into_iter().find_map(|t| {
+        let name = normalize(&t.name());
+        if venue.notices.contains(&&*name) {
+            (venue.link)(&t)
+        } else {
+            None
+        }
+    });
+    Ok(discussion.map(|discussion| Nomination {
+        venue: venue.name,
+        title: title.to_owned(),
+        discussion,
+    }))
+}
+
+/// Normalized names of the templates in the lead of the talk page of `title`.
+async fn banners(parsoid: &Parsoid, site: &SiteInfo, title: &str) -> Result<Vec<String>> {
+    let talk = site.talk_page(title);
+    // Banners are at the top, and talk pages can have years of discussion
+    // below them.
+    match parsoid.get_lead(&talk).await? {
+        Some(code) => Ok(code
+            .into_mutable()
+            .filter_templates()?
+            .iter()
+            .map(|t| normalize(&t.name()))
+            .collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn post(client: &wiki::Bot, page: &str, nominations: &[Nomination]) -> Result<()> {
+    let old = fetch_content(client, ENWIKI_API, page)
+        .await
+        .unwrap_or_default();
+    let new: Vec<_> = nominations
+        .iter()
+        .filter(|n| !old.contains(&format!("[[{}|", n.discussion)))
+        .collect();
+    if new.is_empty() {
+        return Ok(());
+    }
+    let mut text = old.clone();
+    for n in &new {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&format!(
+            "* [[:{}]] ([[{}|{}]]) – noticed ~~~~~",
+            n.title, n.discussion, n.venue
+        ));
+    }
+    info!("posting {} alert(s) to [[{page}]]", new.len());
+    save(
+        client,
+        PageSpec::Title(page.to_owned()),
+        Some(&old),
+        text,
+        format!("Deletion discussion alerts: {} new", new.len()),
+        None,
+        &FLAGS,
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn main() -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+
+        let subscriptions: HashMap<String, String> =
+            serde_json::from_str(&fetch_content(&client, ENWIKI_API, SUBSCRIPTIONS).await?)?;
+        let subscriptions: HashMap<_, _> = subscriptions
+            .into_iter()
+            .map(|(banner, page)| (normalize(&banner), page))
+            .collect();
+
+        let mut alerts: BTreeMap<&str, Vec<Nomination>> = BTreeMap::new();
+        for venue in VENUES {
+            let pages = CategorySource::new(&client, ENWIKI_API, venue.category)
+                .namespace(venue.namespace)
+                .collect_all()
+                .await?;
+            info!("{} open {} nominations", pages.len(), venue.name);
+
+            for title in scope.filter(pages) {
+                let res = async {
+                    let Some(nom) = nomination(&parsoid, venue, &title).await? else {
+                        debug!("no {} notice on [[{title}]]", venue.name);
+                        return Ok(());
+                    };
+                    let banners = banners(&parsoid, &site, &title).await?;
+                    let targets: BTreeSet<_> = banners
+                        .iter()
+                        .filter_map(|b| subscriptions.get(b))
+                        .collect();
+                    for page in targets {
+                        alerts.entry(page.as_str()).or_default().push(nom.clone());
+                    }
+                    Result::<()>::Ok(())
+                }
+                .await;
+                if let Err(e) = res {
+                    runs::page_failed();
+                    warn!("failed to check [[{title}]]: {e}");
+                }
+            }
+        }
+
+        for (page, nominations) in alerts {
+            if let Err(e) = post(&client, page, &nominations).await {
+                runs::page_failed();
+                warn!("failed to post to [[{page}]]: {e}");
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
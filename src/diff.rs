@@ -0,0 +1,71 @@
+//! Showing a change before it is saved.
+//!
+//! Diffs are colored for reading in a terminal unless `--diff-format unified`
+//! is given, which prints standard unified diffs instead, for piping into
+//! review tools or attaching to a BRFA.
+
+use std::sync::OnceLock;
+
+use color_eyre::eyre::bail;
+use colored_diff::PrettyDifference;
+use similar::TextDiff;
+
+use crate::Result;
+
+/// Lines of context around each hunk of a unified diff.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Pretty,
+    Unified,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// Reads `--diff-format` out of a command line, ignoring the rest. Only the
+/// first call has an effect.
+pub fn from_args(args: &[String]) -> Result<()> {
+    let mut format = Format::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--diff-format" {
+            continue;
+        }
+        format = match args.next().map(String::as_str) {
+            Some("pretty") => Format::Pretty,
+            Some("unified") => Format::Unified,
+            Some(other) => bail!("no diff format {other}, only pretty or unified"),
+            None => bail!("--diff-format needs a value"),
+        };
+    }
+    let _ = FORMAT.set(format);
+    Ok(())
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// The change from `old` to `new` on `title`, in the [`format`] given.
+pub fn render(title: &str, old: &str, new: &str) -> String {
+    match format() {
+        Format::Pretty => PrettyDifference {
+            expected: old,
+            actual: new,
+        }
+        .to_string(),
+        Format::Unified => unified(title, old, new),
+    }
+}
+
+/// The change from `old` to `new` on `title` as a unified diff, whatever the
+/// [`format`].
+pub fn unified(title: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT)
+        .header(&format!("a/{title}"), &format!("b/{title}"))
+        .to_string()
+}
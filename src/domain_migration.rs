@@ -0,0 +1,347 @@
+//! Rewrites external links from a dead or moved domain to its replacement,
+//! for carrying out [[WP:URLREQ]] requests.
+//!
+//! A mapping file looks like:
+//!
+//! ```json
+//! {
+//!     "domains": [{
+//!         "from": "old.example.com",
+//!         "to": "www.example.org",
+//!         "https": true,
+//!         "paths": [{ "pattern": "^/news/(\\d+)\\.html", "replacement": "/articles/${1}" }]
+//!     }],
+//!     "summary": "Migrating links to example.org per [[WP:URLREQ#example.com]]"
+//! }
+//! ```
+//!
+//! `from` also covers its `www.` form. Path rules are tried in order against
+//! the path and query, and the first matching one is applied. Links inside
+//! archive URLs are left alone, but archive links in citations are pointed at
+//! a snapshot of the new URL when the Wayback Machine has one.
+
+use chrono::NaiveDateTime;
+use color_eyre::eyre::{bail, Context};
+use fancy_regex::Regex;
+use parsoid::WikinodeIterator;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+use url::{Position, Url};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{check_dirty_diff, save, EditFlags, TaskFlags};
+use crate::links::{
+    bare_links, is_citation, linking_pages, on_domain, replace_urls, url_pattern, wayback_snapshots,
+};
+use crate::{
+    check_nobots, enwiki_parsoid, fetch_contents, fetch_latest, policy, run_lock, selection,
+    PageContent, Parsoid, Result, CONTENT_BATCH, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("domain_migration", EditFlags::MINOR_BOT);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PathDef {
+    pattern: String,
+    replacement: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DomainDef {
+    from: String,
+    to: String,
+    #[serde(default)]
+    https: bool,
+    #[serde(default)]
+    paths: Vec<PathDef>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    domains: Vec<DomainDef>,
+    summary: String,
+}
+
+pub struct Domain {
+    pub from: String,
+    pub to: String,
+    pub https: bool,
+    pub paths: Vec<(Regex, String)>,
+    /// Bare links on `from`, not preceded by something that makes them part
+    /// of another URL.
+    link: Regex,
+    /// Wayback Machine copies of links on `from`.
+    archive: Regex,
+}
+
+impl Domain {
+    fn new(def: DomainDef) -> Result<Domain> {
+        let url = url_pattern(&def.from, "https?");
+        let paths = def
+            .paths
+            .into_iter()
+            .map(|p| -> Result<(Regex, String)> { Ok((Regex::new(&p.pattern)?, p.replacement)) })
+            .collect::<Result<_>>()?;
+        Ok(Domain {
+            link: bare_links(&url)?,
+            archive: Regex::new(&format!(
+                r"^https?://web\.archive\.org/web/([0-9]+)/({url})$"
+            ))?,
+            from: def.from,
+            to: def.to,
+            https: def.https,
+            paths,
+        })
+    }
+
+    /// The replacement for `s`, or `None` if it isn't on this domain.
+    pub fn rewrite(&self, s: &str) -> Result<Option<String>> {
+        let url = Url::parse(s)?;
+        if !url.host_str().is_some_and(|h| on_domain(h, &self.from)) {
+            return Ok(None);
+        }
+        let mut rest = url[Position::BeforePath..].to_owned();
+        for (re, replacement) in &self.paths {
+            if re.is_match(&rest)? {
+                rest = re
+                    .try_replacen(&rest, 1, replacement.as_str())?
+                    .into_owned();
+                break;
+            }
+        }
+        let scheme = if self.https { "https" } else { url.scheme() };
+        let new_url: String = Url::parse(&format!("{scheme}://{}{rest}", self.to))?.into();
+        Ok((new_url != s).then_some(new_url))
+    }
+}
+
+pub struct Mapping {
+    pub domains: Vec<Domain>,
+    pub summary: String,
+    /// Lines the task may change.
+    intended: Regex,
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> Result<Mapping> {
+        let file: MappingFile = serde_json::from_str(&checkpoint::read_required(path)?)
+            .with_context(|| format!("parsing {path}"))?;
+
+        if file.domains.is_empty() {
+            bail!("{path} lists no domains");
+        }
+        let domains: Vec<_> = file
+            .domains
+            .iter()
+            .map(|d| fancy_regex::escape(&d.from).into_owned())
+            .collect();
+        let intended = Regex::new(&format!("(?i){}|archive-date", domains.join("|")))?;
+
+        Ok(Mapping {
+            domains: file
+                .domains
+                .into_iter()
+                .map(Domain::new)
+                .collect::<Result<_>>()?,
+            summary: file.summary,
+            intended,
+        })
+    }
+
+    /// Whether `text` might link to any of the domains.
+    pub fn mentioned_in(&self, text: &str) -> bool {
+        let text = text.to_ascii_lowercase();
+        self.domains
+            .iter()
+            .any(|d| text.contains(&d.from.to_ascii_lowercase()))
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Counts {
+    pub links: usize,
+    pub archives: usize,
+}
+
+impl Counts {
+    fn summary(&self, base: &str) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let archives = if self.archives > 0 {
+            format!(
+                ", {} archive link{} updated",
+                self.archives,
+                plural(self.archives)
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "{base} ({} link{} updated{archives})",
+            self.links,
+            plural(self.links)
+        )
+    }
+}
+
+/// The snapshot of `url` closest in time to `timestamp`.
+async fn closest_snapshot(
+    client: &reqwest::Client,
+    url: &str,
+    timestamp: &str,
+) -> Result<Option<String>> {
+    let target: u64 = timestamp.parse()?;
+    Ok(wayback_snapshots(client, url)
+        .await?
+        .into_iter()
+        .filter_map(|ts| Some((ts.parse::<u64>().ok()?.abs_diff(target), ts)))
+        .min()
+        .map(|(_, ts)| ts))
+}
+
+pub async fn treat(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    http: &reqwest::Client,
+    mapping: &Mapping,
+    page: &PageContent,
+) -> Result<Counts> {
+    let title = &page.title;
+    let code = parsoid
+        .get_revision(title, page.revid as u64)
+        .await?
+        .into_mutable();
+    let templates = code.filter_templates()?;
+
+    if templates.iter().any(check_nobots) {
+        return Ok(Counts::default());
+    }
+
+    let mut counts = Counts::default();
+    for t in templates.iter().filter(|t| is_citation(t)) {
+        let Some(archive) = t.param("archive-url") else {
+            continue;
+        };
+        for domain in &mapping.domains {
+            let Some(captures) = domain.archive.captures(archive.trim())? else {
+                continue;
+            };
+            let Some(new_url) = domain.rewrite(&captures[2])? else {
+                break;
+            };
+            match closest_snapshot(http, &new_url, &captures[1]).await {
+                Ok(Some(ts)) => {
+                    let date = NaiveDateTime::parse_from_str(&ts, "%Y%m%d%H%M%S")?;
+                    t.set_param(
+                        "archive-url",
+                        &format!("https://web.archive.org/web/{ts}/{new_url}"),
+                    )?;
+                    t.set_param("archive-date", &date.format("%Y-%m-%d").to_string())?;
+                    counts.archives += 1;
+                }
+                Ok(None) => debug!("no snapshot of {new_url}"),
+                Err(e) => info!("did not fix archive of {new_url}: {e}"),
+            }
+            break;
+        }
+    }
+
+    let mut text = parsoid.transform_to_wikitext(&code).await?;
+    for domain in &mapping.domains {
+        let (newtext, links) = replace_urls(&text, &domain.link, |url| domain.rewrite(url))?;
+        text = newtext;
+        counts.links += links;
+    }
+
+    debug!(?counts);
+    if counts.links + counts.archives == 0 {
+        return Ok(counts);
+    }
+    check_dirty_diff(title, &page.text, &text, &mapping.intended)?;
+
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        Some(&page.text),
+        text,
+        counts.summary(&mapping.summary),
+        Some(page.revid),
+        &FLAGS,
+    )
+    .await?;
+    Ok(counts)
+}
+
+pub async fn main(mapping: &str) -> Result<()> {
+    let mapping = Mapping::load(mapping)?;
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+        let http = crate::http()?.archive.clone();
+
+        let linking = async {
+            let mut pages = Vec::new();
+            for domain in &mapping.domains {
+                pages.extend(linking_pages(&client, &domain.from, &["http", "https"]).await?);
+            }
+            pages.sort();
+            pages.dedup();
+            info!("{} pages link to the domains", pages.len());
+            Ok(pages)
+        };
+        let pages = scope.titles(linking).await?;
+
+        let mut total = Counts::default();
+        for chunk in pages.chunks(CONTENT_BATCH) {
+            let chunk: Vec<_> = chunk.iter().map(String::as_str).collect();
+            let contents = match fetch_contents(&client, ENWIKI_API, &chunk).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warn!("failed to fetch batch starting at [[{}]]: {e}", chunk[0]);
+                    continue;
+                }
+            };
+            // links added by templates show up in exturlusage too
+            for page in contents.iter().filter(|p| mapping.mentioned_in(&p.text)) {
+                let title = &page.title;
+                let mut prefetched = Some(page);
+                let treated = || {
+                    let prefetched = prefetched.take();
+                    let (client, parsoid, http, mapping) = (&client, &parsoid, &http, &mapping);
+                    async move {
+                        // treating it again, the prefetched revision may be stale
+                        let latest;
+                        let page = match prefetched {
+                            Some(page) => page,
+                            None => {
+                                latest = fetch_latest(client, ENWIKI_API, title).await?;
+                                &latest
+                            }
+                        };
+                        treat(client, parsoid, http, mapping, page).await
+                    }
+                };
+                match policy::page(FLAGS.task, title, treated).await? {
+                    Some(c) if c.links + c.archives == 0 => continue,
+                    Some(c) => {
+                        total.links += c.links;
+                        total.archives += c.archives;
+                    }
+                    None => {}
+                }
+            }
+        }
+        info!(
+            "updated {} links and {} archive links",
+            total.links, total.archives
+        );
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,112 @@
+//! `--dry-run` runs, which go through the pages a task would edit and show
+//! each edit as a diff instead of saving it, for demonstrating a task in a
+//! BRFA.
+//!
+//! Instead of saving, [`edit::pre_save`](crate::edit::pre_save) renders a
+//! unified diff of the edit, against the text the task passed it replaces or
+//! else the page as it is now, or a line for a null edit, and prints it, or
+//! appends it to the file given with `--diff-file <path>`. Unlike in a
+//! `--list-only` run, saves made outside of a page, like reports, are shown
+//! too. At the end, the changes are summed up like a
+//! [`worklist`](crate::worklist)'s.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use color_eyre::eyre::{bail, Context};
+use tracing::debug;
+use wiki::req::PageSpec;
+
+use crate::edit::Change;
+use crate::{diff, fetch_content, timing, worklist, Result};
+
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File(PathBuf),
+}
+
+static OUTPUT: OnceLock<Option<Output>> = OnceLock::new();
+
+/// Held while writing a diff, so that those of concurrent saves don't mix.
+static WRITING: Mutex<()> = Mutex::new(());
+
+/// Reads `--dry-run` and `--diff-file` out of a command line, ignoring the
+/// rest. `--diff-file` implies `--dry-run`. Only the first call has an
+/// effect.
+pub fn from_args(args: &[String]) -> Result<()> {
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => {
+                output.get_or_insert(Output::Stdout);
+            }
+            "--diff-file" => {
+                let Some(path) = args.next() else {
+                    bail!("--diff-file needs a path");
+                };
+                output = Some(Output::File(PathBuf::from(path)));
+            }
+            _ => {}
+        }
+    }
+    let _ = OUTPUT.set(output);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    matches!(OUTPUT.get(), Some(Some(_)))
+}
+
+/// Shows the edit that would have made `change` to `page` with `summary`.
+pub async fn show(
+    client: &wiki::Bot,
+    api_url: &str,
+    page: &PageSpec,
+    change: Change<'_>,
+    summary: &str,
+) -> Result<()> {
+    let title = match page {
+        PageSpec::Title(title) => title.clone(),
+        // saved by page ID, always while treating it
+        _ => timing::current_page().unwrap_or_default(),
+    };
+    let shown = match change {
+        Change::Null => format!("Null edit of [[{title}]]"),
+        Change::Text { old, new } => {
+            let old = match (old, page) {
+                (Some(old), _) => Some(old.to_owned()),
+                (None, PageSpec::Title(title)) => {
+                    match fetch_content(client, api_url, title).await {
+                        Ok(text) => Some(text),
+                        Err(e) => {
+                            // most likely a page the edit would create
+                            debug!("diffing [[{title}]] against nothing: {e}");
+                            Some(String::new())
+                        }
+                    }
+                }
+                (None, _) => None,
+            };
+            worklist::shown(&title, old.as_deref(), new);
+            let diff = diff::unified(&title, old.as_deref().unwrap_or_default(), new);
+            format!("Summary: {summary}\n{diff}")
+        }
+    };
+    let _writing = WRITING.lock().unwrap();
+    match OUTPUT.get() {
+        Some(Some(Output::File(path))) => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("opening {}", path.display()))?;
+            writeln!(file, "{shown}")?;
+        }
+        _ => println!("{shown}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Runs tasks over an XML dump instead of the live wiki, to measure how many
+//! pages they would change or fail on across millions of pages.
+//!
+//! Takes a `pages-articles` or talk pages dump, compressed with bzip2 or not.
+//! Nothing is saved and nothing else is fetched: tasks that need Parsoid or
+//! other sites for a page can only say so, which is counted too.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Instant;
+
+use bzip2::read::MultiBzDecoder;
+use color_eyre::eyre::{bail, Context};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tracing::{debug, info};
+
+use crate::{remove_twitter_trackers, wikitext, Result};
+
+/// How often progress is logged, in pages.
+const PROGRESS_EVERY: u64 = 100_000;
+
+/// The tasks that can run over a dump.
+pub const TASKS: &[&str] = &["remove_twitter_trackers", "wikitext"];
+
+#[derive(Debug, Default)]
+pub struct DumpPage {
+    pub title: String,
+    pub ns: i32,
+    pub pageid: u64,
+    pub revid: u64,
+    pub text: String,
+}
+
+/// What a task would do with a page.
+#[derive(Debug)]
+pub enum Outcome {
+    Unchanged,
+    /// Edited, making this many changes.
+    Changed(usize),
+    Skipped(&'static str),
+    /// Needs something the dump doesn't have, like Parsoid.
+    Online(&'static str),
+}
+
+/// Reads the pages of a dump one by one.
+pub struct Dump<R> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+}
+
+impl Dump<Box<dyn BufRead>> {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {path}"))?;
+        let read: Box<dyn BufRead> = if path.ends_with(".bz2") {
+            Box::new(BufReader::new(MultiBzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        Ok(Dump::new(read))
+    }
+}
+
+impl<R: BufRead> Dump<R> {
+    pub fn new(read: R) -> Self {
+        Dump {
+            reader: Reader::from_reader(read),
+            buf: Vec::new(),
+        }
+    }
+
+    fn next_page(&mut self) -> Result<Option<DumpPage>> {
+        let mut page = None;
+        // names of the open elements inside <page>
+        let mut open: Vec<Vec<u8>> = Vec::new();
+        let mut text = String::new();
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(e) if e.name().as_ref() == b"page" => {
+                    page = Some(DumpPage::default());
+                    open.clear();
+                }
+                Event::Start(e) if page.is_some() => {
+                    open.push(e.name().as_ref().to_owned());
+                    text.clear();
+                }
+                Event::Text(t) if page.is_some() => text.push_str(&t.unescape()?),
+                Event::End(e) if e.name().as_ref() == b"page" => return Ok(page),
+                Event::End(_) => {
+                    let (Some(page), Some(name)) = (&mut page, open.pop()) else {
+                        continue;
+                    };
+                    let parent = open.last().map(Vec::as_slice);
+                    let value = std::mem::take(&mut text);
+                    match (parent, name.as_slice()) {
+                        (None, b"title") => page.title = value,
+                        (None, b"ns") => page.ns = value.trim().parse()?,
+                        (None, b"id") => page.pageid = value.trim().parse()?,
+                        (Some(b"revision"), b"id") => page.revid = value.trim().parse()?,
+                        (Some(b"revision"), b"text") => page.text = value,
+                        _ => {}
+                    }
+                }
+                Event::Eof if page.is_some() => bail!("dump ends inside a page"),
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Dump<R> {
+    type Item = Result<DumpPage>;
+
+    fn next(&mut self) -> Option<Result<DumpPage>> {
+        self.next_page().transpose()
+    }
+}
+
+fn simulate_page(task: &str, text: &str) -> Result<Outcome> {
+    match task {
+        "remove_twitter_trackers" => remove_twitter_trackers::simulate(text),
+        "wikitext" => {
+            wikitext::parse_templates(text)?;
+            Ok(Outcome::Unchanged)
+        }
+        _ => bail!("{task} can't run over a dump, only {}", TASKS.join(", ")),
+    }
+}
+
+#[derive(Default)]
+struct Tally {
+    pages: u64,
+    unchanged: u64,
+    changed: u64,
+    changes: u64,
+    skipped: BTreeMap<&'static str, u64>,
+    online: BTreeMap<&'static str, u64>,
+    /// Error messages, with how often they happened and the first page.
+    failed: BTreeMap<String, (u64, String)>,
+}
+
+impl Tally {
+    fn add(&mut self, title: &str, outcome: Result<Outcome>) {
+        self.pages += 1;
+        match outcome {
+            Ok(Outcome::Unchanged) => self.unchanged += 1,
+            Ok(Outcome::Changed(n)) => {
+                self.changed += 1;
+                self.changes += n as u64;
+            }
+            Ok(Outcome::Skipped(why)) => *self.skipped.entry(why).or_default() += 1,
+            Ok(Outcome::Online(why)) => *self.online.entry(why).or_default() += 1,
+            Err(e) => {
+                debug!("[[{title}]]: {e}");
+                let (n, _) = self
+                    .failed
+                    .entry(e.to_string())
+                    .or_insert_with(|| (0, title.to_owned()));
+                *n += 1;
+            }
+        }
+    }
+
+    fn print(&self) {
+        let pct = |n: u64| 100.0 * n as f64 / self.pages.max(1) as f64;
+        let failed: u64 = self.failed.values().map(|(n, _)| n).sum();
+        println!("{} page(s)", self.pages);
+        println!(
+            "  unchanged  {:>10} {:>6.2}%",
+            self.unchanged,
+            pct(self.unchanged)
+        );
+        println!(
+            "  changed    {:>10} {:>6.2}%  ({} change(s))",
+            self.changed,
+            pct(self.changed),
+            self.changes
+        );
+        for (why, &n) in &self.skipped {
+            println!("  skipped    {n:>10} {:>6.2}%  {why}", pct(n));
+        }
+        for (why, &n) in &self.online {
+            println!("  online     {n:>10} {:>6.2}%  {why}", pct(n));
+        }
+        println!("  failed     {failed:>10} {:>6.2}%", pct(failed));
+        let mut errors: Vec<_> = self.failed.iter().collect();
+        errors.sort_by_key(|(_, (n, _))| Reverse(*n));
+        for (e, (n, first)) in errors {
+            println!("    {n:>8}  {e} (first on [[{first}]])");
+        }
+    }
+}
+
+/// Runs `task` over the pages in namespace `ns` of the dump at `path`, or all
+/// of them, printing what it would have done.
+pub fn simulate(task: &str, path: &str, ns: Option<i32>) -> Result<()> {
+    if !TASKS.contains(&task) {
+        bail!("{task} can't run over a dump, only {}", TASKS.join(", "));
+    }
+    let start = Instant::now();
+    let mut tally = Tally::default();
+    for page in Dump::open(path)? {
+        let page = page?;
+        if ns.is_some_and(|ns| ns != page.ns) {
+            continue;
+        }
+        tally.add(&page.title, simulate_page(task, &page.text));
+        if tally.pages % PROGRESS_EVERY == 0 {
+            info!("{} pages in {:?}", tally.pages, start.elapsed());
+        }
+    }
+    tally.print();
+    Ok(())
+}
@@ -0,0 +1,464 @@
+//! Saving pages: checks on new page text before it is saved, the flags it is
+//! saved with, and what happens after.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use color_eyre::eyre::{bail, eyre};
+use fancy_regex::Regex;
+use kuchiki::traits::TendrilSink;
+use serde::Deserialize;
+use serde_json::Value;
+use similar::TextDiff;
+use tracing::{debug, info, warn};
+use wiki::req::PageSpec;
+
+use crate::client::{post_edit, session_user};
+use crate::timing::{self, Phase};
+use crate::{
+    accounts, audit, checkpoint, credentials, dashboard, dry_run, health, maxlag, query_raw,
+    reverts, runs, shutoff, skip_list, talk_page, throttle, trial, worklist, Result, ENWIKI_API,
+};
+
+/// Where unintended changes are recorded.
+const DIRTY_DIFF_LOG: &str = "dirty-diffs.log";
+
+/// Per-task overrides of [`EditFlags`], as a JSON object keyed by task name,
+/// e.g. `{"param_migration": {"minor": false}}`. Optional, and read again on
+/// [`reload_flags`].
+pub const EDIT_FLAGS: &str = "edit-flags.json";
+
+static EDITS: AtomicU64 = AtomicU64::new(0);
+
+/// Edits saved so far in this run, by any task.
+pub fn edit_count() -> u64 {
+    EDITS.load(Ordering::Relaxed)
+}
+
+/// How a task's edits are marked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EditFlags {
+    pub minor: bool,
+    pub bot: bool,
+}
+
+impl EditFlags {
+    pub const MINOR_BOT: EditFlags = EditFlags {
+        minor: true,
+        bot: true,
+    };
+    pub const BOT: EditFlags = EditFlags {
+        minor: false,
+        bot: true,
+    };
+    pub const NONE: EditFlags = EditFlags {
+        minor: false,
+        bot: false,
+    };
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Override {
+    minor: Option<bool>,
+    bot: Option<bool>,
+}
+
+/// The overrides in `text`, the contents of [`EDIT_FLAGS`] if there is one.
+fn parse_overrides(text: Option<&str>) -> Result<HashMap<String, Override>, String> {
+    match text {
+        Some(s) => serde_json::from_str(s).map_err(|e| format!("invalid {EDIT_FLAGS}: {e}")),
+        None => Ok(HashMap::new()),
+    }
+}
+
+fn load_overrides() -> Result<HashMap<String, Override>, String> {
+    let text = checkpoint::read_config(EDIT_FLAGS).map_err(|e| format!("{e:#}"))?;
+    parse_overrides(text.as_deref())
+}
+
+static OVERRIDES: LazyLock<RwLock<Result<HashMap<String, Override>, String>>> =
+    LazyLock::new(|| RwLock::new(load_overrides()));
+
+/// Reads [`EDIT_FLAGS`] again. If it has become invalid, the overrides read
+/// before stay in use.
+pub fn reload_flags() -> Result<()> {
+    let overrides = load_overrides().map_err(|e| eyre!("{e}"))?;
+    *OVERRIDES.write().unwrap() = Ok(overrides);
+    Ok(())
+}
+
+/// The edit flags of one task: its defaults, unless [`EDIT_FLAGS`] says
+/// otherwise.
+pub struct TaskFlags {
+    pub task: &'static str,
+    pub default: EditFlags,
+}
+
+impl TaskFlags {
+    pub const fn new(task: &'static str, default: EditFlags) -> TaskFlags {
+        TaskFlags { task, default }
+    }
+
+    pub fn get(&self) -> Result<EditFlags> {
+        let overrides = OVERRIDES.read().unwrap();
+        let overrides = overrides.as_ref().map_err(|e| eyre!("{e}"))?;
+        let mut flags = self.default;
+        if let Some(o) = overrides.get(self.task) {
+            flags.minor = o.minor.unwrap_or(flags.minor);
+            flags.bot = o.bot.unwrap_or(flags.bot);
+        }
+        Ok(flags)
+    }
+}
+
+/// What an edit does to a page.
+#[derive(Clone, Copy, Debug)]
+pub enum Change<'a> {
+    /// Replaces the page's text, which is `old` if the task has it, with
+    /// `new`.
+    Text { old: Option<&'a str>, new: &'a str },
+    /// Saves the page as it is, so that everything its templates pull in is
+    /// brought up to date.
+    Null,
+}
+
+/// Goes through what every edit by `task` to the wiki at `api_url` has to
+/// before it is saved, and returns the summary to save it with, or `None` if
+/// it isn't to be saved.
+///
+/// Titles on the [`skip_list`], or where an edit of the bot was [`reverts`]ed,
+/// aren't saved. Tasks that save by page ID check both themselves. In a
+/// `--list-only` run, nothing is saved and the page goes on the [`worklist`]
+/// instead, and in a `--dry-run` run, it is shown as a [diff](dry_run::show);
+/// edits that replace the text with the same text are left out of both, and
+/// null edits of the worklist. In a `--trial` run, edits that change the page
+/// count towards the task's [`trial`]. Nothing is saved outside of the wiki's
+/// edit hours, once the bot is [shut off](shutoff), or while it has unread
+/// [messages](talk_page), and each edit waits for a [turn](throttle::permit)
+/// on the wiki.
+pub async fn pre_save(
+    client: &wiki::Bot,
+    api_url: &str,
+    page: &PageSpec,
+    change: Change<'_>,
+    summary: String,
+    task: &TaskFlags,
+) -> Result<Option<String>> {
+    if let PageSpec::Title(title) = page {
+        if skip_list::skips(task.task, title) {
+            info!("not saving [[{title}]], which is on the skip list");
+            runs::skipped("skip list");
+            return Ok(None);
+        }
+        if reverts::reverted(title) {
+            info!("not saving [[{title}]], where an edit of the bot was reverted");
+            runs::skipped("reverted");
+            return Ok(None);
+        }
+    }
+    let unchanged = matches!(change, Change::Text { old: Some(old), new } if old == new);
+    if unchanged && (worklist::enabled() || dry_run::enabled()) {
+        debug!("not making an edit that changes nothing");
+        return Ok(None);
+    }
+    if worklist::enabled() {
+        let Some(current) = timing::current_page() else {
+            debug!("not saving outside of a page in a list-only run");
+            return Ok(None);
+        };
+        let Change::Text { old, new } = change else {
+            debug!("not listing a null edit");
+            return Ok(None);
+        };
+        let title = match page {
+            PageSpec::Title(title) => title,
+            _ => &current,
+        };
+        worklist::add(title, old, new);
+        return Ok(None);
+    }
+    if dry_run::enabled() {
+        dry_run::show(client, api_url, page, change, &summary).await?;
+        return Ok(None);
+    }
+    throttle::edit_hours().await?;
+    talk_page::wait_for_read(client).await?;
+    shutoff::check(client, api_url).await?;
+    let summary = match change {
+        Change::Text { .. } if !unchanged => trial::summary(task.task, summary)?,
+        _ => summary,
+    };
+    throttle::permit(api_url).await?;
+    Ok(Some(summary))
+}
+
+/// Saves `text` to `page` with the flags configured for `task` once it is
+/// through [`pre_save`], waiting out rate limits and [replication
+/// lag](maxlag), and records the edit in the [`audit`] log. `old` is the
+/// wikitext `text` replaces, if the task has it.
+pub async fn save(
+    client: &wiki::Bot,
+    page: PageSpec,
+    old: Option<&str>,
+    text: impl Into<String>,
+    summary: impl Into<String>,
+    baserevid: Option<u32>,
+    task: &TaskFlags,
+) -> Result<()> {
+    let flags = task.get()?;
+    let (text, summary) = (text.into(), summary.into());
+    // saves made outside of a task's run, like reports, are all to enwiki
+    let api_url = throttle::api_url().unwrap_or_else(|| ENWIKI_API.to_owned());
+    let change = Change::Text { old, new: &text };
+    let Some(summary) = pre_save(client, &api_url, &page, change, summary, task).await? else {
+        return Ok(());
+    };
+    let (key, value) = match &page {
+        PageSpec::Title(title) => ("title", title.clone()),
+        PageSpec::PageId(id) => ("pageid", id.to_string()),
+    };
+    let base = baserevid.map(|rev| rev.to_string());
+    let mut form = vec![
+        (key, value.as_str()),
+        ("text", text.as_str()),
+        ("summary", summary.as_str()),
+    ];
+    if let Some(base) = &base {
+        form.push(("baserevid", base.as_str()));
+    }
+    if flags.minor {
+        form.push(("minor", "1"));
+    }
+    if flags.bot {
+        form.push(("bot", "1"));
+    }
+    let assertions = assertions(task, &api_url, flags.bot)?;
+    form.extend(assertions.iter().map(|(k, v)| (*k, v.as_str())));
+    let res = timing::phase(Phase::Save, post_edit(client, &api_url, &form)).await?;
+    let changes = old.map(|old| audit::hunks(old, &text));
+    saved(task, &res, baserevid, &summary, changes);
+    Ok(())
+}
+
+/// Counts an edit by `task` that went through, `res` being the API's response
+/// to it, and records it in the [`audit`] log, the task's [`trial`] and on the
+/// [`dashboard`].
+pub fn saved(
+    task: &TaskFlags,
+    res: &Value,
+    baserevid: Option<u32>,
+    summary: &str,
+    changes: Option<Vec<audit::Hunk>>,
+) {
+    health::edited();
+    EDITS.fetch_add(1, Ordering::Relaxed);
+    audit::record(task.task, res, baserevid, summary, changes);
+    trial::record(task.task, res);
+    dashboard::edited(task.task, res);
+}
+
+/// Parameters that have the API refuse an edit by `task` to the wiki at
+/// `api_url` unless it is made logged in, with the bot flag for `bot` edits,
+/// and as the account its credential is for, if that is known, so that a lost
+/// session or bot flag fails the run instead of making anonymous or unflagged
+/// edits. The credential is the one of the [`Client`](crate::client::Client)
+/// making the edit, or else the one for `task` in [`accounts`].
+pub fn assertions(
+    task: &TaskFlags,
+    api_url: &str,
+    bot: bool,
+) -> Result<Vec<(&'static str, String)>> {
+    let assert = if bot { "bot" } else { "user" };
+    let mut params = vec![("assert", assert.to_owned())];
+    let user = match session_user() {
+        Some(user) => user,
+        None => credentials::user_for(api_url, accounts::token_file(task)?)?,
+    };
+    if let Some(user) = user {
+        params.push(("assertuser", user));
+    }
+    Ok(params)
+}
+
+/// A change outside the parts of the page a task meant to touch, usually
+/// from Parsoid failing to serialize selectively.
+#[derive(Debug)]
+pub struct DirtyDiff {
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl fmt::Display for DirtyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.removed {
+            writeln!(f, "-{}", line.trim_end_matches('\n'))?;
+        }
+        for line in &self.added {
+            writeln!(f, "+{}", line.trim_end_matches('\n'))?;
+        }
+        Ok(())
+    }
+}
+
+/// The first changed hunk between `old` and `new` that `intended` doesn't
+/// match.
+///
+/// A hunk counts as intended if any line it removes matches, or, for pure
+/// insertions, if any line it adds does.
+pub fn find_dirty_diff(old: &str, new: &str, intended: &Regex) -> Result<Option<DirtyDiff>> {
+    let diff = TextDiff::from_lines(old, new);
+    for group in diff.grouped_ops(0) {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for op in group {
+            removed.extend_from_slice(&diff.old_slices()[op.old_range()]);
+            added.extend_from_slice(&diff.new_slices()[op.new_range()]);
+        }
+        let checked = if removed.is_empty() { &added } else { &removed };
+        let mut ok = false;
+        for line in checked {
+            if intended.is_match(line)? {
+                ok = true;
+                break;
+            }
+        }
+        if !ok {
+            return Ok(Some(DirtyDiff {
+                removed: removed.into_iter().map(str::to_owned).collect(),
+                added: added.into_iter().map(str::to_owned).collect(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Fails if `new` changes anything in `old` that `intended` doesn't match,
+/// recording the change in [`DIRTY_DIFF_LOG`] first.
+pub fn check_dirty_diff(title: &str, old: &str, new: &str, intended: &Regex) -> Result<()> {
+    let Some(dirty) = find_dirty_diff(old, new, intended)? else {
+        return Ok(());
+    };
+    warn!("unintended change on [[{title}]]:\n{dirty}");
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(DIRTY_DIFF_LOG)?;
+    writeln!(log, "== [[{title}]] ==\n{dirty}")?;
+    bail!("unintended change, see {DIRTY_DIFF_LOG}");
+}
+
+/// The server's diff from `baserevid` to `text`, after pre-save transform,
+/// as `-`, `+` and ` ` prefixed lines with `@@` line markers. Unlike a local
+/// diff, this shows what substitutions and signatures will actually save.
+pub async fn compare(
+    client: &wiki::Bot,
+    api_url: &str,
+    title: &str,
+    baserevid: u32,
+    text: &str,
+) -> Result<String> {
+    let baserevid = baserevid.to_string();
+    let res: Value = maxlag::api(client.client.post(api_url).form(&[
+        ("action", "compare"),
+        ("format", "json"),
+        ("formatversion", "2"),
+        ("fromrev", &baserevid),
+        ("totitle", title),
+        ("toslots", "main"),
+        ("totext-main", text),
+        ("tocontentmodel-main", "wikitext"),
+        ("topst", "1"),
+        ("prop", "diff"),
+    ]))
+    .await?;
+    if let Some(e) = res.get("error") {
+        bail!("api error: {e}");
+    }
+    let body = res["compare"]["body"].as_str().unwrap_or_default();
+    Ok(render_diff_table(body))
+}
+
+fn render_diff_table(body: &str) -> String {
+    let html = kuchiki::parse_html().one(format!("<table>{body}</table>"));
+    let mut out = Vec::new();
+    for row in html.select("tr").into_iter().flatten() {
+        let cell = |class: &str| {
+            row.as_node()
+                .select(&format!("td.{class}"))
+                .into_iter()
+                .flatten()
+                .map(|td| td.text_contents())
+                .collect::<Vec<_>>()
+        };
+        if let Some(line) = cell("diff-lineno").first() {
+            out.push(format!("@@ {} @@", line.trim()));
+            continue;
+        }
+        for line in cell("diff-deletedline") {
+            out.push(format!("-{line}"));
+        }
+        for line in cell("diff-addedline") {
+            out.push(format!("+{line}"));
+        }
+        // context lines appear on both sides
+        if let Some(line) = cell("diff-context").last() {
+            out.push(format!(" {line}"));
+        }
+    }
+    out.join("\n")
+}
+
+/// Purges `title` and its subject or talk page, updating the links tables so
+/// that categories populated by templates on one of them catch up.
+pub async fn purge_with_talk(client: &wiki::Bot, api_url: &str, title: &str) -> Result<()> {
+    let batches = query_raw(
+        client,
+        api_url,
+        &[
+            ("prop", "info"),
+            ("inprop", "talkid|subjectid"),
+            ("titles", title),
+        ],
+    )
+    .await?;
+    let ids: Vec<_> = batches
+        .iter()
+        .flat_map(|b| b["pages"].as_array().into_iter().flatten())
+        .flat_map(|p| [&p["pageid"], &p["talkid"], &p["subjectid"]])
+        .filter_map(Value::as_u64)
+        .map(|id| id.to_string())
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let res: Value = maxlag::api(client.client.post(api_url).form(&[
+        ("action", "purge"),
+        ("format", "json"),
+        ("formatversion", "2"),
+        ("forcelinkupdate", "1"),
+        ("pageids", &ids.join("|")),
+    ]))
+    .await?;
+    if let Some(e) = res.get("error") {
+        bail!("api error: {e}");
+    }
+    Ok(())
+}
+
+/// Runs after a successful edit to `title`. Purging is on when
+/// `PURGE_AFTER_EDIT` is set; a failed purge is only logged, since the edit
+/// itself went through.
+pub async fn after_edit(client: &wiki::Bot, api_url: &str, title: &str) {
+    if std::env::var_os("PURGE_AFTER_EDIT").is_none() {
+        return;
+    }
+    match purge_with_talk(client, api_url, title).await {
+        Ok(()) => debug!("purged [[{title}]]"),
+        Err(e) => warn!("failed to purge [[{title}]]: {e}"),
+    }
+}
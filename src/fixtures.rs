@@ -0,0 +1,78 @@
+//! Saves pages for tests to replay without the network, so that a reported
+//! bad edit can become a regression test.
+//!
+//! Each page gets a directory under [`DIR`], named after its title with
+//! underscores and percent-encoding, holding:
+//!
+//! - `page.json`: the title, page ID and revision ID
+//! - `wikitext.txt`: the wikitext of that revision
+//! - `parsoid.html`: Parsoid's HTML for that revision
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::accounts::bot_for;
+use crate::edit::{EditFlags, TaskFlags};
+use crate::{enwiki_parsoid, fetch_contents, Result, ENWIKI_API};
+
+/// Where fixtures go by default.
+pub const DIR: &str = "tests/fixtures";
+
+const FLAGS: TaskFlags = TaskFlags::new("fetch_fixtures", EditFlags::NONE);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FixtureMeta {
+    pub title: String,
+    pub pageid: u32,
+    pub revid: u32,
+}
+
+/// The directory of the fixture for `title` under `dir`.
+pub fn path(dir: &Path, title: &str) -> PathBuf {
+    dir.join(&*urlencoding::encode(&title.replace(' ', "_")))
+}
+
+/// Downloads the current revision of every title listed, one per line, in
+/// `titles_file` into `dir`. Pages that don't exist are reported and skipped.
+pub async fn fetch(titles_file: &str, dir: &Path) -> Result<()> {
+    let list = fs::read_to_string(titles_file).with_context(|| format!("reading {titles_file}"))?;
+    let titles: Vec<_> = list
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+    let parsoid = enwiki_parsoid()?;
+
+    let pages = fetch_contents(&client, ENWIKI_API, &titles).await?;
+    for title in &titles {
+        let normalized = title.replace('_', " ");
+        if !pages.iter().any(|p| p.title == normalized) {
+            warn!("[[{title}]] doesn't exist");
+        }
+    }
+    for page in pages {
+        let html = parsoid.get_revision(&page.title, page.revid as u64).await?;
+        let out = path(dir, &page.title);
+        fs::create_dir_all(&out).with_context(|| format!("creating {}", out.display()))?;
+        let meta = FixtureMeta {
+            title: page.title.clone(),
+            pageid: page.pageid,
+            revid: page.revid,
+        };
+        fs::write(out.join("page.json"), serde_json::to_string_pretty(&meta)?)?;
+        fs::write(out.join("wikitext.txt"), &page.text)?;
+        fs::write(out.join("parsoid.html"), html.html())?;
+        info!(
+            "saved [[{}]] at revision {} to {}",
+            page.title,
+            page.revid,
+            out.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Health checks for the daemon, so that Toolforge or Kubernetes can restart
+//! a bot that has stopped making progress.
+//!
+//! When `HEALTH_ADDR` is set, e.g. to `0.0.0.0:8000`, the daemon serves
+//!
+//! * `/readyz`, which succeeds once the schedule is loaded, and
+//! * `/healthz`, which fails while a task is running but the wikis' APIs
+//!   haven't answered for [`WEDGED_AFTER`].
+//!
+//! Both return the last successful API contact, the last edit and the status
+//! of each task as JSON.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::{LazyLock, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::Context;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::Result;
+
+/// How long a running task may go without hearing from an API.
+const WEDGED_AFTER: TimeDelta = TimeDelta::minutes(30);
+
+#[derive(Clone, Debug)]
+pub enum TaskStatus {
+    Waiting { next: DateTime<Utc> },
+    Running { since: DateTime<Utc> },
+    Failed { at: DateTime<Utc>, error: String },
+}
+
+impl TaskStatus {
+    pub fn to_json(&self) -> Value {
+        match self {
+            TaskStatus::Waiting { next } => json!({"state": "waiting", "next": next.to_rfc3339()}),
+            TaskStatus::Running { since } => {
+                json!({"state": "running", "since": since.to_rfc3339()})
+            }
+            TaskStatus::Failed { at, error } => {
+                json!({"state": "failed", "at": at.to_rfc3339(), "error": error})
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ready: bool,
+    last_api_contact: Option<DateTime<Utc>>,
+    last_edit: Option<DateTime<Utc>>,
+    tasks: BTreeMap<String, TaskStatus>,
+}
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(Default::default);
+
+pub fn set_ready() {
+    STATE.lock().unwrap().ready = true;
+}
+
+/// Records that a wiki's API answered.
+pub fn api_contact() {
+    STATE.lock().unwrap().last_api_contact = Some(Utc::now());
+}
+
+pub fn edited() {
+    let now = Utc::now();
+    let mut state = STATE.lock().unwrap();
+    state.last_edit = Some(now);
+    state.last_api_contact = Some(now);
+}
+
+pub fn set_task(task: &str, status: TaskStatus) {
+    STATE.lock().unwrap().tasks.insert(task.to_owned(), status);
+}
+
+/// The status of each scheduled task.
+pub fn tasks() -> BTreeMap<String, TaskStatus> {
+    STATE.lock().unwrap().tasks.clone()
+}
+
+/// Stops reporting on `task`, which is no longer scheduled.
+pub fn remove_task(task: &str) {
+    STATE.lock().unwrap().tasks.remove(task);
+}
+
+/// Whether things look fine, and the state as JSON.
+fn check(path: &str) -> Option<(bool, Value)> {
+    let state = STATE.lock().unwrap();
+    let ok = match path {
+        "/readyz" => state.ready,
+        "/healthz" => {
+            let running = state
+                .tasks
+                .values()
+                .filter_map(|s| match s {
+                    TaskStatus::Running { since } => Some(*since),
+                    _ => None,
+                })
+                .min();
+            // a task that just started may not have made a request yet
+            let heard = state.last_api_contact.max(running);
+            running.is_none() || heard.is_some_and(|t| Utc::now() - t < WEDGED_AFTER)
+        }
+        _ => return None,
+    };
+    let time = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339());
+    let tasks: serde_json::Map<_, _> = state
+        .tasks
+        .iter()
+        .map(|(task, status)| (task.clone(), status.to_json()))
+        .collect();
+    Some((
+        ok,
+        json!({
+            "ok": ok,
+            "last_api_contact": time(state.last_api_contact),
+            "last_edit": time(state.last_edit),
+            "tasks": tasks,
+        }),
+    ))
+}
+
+async fn respond(stream: TcpStream) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let path = line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match check(path) {
+        Some((true, body)) => ("200 OK", body.to_string()),
+        Some((false, body)) => ("503 Service Unavailable", body.to_string()),
+        None => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\ncontent-type: application/json\r\n\
+        content-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Serves the health checks on `HEALTH_ADDR`, if it is set.
+pub async fn serve() -> Result<()> {
+    let Ok(addr) = env::var("HEALTH_ADDR") else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("binding {addr}"))?;
+    info!("serving health checks on {addr}");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                warn!("failed to answer health check: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,245 @@
+//! Upgrades `http://` external links to `https://` for domains on an
+//! allowlist.
+//!
+//! The allowlist file has one domain per line; blank lines and lines starting
+//! with `#` are ignored. Each domain is checked live before any edits: its
+//! plain HTTP front page has to end up, after following redirects, on HTTPS
+//! on the same domain. Domains failing the check are skipped.
+
+use std::sync::LazyLock;
+
+use color_eyre::eyre::bail;
+use fancy_regex::Regex;
+use parsoid::{Wikicode, WikinodeIterator};
+use tracing::{debug, info, warn};
+use url::Url;
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{check_dirty_diff, save, EditFlags, TaskFlags};
+use crate::large_pages::{is_large, log_skipped, Strategy};
+use crate::links::{bare_links, linking_pages, on_domain, replace_urls, url_pattern};
+use crate::{
+    check_nobots, check_nobots_wikitext, enwiki_parsoid, fetch_contents, fetch_latest, policy,
+    run_lock, selection, PageContent, Parsoid, Result, CONTENT_BATCH, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("https_upgrade", EditFlags::MINOR_BOT);
+
+/// Lines the task may change.
+static HTTP: LazyLock<Regex> = LazyLock::new(|| Regex::new("http://").unwrap());
+
+pub struct Domain {
+    pub name: String,
+    link: Regex,
+}
+
+pub fn load_allowlist(path: &str) -> Result<Vec<String>> {
+    let domains: Vec<_> = checkpoint::read_required(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.trim_start_matches("www.").to_lowercase())
+        .collect();
+    if domains.is_empty() {
+        bail!("{path} lists no domains");
+    }
+    Ok(domains)
+}
+
+/// Whether `domain` reliably serves HTTPS.
+pub async fn verify(http: &reqwest::Client, domain: &str) -> Result<bool> {
+    let resp = http.get(format!("http://{domain}/")).send().await?;
+    let url = resp.url();
+    debug!(?url);
+    Ok(resp.status().is_success()
+        && url.scheme() == "https"
+        && url.host_str().is_some_and(|h| on_domain(h, domain)))
+}
+
+fn upgrade(s: &str) -> Result<Option<String>> {
+    let mut url = Url::parse(s)?;
+    if url.scheme() != "http" || url.set_scheme("https").is_err() {
+        return Ok(None);
+    }
+    Ok(Some(url.into()))
+}
+
+/// The current wikitext of `page` as Parsoid round-trips it, or `None` if
+/// the page opts out.
+pub async fn fetch(parsoid: &Parsoid, page: &PageContent) -> Result<Option<Wikicode>> {
+    let code = parsoid
+        .get_revision(&page.title, page.revid as u64)
+        .await?
+        .into_mutable();
+    if code.filter_templates()?.iter().any(check_nobots) {
+        return Ok(None);
+    }
+    Ok(Some(code))
+}
+
+/// The wikitext of `page` to upgrade links in: as Parsoid round-trips it, or
+/// as is for a large page. `None` if the page opts out or is skipped.
+async fn text_of(parsoid: &Parsoid, page: &PageContent) -> Result<Option<String>> {
+    if is_large(&page.text) {
+        let skipped = Strategy::from_env() == Strategy::Skip || check_nobots_wikitext(&page.text);
+        return Ok((!skipped).then(|| page.text.clone()));
+    }
+    let Some(code) = fetch(parsoid, page).await? else {
+        return Ok(None);
+    };
+    Ok(Some(parsoid.transform_to_wikitext(&code).await?))
+}
+
+/// Upgrades links in `text`, the round-tripped wikitext of `page`. Returns
+/// the number of links upgraded.
+pub async fn treat(
+    client: &wiki::Bot,
+    domains: &[Domain],
+    page: &PageContent,
+    mut text: String,
+) -> Result<usize> {
+    let title = &page.title;
+    let mut upgraded = 0;
+    for domain in domains {
+        let (newtext, n) = replace_urls(&text, &domain.link, upgrade)?;
+        text = newtext;
+        upgraded += n;
+    }
+    if upgraded == 0 {
+        return Ok(0);
+    }
+    check_dirty_diff(title, &page.text, &text, &HTTP)?;
+
+    let plural = if upgraded == 1 { "" } else { "s" };
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        Some(&page.text),
+        text,
+        format!("Upgrading {upgraded} external link{plural} to HTTPS"),
+        Some(page.revid),
+        &FLAGS,
+    )
+    .await?;
+    Ok(upgraded)
+}
+
+pub async fn main(allowlist: &str) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+        let http = crate::http()?.external.clone();
+
+        let mut domains = Vec::new();
+        for name in load_allowlist(allowlist)? {
+            match verify(&http, &name).await {
+                Ok(true) => domains.push(Domain {
+                    link: bare_links(&url_pattern(&name, "http"))?,
+                    name,
+                }),
+                Ok(false) => warn!("{name} doesn't redirect to HTTPS, skipping"),
+                Err(e) => warn!("failed to verify {name}, skipping: {e}"),
+            }
+        }
+        info!("{} domains verified", domains.len());
+
+        let linking = async {
+            let mut pages = Vec::new();
+            for domain in &domains {
+                pages.extend(linking_pages(&client, &domain.name, &["http"]).await?);
+            }
+            pages.sort();
+            pages.dedup();
+            info!("{} pages have HTTP links to the domains", pages.len());
+            Ok(pages)
+        };
+        let pages = scope.titles(linking).await?;
+
+        let mut total = 0;
+        for chunk in pages.chunks(CONTENT_BATCH) {
+            let chunk: Vec<_> = chunk.iter().map(String::as_str).collect();
+            let contents = match fetch_contents(&client, ENWIKI_API, &chunk).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warn!("failed to fetch batch starting at [[{}]]: {e}", chunk[0]);
+                    continue;
+                }
+            };
+            let mut pages = Vec::new();
+            let mut codes = Vec::new();
+            // edited from their wikitext as is
+            let mut large = Vec::new();
+            // treated through the policy, which decides whether to retry
+            let (mut unfetched, mut errors) = (Vec::new(), Vec::new());
+            for page in contents {
+                let title = &page.title;
+                let linked = domains
+                    .iter()
+                    .any(|d| d.link.is_match(&page.text).unwrap_or(true));
+                if !linked {
+                    debug!("no HTTP links in the wikitext of [[{title}]]");
+                    continue;
+                }
+                if is_large(&page.text) {
+                    if Strategy::from_env() == Strategy::Skip {
+                        if let Err(e) = log_skipped(title, &page.text) {
+                            warn!("failed to log [[{title}]]: {e}");
+                        }
+                    } else if !check_nobots_wikitext(&page.text) {
+                        large.push(page);
+                    }
+                    continue;
+                }
+                match fetch(&parsoid, &page).await {
+                    Ok(Some(code)) => {
+                        pages.push(page);
+                        codes.push(code);
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        unfetched.push(page);
+                        errors.push(e);
+                    }
+                }
+            }
+            let texts = parsoid.transform_all(&codes).await;
+            let large = large.iter().map(|page| (page, Ok(page.text.clone())));
+            let unfetched = unfetched.iter().zip(errors.into_iter().map(Err));
+            for (page, text) in pages.iter().zip(texts).chain(large).chain(unfetched) {
+                let title = &page.title;
+                let mut prefetched = Some((page, text));
+                let treated = || {
+                    let prefetched = prefetched.take();
+                    let (client, parsoid, domains) = (&client, &parsoid, &domains);
+                    async move {
+                        let latest;
+                        let (page, text) = match prefetched {
+                            Some((page, text)) => (page, text?),
+                            // treating it again, the prefetched revision may be stale
+                            None => {
+                                latest = fetch_latest(client, ENWIKI_API, title).await?;
+                                let Some(text) = text_of(parsoid, &latest).await? else {
+                                    return Ok(0);
+                                };
+                                (&latest, text)
+                            }
+                        };
+                        treat(client, domains, page, text).await
+                    }
+                };
+                match policy::page(FLAGS.task, title, treated).await? {
+                    Some(0) => continue,
+                    Some(n) => total += n,
+                    None => {}
+                }
+            }
+        }
+        info!("upgraded {total} links");
+
+        Ok(())
+    })
+    .await
+}
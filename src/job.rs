@@ -0,0 +1,101 @@
+//! Running as a batch job, under `toolforge jobs` or a Kubernetes CronJob,
+//! where nobody reads colors or answers prompts and the exit code is what
+//! gets looked at.
+//!
+//! Job mode is on with `--job`, and on its own on Toolforge. Logs are then
+//! plain, prompts fail instead of waiting, and the run exits with one of the
+//! codes below, so that a scheduler can tell a lost login from a bad page.
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process;
+use std::sync::OnceLock;
+
+use color_eyre::Report;
+use tracing::error;
+
+pub const SUCCESS: i32 = 0;
+/// The run ended with an error other than the ones below.
+pub const FAILED: i32 = 1;
+/// The bot couldn't log in, or its login stopped working.
+pub const AUTH: i32 = 2;
+/// The run was stopped on request, e.g. from the wiki.
+pub const SHUT_OFF: i32 = 3;
+/// The run went through, but some pages failed.
+pub const PARTIAL: i32 = 4;
+
+/// Errors from MediaWiki and from reading tokens that mean the login is
+/// missing or broken.
+const AUTH_ERRORS: &[&str] = &[
+    "mwoauth-invalid-authorization",
+    "assertuserfailed",
+    "assertbotfailed",
+    "notloggedin",
+    "badtoken",
+    "no secret",
+    "oauth2 token",
+];
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// An error for runs stopped on request rather than failing.
+#[derive(Debug)]
+pub struct ShutOff(pub String);
+
+impl fmt::Display for ShutOff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shut off: {}", self.0)
+    }
+}
+
+impl std::error::Error for ShutOff {}
+
+/// Whether this is a Toolforge tool's container or bastion.
+fn on_toolforge() -> bool {
+    env::var_os("TOOL_DATA_DIR").is_some() || Path::new("/etc/wmcs-project").exists()
+}
+
+/// Reads `--job` out of a command line, or detects Toolforge. Only the first
+/// call has an effect.
+pub fn from_args(args: &[String]) {
+    let _ = ENABLED.set(args.iter().any(|a| a == "--job") || on_toolforge());
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+fn is_auth(e: &Report) -> bool {
+    e.chain().any(|cause| {
+        let status = cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status);
+        if status.is_some_and(|s| s.as_u16() == 401 || s.as_u16() == 403) {
+            return true;
+        }
+        let msg = cause.to_string();
+        AUTH_ERRORS.iter().any(|code| msg.contains(code))
+    })
+}
+
+/// The exit code of a run that ended with `res`, after `failed` pages
+/// failed.
+pub fn exit_code(res: &color_eyre::Result<()>, failed: u64) -> i32 {
+    match res {
+        Ok(()) if failed > 0 => PARTIAL,
+        Ok(()) => SUCCESS,
+        Err(e) if e.chain().any(|c| c.is::<ShutOff>()) => SHUT_OFF,
+        Err(e) if is_auth(e) => AUTH,
+        Err(_) => FAILED,
+    }
+}
+
+/// Ends a job that ended with `res`, after `failed` pages failed.
+pub fn exit(res: color_eyre::Result<()>, failed: u64) -> ! {
+    let code = exit_code(&res, failed);
+    if let Err(e) = &res {
+        error!("{e:#}");
+    }
+    process::exit(code)
+}
@@ -0,0 +1,51 @@
+//! Pages too big for Parsoid.
+//!
+//! Parsoid gives up on wikitext over a megabyte, often only after timing out.
+//! Tasks check sizes before asking it, and then either skip the page, listing
+//! it in [`LARGE_PAGE_LOG`] to be done by hand, or edit the wikitext directly
+//! with only their regexes, as chosen with `LARGE_PAGES`.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use tracing::warn;
+
+use crate::{runs, Result};
+
+/// Parsoid's limit on the wikitext it converts to HTML, in bytes.
+pub const MAX_PARSOID_BYTES: usize = 1_000_000;
+
+const LARGE_PAGE_LOG: &str = "large-pages.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Skip,
+    Wikitext,
+}
+
+impl Strategy {
+    /// From `LARGE_PAGES`: `skip`, the default, or `wikitext`.
+    pub fn from_env() -> Strategy {
+        match std::env::var("LARGE_PAGES").as_deref() {
+            Ok("wikitext") => Strategy::Wikitext,
+            _ => Strategy::Skip,
+        }
+    }
+}
+
+pub fn is_large(text: &str) -> bool {
+    text.len() > MAX_PARSOID_BYTES
+}
+
+/// Records that `title` was skipped for its size.
+pub fn log_skipped(title: &str, text: &str) -> Result<()> {
+    let size = text.len();
+    warn!("skipping [[{title}]], {size} bytes is too big for Parsoid");
+    runs::skipped("too large");
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LARGE_PAGE_LOG)?;
+    writeln!(log, "[[{title}]] ({size} bytes)")?;
+    Ok(())
+}
@@ -1,109 +1,631 @@
-use std::{env, fs};
+use std::env;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
 
-use color_eyre::eyre::Context;
-use futures_util::{Future, Stream, TryStreamExt};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{bail, eyre, ContextCompat};
+use fancy_regex::Regex;
+use futures_util::{stream, Future, Stream, TryStreamExt};
 use parsoid::Template;
+use proxy::Upstream;
+use reqwest::redirect::Policy;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use wiki::req::search::SearchGenerator;
-use wiki::req::{self, Query, QueryGenerator};
+use serde_json::Value;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use wiki::ClientBuilder;
 
-const UA: &str = concat!(
-    "DeadbeefBot/",
-    env!("CARGO_PKG_VERSION"),
-    " (https://github.com/fee1-dead/deadbeefbot; ent3rm4n@gmail.com) mwapi/0.4.3 parsoid/0.7.4"
-);
+/// How to reach the operator, which the Wikimedia User-Agent policy asks for.
+/// Deployments run by someone else set their own in `BOT_CONTACT`.
+const CONTACT: &str = "https://github.com/fee1-dead/deadbeefbot; ent3rm4n@gmail.com";
 
+/// The User-Agent of every request: the bot's name from `BOT_NAME` and its
+/// contact, ending with the task being run.
+static UA: LazyLock<String> = LazyLock::new(|| {
+    let name = env::var("BOT_NAME").unwrap_or_else(|_| "DeadbeefBot".to_owned());
+    let contact = env::var("BOT_CONTACT").unwrap_or_else(|_| CONTACT.to_owned());
+    format!(
+        "{name}/{} ({contact}) mwapi/0.4.3 parsoid/0.7.4 ({})",
+        env!("CARGO_PKG_VERSION"),
+        run_name()
+    )
+});
+
+pub mod accounts;
 pub mod articlehistory;
+pub mod audit;
+pub mod bare_refs;
+pub mod category_redirects;
+pub mod check;
+pub mod checkpoint;
+pub mod client;
+pub mod config;
+pub mod conv;
+pub mod credentials;
+pub mod dab_redirects;
+pub mod daemon;
+pub mod dashboard;
+pub mod deletion_alerts;
+pub mod diff;
+pub mod domain_migration;
+pub mod dry_run;
+pub mod dump;
+pub mod edit;
+pub mod email;
+pub mod fixtures;
+pub mod health;
+pub mod https_upgrade;
+pub mod job;
+pub mod large_pages;
+pub mod links;
+pub mod maxlag;
+pub mod non_free;
+pub mod notify;
+pub mod orphaned_talk;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod param_migration;
+pub mod parsoid_backend;
+pub mod peer_review;
+pub mod policy;
+pub mod proxy;
+pub mod ratelimit;
 pub mod remove_twitter_trackers;
+pub mod reverts;
+pub mod run_lock;
+pub mod runs;
+pub mod secrets;
+pub mod selection;
+pub mod short_description;
+pub mod shutoff;
+pub mod siteinfo;
+pub mod sitelinks;
+pub mod skip_list;
+pub mod source;
+pub mod state;
+pub mod status_page;
+pub mod talk_page;
+pub mod tasks;
+pub mod throttle;
+pub mod timing;
+pub mod trial;
+pub mod typos;
+pub mod webhook;
+pub mod wikidata;
+pub mod wikiproject_tagging;
+pub mod wikitext;
+pub mod worklist;
+
+pub use parsoid_backend::{Parsoid, ParsoidBackend};
 
 pub type Result<T, E = color_eyre::Report> = std::result::Result<T, E>;
 
-#[derive(Deserialize, Debug)]
-struct Revision {
-    revid: u32,
+/// Timeout for sites other than the wikis, which can hang.
+const EXTERNAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Timeout for archive.org, which is asked about links one at a time.
+const ARCHIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// HTTP clients shared by everything in a run, so that connections are pooled
+/// and every request carries [`UA`].
+pub struct HttpClients {
+    /// For Wikimedia services other than the wikis' APIs: Wikidata, PetScan
+    /// and Quarry.
+    pub wikimedia: reqwest::Client,
+    /// For Parsoid, through the proxy in `PARSOID_PROXY`.
+    pub parsoid: reqwest::Client,
+    /// For archive.org, through the proxy in `ARCHIVE_PROXY`. Redirects are
+    /// not followed, since snapshot lookups look at them.
+    pub archive: reqwest::Client,
+    /// For arbitrary external sites.
+    pub external: reqwest::Client,
+}
+
+impl HttpClients {
+    fn new() -> Result<HttpClients> {
+        let builder = || {
+            reqwest::Client::builder()
+                .user_agent(UA.as_str())
+                .use_rustls_tls()
+        };
+        Ok(HttpClients {
+            wikimedia: builder().build()?,
+            parsoid: proxy::configure(builder(), Upstream::Parsoid)?.build()?,
+            archive: proxy::configure(builder(), Upstream::Archive)?
+                .redirect(Policy::none())
+                .timeout(ARCHIVE_TIMEOUT)
+                .build()?,
+            external: builder().timeout(EXTERNAL_TIMEOUT).build()?,
+        })
+    }
+}
+
+static HTTP: LazyLock<Result<HttpClients, String>> =
+    LazyLock::new(|| HttpClients::new().map_err(|e| format!("building HTTP clients: {e}")));
+
+/// The shared HTTP clients.
+pub fn http() -> Result<&'static HttpClients> {
+    HTTP.as_ref().map_err(|e| eyre!("{e}"))
 }
+
 #[derive(Deserialize, Debug)]
-struct SearchResult {
-    pageid: u32,
-    title: String,
-    revisions: Vec<Revision>,
+pub struct Revision {
+    pub revid: u32,
 }
+
+/// A page from a generator, with whatever revisions were requested.
 #[derive(Deserialize, Debug)]
-struct SearchResponseBody {
-    pages: Vec<SearchResult>,
+pub struct Page {
+    pub pageid: u32,
+    pub title: String,
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
 }
 
-pub fn search_with_rev_ids<T: DeserializeOwned>(
+/// Runs an `action=query` request with raw parameters, following continuation
+/// as batches are read, and deserializes the `query` object of every batch.
+/// Batches without one are skipped.
+pub fn query_typed<'a, T: DeserializeOwned>(
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    params: Vec<(&'static str, String)>,
+) -> impl Stream<Item = Result<T>> + 'a {
+    stream::try_unfold(Some(Continue::new()), move |cont| {
+        let params = params.clone();
+        async move {
+            let Some(cont) = cont else {
+                return Ok(None);
+            };
+            let params: Vec<_> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            let (batch, next) = query_batch(client, api_url, &params, &cont).await?;
+            Ok(Some((batch, next)))
+        }
+    })
+    .try_filter_map(|batch| async move {
+        Ok(match batch {
+            Value::Null => None,
+            batch => Some(serde_json::from_value(batch)?),
+        })
+    })
+}
+
+#[derive(Deserialize)]
+struct Pages<T> {
+    #[serde(default)]
+    pages: Vec<T>,
+}
+
+/// Pages found by searching for `search` in `namespace`, `limit` at a time,
+/// each with the ID of its latest revision.
+pub fn search_with_rev_ids<'a>(
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    search: &str,
+    namespace: &str,
+    limit: u32,
+) -> impl Stream<Item = Result<Page>> + 'a {
+    let params = vec![
+        ("generator", "search".to_owned()),
+        ("gsrsearch", search.to_owned()),
+        ("gsrnamespace", namespace.to_owned()),
+        ("gsrlimit", limit.to_string()),
+        ("prop", "revisions".to_owned()),
+        ("rvprop", "ids".to_owned()),
+        ("rvslots", "main".to_owned()),
+    ];
+    query_typed::<Pages<Page>>(client, api_url, params)
+        .map_ok(|b| stream::iter(b.pages.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// Titles of all pages matching `search` in `namespace`.
+pub async fn search_titles(
+    client: &wiki::Bot,
+    api_url: &str,
+    search: &str,
+    namespace: &str,
+) -> Result<Vec<String>> {
+    let batches = query_raw(
+        client,
+        api_url,
+        &[
+            ("list", "search"),
+            ("srsearch", search),
+            ("srnamespace", namespace),
+            ("srlimit", "max"),
+            ("srprop", ""),
+        ],
+    )
+    .await?;
+    Ok(batches
+        .iter()
+        .flat_map(|b| b["search"].as_array().into_iter().flatten())
+        .filter_map(|p| Some(p["title"].as_str()?.to_owned()))
+        .collect())
+}
+
+pub const ENWIKI_API: &str = "https://en.wikipedia.org/w/api.php";
+
+pub async fn enwiki_bot() -> Result<wiki::Bot> {
+    site_from_url(ENWIKI_API).await
+}
+
+/// Continuation parameters for the next batch of a query.
+pub type Continue = Vec<(String, String)>;
+
+/// Runs one batch of an `action=query` request with raw parameters.
+///
+/// Returns the `query` object and, if there is more, the parameters to pass as
+/// `cont` for the next batch.
+pub async fn query_batch(
     client: &wiki::Bot,
-    gen: SearchGenerator,
-) -> impl Stream<Item = Result<T>> {
-    let pages = client.query_all(Query {
-        prop: Some(
-            req::QueryProp::Revisions(req::QueryPropRevisions {
-                prop: req::RvProp::IDS,
-                slots: req::RvSlot::Main.into(),
-                limit: req::Limit::None,
-            })
-            .into(),
+    api_url: &str,
+    params: &[(&str, &str)],
+    cont: &[(String, String)],
+) -> Result<(Value, Option<Continue>)> {
+    let mut res: Value = maxlag::api(
+        client
+            .client
+            .get(api_url)
+            .query(&[
+                ("action", "query"),
+                ("format", "json"),
+                ("formatversion", "2"),
+            ])
+            .query(params)
+            .query(cont),
+    )
+    .await?;
+    if let Some(e) = res.get("error") {
+        bail!("api error: {e}");
+    }
+    let next = match res["continue"].take() {
+        Value::Object(next) => Some(
+            next.into_iter()
+                .filter_map(|(k, v)| Some((k, v.as_str()?.to_owned())))
+                .collect(),
         ),
-        generator: Some(QueryGenerator::Search(gen)),
-        ..Default::default()
-    });
+        _ => None,
+    };
+    Ok((res["query"].take(), next))
+}
 
-    pages
-        .map_err(Into::into)
-        .and_then(|x| async { Ok(serde_json::from_value(x)?) })
+/// Runs an `action=query` request with raw parameters, following continuation.
+///
+/// Returns the `query` object of every batch. This is for lists that the typed
+/// requests in `wiki` don't cover.
+pub async fn query_raw(
+    client: &wiki::Bot,
+    api_url: &str,
+    params: &[(&str, &str)],
+) -> Result<Vec<Value>> {
+    let mut cont = Vec::new();
+    let mut batches = Vec::new();
+    loop {
+        let (batch, next) = query_batch(client, api_url, params, &cont).await?;
+        batches.push(batch);
+        match next {
+            Some(next) => cont = next,
+            None => break,
+        }
+    }
+    Ok(batches)
 }
 
-pub async fn enwiki_bot() -> Result<wiki::Bot> {
-    site_from_url("https://en.wikipedia.org/w/api.php").await
+/// The wikitext of a page's latest revision.
+#[derive(Debug)]
+pub struct PageContent {
+    pub pageid: u32,
+    pub title: String,
+    pub revid: u32,
+    pub text: String,
 }
 
-fn oauth_token() -> Result<String> {
-    if let Ok(token) = env::var("BOT_TOKEN") {
-        return Ok(token);
+/// How many titles [`fetch_contents`] sends per request.
+pub const CONTENT_BATCH: usize = 50;
+
+/// Fetches the latest wikitext of `titles` with one query per
+/// [`CONTENT_BATCH`] titles, so that tasks can cheaply skip pages before
+/// asking parsoid for them. Missing pages are left out.
+pub async fn fetch_contents(
+    client: &wiki::Bot,
+    api_url: &str,
+    titles: &[&str],
+) -> Result<Vec<PageContent>> {
+    let mut contents = Vec::new();
+    for chunk in titles.chunks(CONTENT_BATCH) {
+        let titles = chunk.join("|");
+        let query = query_raw(
+            client,
+            api_url,
+            &[
+                ("prop", "revisions"),
+                ("titles", &titles),
+                ("rvprop", "ids|content"),
+                ("rvslots", "main"),
+            ],
+        );
+        let batches = timing::phase(timing::Phase::Fetch, query).await?;
+        // pages whose content didn't fit come back again in a later batch
+        contents.extend(
+            batches
+                .iter()
+                .flat_map(|b| b["pages"].as_array().into_iter().flatten())
+                .filter_map(|p| {
+                    let rev = &p["revisions"][0];
+                    Some(PageContent {
+                        pageid: p["pageid"].as_u64()? as u32,
+                        title: p["title"].as_str()?.to_owned(),
+                        revid: rev["revid"].as_u64()? as u32,
+                        text: rev["slots"]["main"]["content"].as_str()?.to_owned(),
+                    })
+                }),
+        );
     }
+    Ok(contents)
+}
+
+/// The latest wikitext of `title`, for treating a page again once the
+/// revision [`fetch_contents`] got has been edited since.
+pub async fn fetch_latest(client: &wiki::Bot, api_url: &str, title: &str) -> Result<PageContent> {
+    fetch_contents(client, api_url, &[title])
+        .await?
+        .pop()
+        .with_context(|| format!("[[{title}]] doesn't exist"))
+}
+
+/// The latest wikitext of `title`. Unlike `wiki`'s own, this waits out
+/// [replication lag](maxlag). Fails if the page doesn't exist.
+pub async fn fetch_content(client: &wiki::Bot, api_url: &str, title: &str) -> Result<String> {
+    Ok(fetch_latest(client, api_url, title).await?.text)
+}
 
-    Ok(fs::read_to_string("./token.secret").context("please put oauth2 token in token.secret")?)
+/// Time of the most recent edit to `title`.
+pub async fn last_edited(client: &wiki::Bot, api_url: &str, title: &str) -> Result<DateTime<Utc>> {
+    let batches = query_raw(
+        client,
+        api_url,
+        &[
+            ("prop", "revisions"),
+            ("titles", title),
+            ("rvprop", "timestamp"),
+            ("rvlimit", "1"),
+        ],
+    )
+    .await?;
+    let timestamp = batches
+        .first()
+        .and_then(|b| b["pages"][0]["revisions"][0]["timestamp"].as_str())
+        .with_context(|| format!("[[{title}]] has no revisions"))?;
+    Ok(DateTime::parse_from_rfc3339(timestamp)?.with_timezone(&Utc))
+}
+
+/// A regex pattern matching `title` the way MediaWiki would: the first letter
+/// in either case, and spaces and underscores interchangeably.
+pub fn title_pattern(title: &str) -> String {
+    let mut chars = title.chars();
+    let first = chars.next().unwrap_or_default();
+    let rest = fancy_regex::escape(chars.as_str()).replace(' ', "[ _]+");
+    let first = if first.is_alphabetic() {
+        format!("[{}{}]", first.to_uppercase(), first.to_lowercase())
+    } else {
+        fancy_regex::escape(&first.to_string()).into_owned()
+    };
+    format!("{first}{rest}")
 }
 
 pub async fn site_from_url(url: &str) -> Result<wiki::Bot> {
-    Ok(ClientBuilder::new(url)
-        .oauth(oauth_token()?.trim())
-        .user_agent(UA)
+    site_with_token(url, None).await
+}
+
+/// Like [`site_from_url`], preferring the token in `token_file` if it exists.
+pub async fn site_with_token(url: &str, token_file: Option<&str>) -> Result<wiki::Bot> {
+    let credential = credentials::for_wiki(url, token_file)?;
+    Ok(credential
+        .login(ClientBuilder::new(url))
+        .user_agent(UA.as_str())
         .build()
         .await?)
 }
 
-pub fn enwiki_parsoid() -> Result<parsoid::Client> {
-    parsoid_from_url("https://en.wikipedia.org/api/rest_v1")
+pub fn enwiki_parsoid() -> Result<Parsoid> {
+    Parsoid::new(ENWIKI_API, ParsoidBackend::RestBase)
 }
 
 pub fn parsoid_from_url(url: &str) -> Result<parsoid::Client> {
-    Ok(parsoid::Client::new(url, UA)?)
+    Ok(parsoid::Client::new(url, &UA)?)
+}
+
+/// The template's name without the namespace, lowercased for comparisons.
+pub fn template_name(t: &Template) -> String {
+    t.name()
+        .trim_start_matches("Template:")
+        .replace('_', " ")
+        .to_ascii_lowercase()
 }
 
 pub fn check_nobots(t: &Template) -> bool {
     let name = t.name().to_ascii_lowercase();
-    name == "template:nobots"
+    let denied = name == "template:nobots"
         || (name == "template:bots"
             && (t.param("allow").as_deref() == Some("none")
                 || t.param("deny").as_deref() == Some("all")
                 || t.param("optout").as_deref() == Some("all")
-                || t.param("deny").map_or(false, |x| x.contains("DeadbeefBot"))))
+                || t.param("deny").map_or(false, |x| x.contains("DeadbeefBot"))));
+    if denied {
+        runs::skipped("nobots");
+    }
+    denied
+}
+
+static NOBOTS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\{\{\s*(?:nobots\s*\}\}|bots\s*\|[^}]*(?:allow\s*=\s*none|(?:deny|optout)\s*=\s*all|deny\s*=[^}]*DeadbeefBot))",
+    )
+    .unwrap()
+});
+
+/// Like [`check_nobots`], for tasks that work on raw wikitext.
+pub fn check_nobots_wikitext(text: &str) -> bool {
+    let denied = NOBOTS.is_match(text).unwrap_or(true);
+    if denied {
+        runs::skipped("nobots");
+    }
+    denied
+}
+
+/// Log filters for `-q`, no flag, `-v` and `-vv`. Libraries stay quieter
+/// than the bot, since their debug logs are mostly about connections.
+const VERBOSITY: &[&str] = &[
+    "warn",
+    "warn,deadbeefbot=info",
+    "info,deadbeefbot=debug",
+    "debug,deadbeefbot=trace,hyper=info,hyper_util=info,h2=info,rustls=info",
+];
+
+/// The log filter for a command line: `RUST_LOG` as is, unless one of `-q`,
+/// `-v` or `-vv` is given, in which case it picks the filter from
+/// [`VERBOSITY`].
+pub fn log_filter(args: &[String]) -> tracing_subscriber::EnvFilter {
+    let mut verbosity = None;
+    for arg in args {
+        match arg.as_str() {
+            "-q" | "--quiet" => verbosity = Some(0),
+            "-v" | "--verbose" => verbosity = Some(verbosity.unwrap_or(1) + 1),
+            "-vv" => verbosity = Some(verbosity.unwrap_or(1) + 2),
+            _ => {}
+        }
+    }
+    match verbosity {
+        Some(v) => tracing_subscriber::EnvFilter::new(VERBOSITY[v.min(VERBOSITY.len() - 1)]),
+        None => tracing_subscriber::EnvFilter::from_default_env(),
+    }
+}
+
+/// The name of the program being run, which for the `taskN` binaries is the
+/// task.
+fn run_name() -> String {
+    env::args()
+        .next()
+        .and_then(|arg0| Some(Path::new(&arg0).file_name()?.to_str()?.to_owned()))
+        .unwrap_or_else(|| "deadbeefbot".to_owned())
+}
+
+/// The flags [`setup`] reads besides those of [`selection`], and whether each
+/// takes a value.
+pub const SETUP_FLAGS: &[(&str, bool)] = &[
+    ("--quiet", false),
+    ("--verbose", false),
+    ("--job", false),
+    ("--skip-file", true),
+    ("--list-only", false),
+    ("--trial", false),
+    ("--diff-format", true),
+    ("--dry-run", false),
+    ("--diff-file", true),
+    ("--token-file", true),
+];
+
+/// The arguments on the command line that aren't flags read by [`setup`] or
+/// their values, for tasks that take some.
+pub fn positional_args() -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        if selection::FLAGS.contains(&flag) || SETUP_FLAGS.contains(&(flag, true)) {
+            args.next();
+        } else if !flag.starts_with('-') {
+            positional.push(arg);
+        }
+    }
+    positional
 }
 
 pub fn setup<F: Future<Output = color_eyre::Result<()>>>(
     x: impl FnOnce() -> F,
+) -> color_eyre::Result<()> {
+    setup_with(&[], x)
+}
+
+/// Like [`setup`], for programs that take `flags` of their own.
+pub fn setup_with<F: Future<Output = color_eyre::Result<()>>>(
+    flags: &[&str],
+    x: impl FnOnce() -> F,
 ) -> color_eyre::Result<()> {
     color_eyre::install()?;
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-    tokio::runtime::Builder::new_multi_thread()
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
-        .build()?
-        .block_on(x())
+        .build()?;
+    let _guard = runtime.enter();
+    let args: Vec<_> = env::args().collect();
+    job::from_args(&args);
+    let registry = tracing_subscriber::registry()
+        .with(log_filter(&args))
+        .with(tracing_subscriber::fmt::layer().with_ansi(!job::enabled()))
+        .with(email::RecentLogs);
+    #[cfg(feature = "otel")]
+    let otel = otel::Otel::init()?;
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel.as_ref().map(|o| o.layer()));
+    registry.init();
+    let name = run_name();
+    email::alert_on_panic(name.clone());
+    // before anything reads its configuration
+    let resumed = match checkpoint::resuming() {
+        Some(id) => {
+            let progress = checkpoint::Progress::load(&id)?;
+            progress.restore_config();
+            Some((id, progress.done))
+        }
+        None => None,
+    };
+    let others: Vec<_> = SETUP_FLAGS
+        .iter()
+        .map(|&(f, _)| f)
+        .chain(flags.iter().copied())
+        .collect();
+    let mut selection = selection::Selection::from_args(&args, &others)?;
+    skip_list::from_args(&args)?;
+    worklist::from_args(&args);
+    trial::from_args(&args);
+    diff::from_args(&args)?;
+    dry_run::from_args(&args)?;
+    credentials::from_args(&args)?;
+    let (id, command) = match resumed {
+        Some((id, done)) => {
+            selection.skip_done(done);
+            (id, None)
+        }
+        None => (runs::run_id(&name, Utc::now()), Some(args)),
+    };
+    selection::set(selection);
+    let res = runtime.block_on(async {
+        let run = runs::recorded_as(id.clone(), &name, x());
+        let (report, res) = checkpoint::saved(id.clone(), command, run).await?;
+        webhook::report(&report).await;
+        status_page::update(&report, None).await;
+        Ok::<_, color_eyre::Report>((report.failed, res))
+    });
+    let (failed, res) = match res {
+        Ok((failed, res)) => (failed, res),
+        Err(e) => (0, Err(e)),
+    };
+    ratelimit::log_counts();
+    maxlag::log_count();
+    timing::log_slowest();
+    worklist::write(&id);
+    #[cfg(feature = "otel")]
+    if let Some(otel) = &otel {
+        otel.shutdown();
+    }
+    if let Err(e) = &res {
+        email::alert(&format!("{name} failed"), &format!("{e:?}"));
+    }
+    if job::enabled() {
+        job::exit(res, failed);
+    }
+    res
 }
@@ -0,0 +1,114 @@
+//! Utilities shared by tasks that rewrite external links.
+
+use std::time::Duration;
+
+use fancy_regex::Regex;
+use parsoid::Template;
+use tracing::debug;
+use url::Url;
+
+use crate::timing::{self, Phase};
+use crate::{query_raw, ratelimit, template_name, Result, ENWIKI_API};
+
+/// Citation templates whose `url` and `archive-url` we may touch.
+const CITATIONS: &[&str] = &[
+    "cite web",
+    "cite news",
+    "cite tweet",
+    "cite magazine",
+    "cite journal",
+    "cite book",
+    "cite press release",
+    "citation",
+];
+
+pub fn is_citation(t: &Template) -> bool {
+    CITATIONS.contains(&&*template_name(t))
+}
+
+/// A pattern for URLs on `domain` or its `www.` form, with a scheme matching
+/// `scheme`.
+pub fn url_pattern(domain: &str, scheme: &str) -> String {
+    format!(
+        r"{scheme}://(?:www\.)?{}(?=[/?#:\s\]<>|}}]|$)[^\s\]<>|}}]*",
+        fancy_regex::escape(domain)
+    )
+}
+
+/// Whether `host` is `domain` or its `www.` form.
+pub fn on_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_prefix("www.") == Some(domain)
+}
+
+/// Matches `pattern` where it isn't part of another URL, such as an archive
+/// link.
+pub fn bare_links(pattern: &str) -> Result<Regex> {
+    Ok(Regex::new(&format!(r"(?<!\?url=|/|cache:){pattern}"))?)
+}
+
+/// Articles linking to `domain` or its subdomains over any of `protocols`.
+pub async fn linking_pages(
+    client: &wiki::Bot,
+    domain: &str,
+    protocols: &[&str],
+) -> Result<Vec<String>> {
+    let query = format!("*.{domain}");
+    let mut pages = Vec::new();
+    for protocol in protocols {
+        let batches = query_raw(
+            client,
+            ENWIKI_API,
+            &[
+                ("list", "exturlusage"),
+                ("euquery", &query),
+                ("euprotocol", protocol),
+                ("eunamespace", "0"),
+                ("euprop", "title"),
+                ("eulimit", "max"),
+            ],
+        )
+        .await?;
+        pages.extend(
+            batches
+                .iter()
+                .flat_map(|b| b["exturlusage"].as_array().into_iter().flatten())
+                .filter_map(|p| Some(p["title"].as_str()?.to_owned())),
+        );
+    }
+    Ok(pages)
+}
+
+/// Replaces every match of `re` in `text` for which `f` returns a new URL.
+///
+/// Returns the new text and the number of replacements.
+pub fn replace_urls(
+    text: &str,
+    re: &Regex,
+    mut f: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<(String, usize)> {
+    let mut newtext = text.to_owned();
+    let mut count = 0;
+    let matches: Vec<_> = re.find_iter(text).collect();
+    for m in matches.into_iter().rev() {
+        let m = m?;
+        if let Some(new_url) = f(m.as_str())? {
+            newtext.replace_range(m.range(), &new_url);
+            count += 1;
+        }
+    }
+    Ok((newtext, count))
+}
+
+/// Timestamps of the Wayback Machine's snapshots of `url`, oldest first.
+pub async fn wayback_snapshots(client: &reqwest::Client, url: &str) -> Result<Vec<String>> {
+    // https://web.archive.org/web/timemap/?url=https://twitter.com/MariahCarey/status/1314585670644641794&collapse=timestamp&fl=timestamp
+    let url = Url::parse_with_params(
+        "https://web.archive.org/web/timemap/",
+        [("url", url), ("collapse", "timestamp"), ("fl", "timestamp")],
+    )?;
+    let get = ratelimit::send(client.get(url).timeout(Duration::from_secs(3)));
+    let resp = timing::phase(Phase::External, get).await?;
+    debug!(?resp);
+    let resp = resp.error_for_status()?;
+    Ok(resp.text().await?.lines().map(str::to_owned).collect())
+}
@@ -0,0 +1,179 @@
+use std::io;
+use std::path::Path;
+
+use clap::builder::PossibleValuesParser;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap_complete::Shell;
+use deadbeefbot::{check, checkpoint, conv, dump, fixtures, runs, selection, tasks, SETUP_FLAGS};
+
+/// The flags [`deadbeefbot::setup`] reads, which it reads again itself, for
+/// the subcommands that go through it. `--quiet` and `--verbose` are global.
+fn setup_args() -> impl Iterator<Item = Arg> {
+    let narrowing = selection::FLAGS.iter().map(|&flag| (flag, true));
+    narrowing
+        .chain(SETUP_FLAGS.iter().copied())
+        .filter(|&(flag, _)| !matches!(flag, "--quiet" | "--verbose"))
+        .map(|(flag, takes_value)| {
+            let name = flag.trim_start_matches('-');
+            let arg = Arg::new(name).long(name);
+            match takes_value {
+                true if name.ends_with("file") => arg
+                    .action(ArgAction::Append)
+                    .value_hint(ValueHint::FilePath),
+                true => arg.action(ArgAction::Append),
+                false => arg.action(ArgAction::SetTrue),
+            }
+        })
+}
+
+/// The subcommands, which completions are generated from too. Flags that
+/// [`deadbeefbot::setup`] reads are read again there.
+fn cli() -> Command {
+    let flag = |name: &'static str| Arg::new(name).long(name).action(ArgAction::SetTrue);
+    let value = |name: &'static str| Arg::new(name).long(name);
+    Command::new("deadbeefbot")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            flag("quiet")
+                .short('q')
+                .global(true)
+                .help("Log less than usual"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .global(true)
+                .help("Log more than usual, or even more with -vv"),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Run the tasks in the schedule")
+                .args(setup_args()),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Show how past runs went")
+                .arg(value("task").value_parser(PossibleValuesParser::new(tasks::NAMES)))
+                .arg(value("since").help("e.g. 30d, 12h or 2w")),
+        )
+        .subcommand(
+            Command::new("simulate")
+                .about("Run a task over a dump without editing")
+                .arg(
+                    Arg::new("task")
+                        .required(true)
+                        .value_parser(PossibleValuesParser::new(dump::TASKS)),
+                )
+                .arg(
+                    Arg::new("dump")
+                        .required(true)
+                        .value_hint(ValueHint::FilePath)
+                        .help("dump.xml or dump.xml.bz2"),
+                )
+                .arg(value("ns").value_parser(value_parser!(i32))),
+        )
+        .subcommand(
+            Command::new("fetch-fixtures")
+                .about("Save the pages listed in a file as test fixtures")
+                .arg(
+                    Arg::new("titles-file")
+                        .required(true)
+                        .value_hint(ValueHint::FilePath),
+                )
+                .arg(
+                    value("dir")
+                        .default_value(fixtures::DIR)
+                        .value_hint(ValueHint::DirPath),
+                ),
+        )
+        .subcommand(
+            Command::new("resume")
+                .about("Resume an interrupted run")
+                .arg(Arg::new("run-id").required(true)),
+        )
+        .subcommand(
+            Command::new("check-template")
+                .about("Survey the parameters of a talk page template")
+                .arg(value("template").action(ArgAction::Append))
+                .arg(value("search"))
+                .arg(value("out").value_hint(ValueHint::FilePath))
+                .args(setup_args()),
+        )
+        .subcommand(
+            Command::new("conv")
+                .about("Show what articlehistory would make of a talk page")
+                .arg(Arg::new("talk-page-title").required(true))
+                .arg(flag("params")),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print completions for a shell")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(value_parser!(Shell)),
+                ),
+        )
+}
+
+fn string<'a>(args: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    args.get_one::<String>(name).map(String::as_str)
+}
+
+fn init_logging() {
+    let args: Vec<_> = std::env::args().collect();
+    tracing_subscriber::fmt()
+        .with_env_filter(deadbeefbot::log_filter(&args))
+        .init();
+}
+
+fn stats(args: &ArgMatches) -> color_eyre::Result<()> {
+    let since = string(args, "since").map(runs::parse_period).transpose()?;
+    runs::print_stats(string(args, "task"), since)
+}
+
+fn simulate(args: &ArgMatches) -> color_eyre::Result<()> {
+    let (task, path) = (string(args, "task"), string(args, "dump"));
+    init_logging();
+    dump::simulate(task.unwrap(), path.unwrap(), args.get_one("ns").copied())
+}
+
+fn fetch_fixtures(args: &ArgMatches) -> color_eyre::Result<()> {
+    let (titles, dir) = (string(args, "titles-file"), string(args, "dir"));
+    init_logging();
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(fixtures::fetch(titles.unwrap(), Path::new(dir.unwrap())))
+}
+
+fn resume(args: &ArgMatches) -> color_eyre::Result<()> {
+    init_logging();
+    checkpoint::resume(string(args, "run-id").unwrap())
+}
+
+fn conv(args: &ArgMatches) -> color_eyre::Result<()> {
+    let (title, params) = (string(args, "talk-page-title"), args.get_flag("params"));
+    init_logging();
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(conv::main(title.unwrap(), params))
+}
+
+fn main() -> color_eyre::Result<()> {
+    match cli().get_matches().subcommand() {
+        Some(("daemon", _)) => deadbeefbot::setup(deadbeefbot::daemon::main),
+        Some(("stats", args)) => stats(args),
+        Some(("simulate", args)) => simulate(args),
+        Some(("fetch-fixtures", args)) => fetch_fixtures(args),
+        Some(("resume", args)) => resume(args),
+        Some(("check-template", _)) => deadbeefbot::setup_with(check::FLAGS, check::main),
+        Some(("conv", args)) => conv(args),
+        Some(("completions", args)) => {
+            let shell = *args.get_one::<Shell>("shell").unwrap();
+            clap_complete::generate(shell, &mut cli(), "deadbeefbot", &mut io::stdout());
+            Ok(())
+        }
+        _ => unreachable!("a subcommand is required"),
+    }
+}
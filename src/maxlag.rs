@@ -0,0 +1,68 @@
+//! Waiting out replication lag, as Wikimedia asks of bots.
+//!
+//! API requests sent through [`api`] carry `maxlag=`[`MAXLAG`], which has the
+//! API refuse them while the database replicas are further behind than that,
+//! and are sent again once the wait in the refusal's `Retry-After` is over.
+//! Edits and reads both go through it, which is why pages are read with
+//! [`fetch_content`](crate::fetch_content) and queries run with
+//! [`query_raw`](crate::query_raw) rather than `wiki`'s own, which don't send
+//! it. Only logging in is left to `wiki`. The REST API, which Parsoid's HTML
+//! comes from, has no `maxlag`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{ratelimit, Result};
+
+/// Seconds of lag above which requests are refused, as Wikimedia recommends.
+pub const MAXLAG: &str = "5";
+
+/// Times a request is sent again before its refusal is returned.
+const MAX_RETRIES: u32 = 10;
+
+/// Used when a refusal has no usable `Retry-After`.
+const DEFAULT_WAIT: Duration = Duration::from_secs(5);
+
+/// Longest wait between two attempts, as the wait grows with each.
+const MAX_WAIT: Duration = Duration::from_secs(120);
+
+static LAGGED: AtomicU64 = AtomicU64::new(0);
+
+pub fn log_count() {
+    let lagged = LAGGED.load(Ordering::Relaxed);
+    if lagged > 0 {
+        info!("waited for replication lag {lagged} time(s)");
+    }
+}
+
+/// Sends the API request `req` with `maxlag`, sending it again while the API
+/// refuses it for lag, and returns the response. Other API errors are left
+/// for the caller.
+pub async fn api(req: RequestBuilder) -> Result<Value> {
+    let req = req.query(&[("maxlag", MAXLAG)]);
+    let mut attempt = 0;
+    loop {
+        let Some(retry) = req.try_clone().filter(|_| attempt < MAX_RETRIES) else {
+            return Ok(ratelimit::send(req)
+                .await?
+                .error_for_status()?
+                .json()
+                .await?);
+        };
+        let resp = ratelimit::send(retry).await?.error_for_status()?;
+        let retry_after = ratelimit::retry_after(resp.headers());
+        let res: Value = resp.json().await?;
+        if res["error"]["code"] != "maxlag" {
+            return Ok(res);
+        }
+        attempt += 1;
+        LAGGED.fetch_add(1, Ordering::Relaxed);
+        let wait = (retry_after.unwrap_or(DEFAULT_WAIT) * attempt).min(MAX_WAIT);
+        warn!("{}, retrying in {wait:?}", res["error"]["info"]);
+        tokio::time::sleep(wait).await;
+    }
+}
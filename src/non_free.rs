@@ -0,0 +1,193 @@
+//! Reports non-free files used in articles without a use rationale for that
+//! article, per [[WP:NFCC#10c]].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use parsoid::WikinodeIterator;
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::source::{CategorySource, PageSource};
+use crate::{
+    enwiki_parsoid, query_raw, run_lock, runs, selection, template_name, Parsoid, Result,
+    ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("non_free", EditFlags::BOT);
+
+const REPORT: &str = "User:DeadbeefBot/Non-free files missing a rationale";
+
+const NON_FREE: &str = "Category:All non-free media";
+
+/// Parameters that rationale templates use to name the article.
+const ARTICLE_PARAMS: &[&str] = &["Article", "article", "Page", "page"];
+
+fn is_rationale(name: &str) -> bool {
+    name.contains("rationale") || name.ends_with(" fur") || name == "fur"
+}
+
+fn is_license(name: &str) -> bool {
+    name.starts_with("non-free") && !is_rationale(name)
+}
+
+fn normalize(title: &str) -> String {
+    let title = title.trim().trim_start_matches(':').replace('_', " ");
+    let mut chars = title.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Articles using each of `files`.
+async fn usage(client: &wiki::Bot, files: &[&str]) -> Result<BTreeMap<String, Vec<String>>> {
+    let titles = files.join("|");
+    let batches = query_raw(
+        client,
+        ENWIKI_API,
+        &[
+            ("prop", "fileusage"),
+            ("titles", &titles),
+            ("funamespace", "0"),
+            ("fulimit", "max"),
+        ],
+    )
+    .await?;
+    let mut usage: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for page in batches
+        .iter()
+        .flat_map(|b| b["pages"].as_array().into_iter().flatten())
+    {
+        let Some(title) = page["title"].as_str() else {
+            continue;
+        };
+        usage.entry(title.to_owned()).or_default().extend(
+            page["fileusage"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|u| Some(u["title"].as_str()?.to_owned())),
+        );
+    }
+    Ok(usage)
+}
+
+pub enum Violation {
+    /// Marked non-free without any rationale at all.
+    NoRationale,
+    /// Used in these articles, which no rationale covers.
+    Uncovered(Vec<String>),
+}
+
+pub async fn check(parsoid: &Parsoid, file: &str, used_in: &[String]) -> Result<Option<Violation>> {
+    let code = parsoid.get(file).await?.into_mutable();
+    let templates = code.filter_templates()?;
+    if !templates.iter().any(|t| is_license(&template_name(t))) {
+        return Ok(None);
+    }
+    let covered: Vec<_> = templates
+        .iter()
+        .filter(|t| is_rationale(&template_name(t)))
+        .map(|t| {
+            ARTICLE_PARAMS
+                .iter()
+                .find_map(|p| t.param(p))
+                .map(|a| normalize(&a))
+        })
+        .collect();
+    if covered.is_empty() {
+        return Ok(Some(Violation::NoRationale));
+    }
+    // a rationale without an article name can't be matched to a use
+    let uncovered: Vec<_> = used_in
+        .iter()
+        .filter(|a| !covered.contains(&Some(normalize(a))))
+        .cloned()
+        .collect();
+    if uncovered.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Violation::Uncovered(uncovered)))
+    }
+}
+
+pub fn render(violations: &[(String, Violation)]) -> String {
+    let mut s = format!(
+        "Non-free files lacking a use rationale, as of ~~~~~. {} entries.\n\n\
+        {{| class=\"wikitable sortable\"\n! File !! Problem\n",
+        violations.len()
+    );
+    for (file, v) in violations {
+        let problem = match v {
+            Violation::NoRationale => "no rationale".to_owned(),
+            Violation::Uncovered(articles) => {
+                let articles: Vec<_> = articles.iter().map(|a| format!("[[{a}]]")).collect();
+                format!("no rationale for {}", articles.join(", "))
+            }
+        };
+        let _ = write!(s, "|-\n| [[:{file}]] || {problem}\n");
+    }
+    s.push_str("|}\n");
+    s
+}
+
+pub async fn main() -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+
+        let files = CategorySource::new(&client, ENWIKI_API, NON_FREE)
+            .namespace("6")
+            .collect_all()
+            .await?;
+        let files = scope.filter(files);
+        info!("checking {} non-free files", files.len());
+
+        let mut violations = Vec::new();
+        for chunk in files.chunks(50) {
+            let titles: Vec<_> = chunk.iter().map(String::as_str).collect();
+            let usage = match usage(&client, &titles).await {
+                Ok(usage) => usage,
+                Err(e) => {
+                    warn!("failed to fetch usage: {e}");
+                    continue;
+                }
+            };
+            for file in chunk {
+                let used_in = usage.get(file).map_or(&[][..], Vec::as_slice);
+                match check(&parsoid, file, used_in).await {
+                    Ok(Some(v)) => violations.push((file.clone(), v)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        runs::page_failed();
+                        warn!("failed to check [[{file}]]: {e}");
+                    }
+                }
+            }
+        }
+
+        info!("{} violations found", violations.len());
+        if scope.is_narrowed() {
+            info!("not updating [[{REPORT}]] from a narrowed run");
+            return Ok(());
+        }
+        save(
+            &client,
+            PageSpec::Title(REPORT.to_owned()),
+            None,
+            render(&violations),
+            format!("Updating report ({} entries)", violations.len()),
+            None,
+            &FLAGS,
+        )
+        .await?;
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,157 @@
+//! Leaves messages on user talk pages.
+//!
+//! Users can opt out of all messages by listing themselves on [`OPT_OUT`];
+//! `{{nobots}}` on their talk page is respected as well.
+
+use std::collections::HashSet;
+
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::{check_nobots_wikitext, fetch_content, Result};
+
+/// Not bot edits, so that recipients get a new message notification.
+const FLAGS: TaskFlags = TaskFlags::new("notify", EditFlags::NONE);
+
+pub const OPT_OUT: &str = "User:DeadbeefBot/No messages";
+
+/// A message with `$USER` standing for the recipient's name.
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+    pub summary: String,
+}
+
+impl Message {
+    /// Tells an editor about pages the bot had to leave alone.
+    pub fn skipped_pages(task: &str, pages: &[(String, String)]) -> Message {
+        let list: String = pages
+            .iter()
+            .map(|(title, reason)| format!("* [[{title}]]: {reason}\n"))
+            .collect();
+        Message {
+            subject: format!("Pages skipped by DeadbeefBot ({task})"),
+            body: format!(
+                "Hello $USER, while running {task} I couldn't process the following \
+                pages you were involved with, and they might need to be fixed by hand:\n\n{list}"
+            ),
+            summary: format!("Notifying about pages skipped by {task}"),
+        }
+    }
+
+    fn render(&self, user: &str) -> String {
+        format!(
+            "== {} ==\n{} ~~~~",
+            self.subject,
+            self.body.replace("$USER", user).trim_end()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    OptedOut,
+    Nobots,
+    /// A section with the same heading already exists.
+    Duplicate,
+}
+
+fn user_links(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split("[[")
+        .skip(1)
+        .filter_map(|l| l.split_once("]]"))
+        .filter_map(|(link, _)| {
+            let link = link.split('|').next()?.trim();
+            let user = link
+                .strip_prefix("User:")
+                .or_else(|| link.strip_prefix("User talk:"))?;
+            Some(user.split('/').next()?.trim().to_owned())
+        })
+}
+
+/// Recipients linked from a page on the wiki at `api_url`, e.g. a project's
+/// member list.
+pub async fn recipients_from_page(
+    client: &wiki::Bot,
+    api_url: &str,
+    title: &str,
+) -> Result<Vec<String>> {
+    let mut users: Vec<_> = user_links(&fetch_content(client, api_url, title).await?).collect();
+    users.sort();
+    users.dedup();
+    Ok(users)
+}
+
+pub struct Notifier<'a> {
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    opt_out: HashSet<String>,
+}
+
+impl<'a> Notifier<'a> {
+    /// Leaves messages on the wiki at `api_url`.
+    pub async fn new(client: &'a wiki::Bot, api_url: &'a str) -> Result<Notifier<'a>> {
+        let opt_out = fetch_content(client, api_url, OPT_OUT)
+            .await
+            .map(|text| user_links(&text).collect())
+            .unwrap_or_default();
+        Ok(Notifier {
+            client,
+            api_url,
+            opt_out,
+        })
+    }
+
+    pub async fn send(&self, user: &str, message: &Message) -> Result<Delivery> {
+        if self.opt_out.contains(user) {
+            return Ok(Delivery::OptedOut);
+        }
+        let talk = format!("User talk:{user}");
+        let old = fetch_content(self.client, self.api_url, &talk)
+            .await
+            .unwrap_or_default();
+        if check_nobots_wikitext(&old) {
+            return Ok(Delivery::Nobots);
+        }
+        if old.contains(&format!("== {} ==", message.subject)) {
+            return Ok(Delivery::Duplicate);
+        }
+        let mut text = old.clone();
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(&message.render(user));
+        save(
+            self.client,
+            PageSpec::Title(talk),
+            Some(&old),
+            text,
+            &*message.summary,
+            None,
+            &FLAGS,
+        )
+        .await?;
+        Ok(Delivery::Sent)
+    }
+
+    /// Sends `message` to every recipient, returning the outcome for each.
+    pub async fn send_all(
+        &self,
+        recipients: &[String],
+        message: &Message,
+    ) -> Vec<(String, Result<Delivery>)> {
+        let mut outcomes = Vec::new();
+        for user in recipients {
+            let res = self.send(user, message).await;
+            match &res {
+                Ok(Delivery::Sent) => info!("messaged [[User talk:{user}]]"),
+                Ok(d) => info!("not messaging {user}: {d:?}"),
+                Err(e) => warn!("failed to message {user}: {e}"),
+            }
+            outcomes.push((user.clone(), res));
+        }
+        outcomes
+    }
+}
@@ -0,0 +1,108 @@
+//! Finds talk pages whose subject page doesn't exist.
+//!
+//! User talk pages, redirects, subpages (which are mostly archives) and pages
+//! marked with `{{G8-exempt}}` are excluded. The rest are listed on
+//! [`REPORT`], and optionally tagged for [[WP:G8]].
+
+use std::fmt::Write;
+
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::siteinfo::SiteInfo;
+use crate::source::{PageSource, ReplicaSource};
+use crate::{check_nobots_wikitext, fetch_content, run_lock, runs, selection, Result, ENWIKI_API};
+
+const FLAGS: TaskFlags = TaskFlags::new("orphaned_talk", EditFlags::BOT);
+
+const REPORT: &str = "User:DeadbeefBot/Orphaned talk pages";
+
+const SQL: &str = "
+SELECT talk.page_namespace, talk.page_title
+FROM page talk
+LEFT JOIN page subject
+  ON subject.page_namespace = talk.page_namespace - 1
+  AND subject.page_title = talk.page_title
+WHERE talk.page_namespace IN (1, 5, 7, 9, 11, 13, 15, 101, 119, 829)
+  AND talk.page_is_redirect = 0
+  AND talk.page_title NOT LIKE '%/%'
+  AND subject.page_id IS NULL
+  AND NOT EXISTS (
+    SELECT 1 FROM templatelinks
+    JOIN linktarget ON lt_id = tl_target_id
+    WHERE tl_from = talk.page_id AND lt_namespace = 10 AND lt_title = 'G8-exempt'
+  )
+ORDER BY talk.page_namespace, talk.page_title";
+
+const TAG: &str = "{{db-talk}}";
+
+pub fn render(pages: &[String]) -> String {
+    let mut s = format!(
+        "Talk pages without a subject page, as of ~~~~~. {} entries.\n\n",
+        pages.len()
+    );
+    for page in pages {
+        let _ = writeln!(s, "* [[{page}]]");
+    }
+    s
+}
+
+async fn tag(client: &wiki::Bot, title: &str) -> Result<bool> {
+    let text = fetch_content(client, ENWIKI_API, title).await?;
+    if check_nobots_wikitext(&text) || text.to_ascii_lowercase().contains("{{db-") {
+        return Ok(false);
+    }
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        Some(&text),
+        format!("{TAG}\n{text}"),
+        "Tagging talk page of a nonexistent page for deletion per [[WP:G8]]",
+        None,
+        &FLAGS,
+    )
+    .await?;
+    Ok(true)
+}
+
+pub async fn main(tag_pages: bool) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+        let pages = ReplicaSource::new("enwiki", SQL, &site)
+            .collect_all()
+            .await?;
+        info!("{} orphaned talk pages", pages.len());
+
+        if scope.is_narrowed() {
+            info!("not updating [[{REPORT}]] from a narrowed run");
+        } else {
+            save(
+                &client,
+                PageSpec::Title(REPORT.to_owned()),
+                None,
+                render(&pages),
+                format!("Updating report ({} entries)", pages.len()),
+                None,
+                &FLAGS,
+            )
+            .await?;
+        }
+
+        if tag_pages {
+            for title in scope.filter(pages) {
+                if let Err(e) = tag(&client, &title).await {
+                    runs::page_failed();
+                    warn!("failed to tag [[{title}]]: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
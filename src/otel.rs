@@ -0,0 +1,92 @@
+//! Exports traces and metrics over OTLP, for deployments that already run an
+//! OpenTelemetry collector. Only built with the `otel` feature.
+//!
+//! Export is on when `OTEL_EXPORTER_OTLP_ENDPOINT` is set, e.g. to
+//! `http://localhost:4317`; the other standard `OTEL_*` variables apply too.
+//! Spans from [`timing`](crate::timing) become traces, and the counters behind
+//! [`runs`](crate::runs) and [`ratelimit`](crate::ratelimit) are exported as
+//! metrics.
+
+use std::env;
+
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::{edit, ratelimit, runs, Result};
+
+pub struct Otel {
+    traces: TracerProvider,
+    metrics: SdkMeterProvider,
+}
+
+impl Otel {
+    /// Sets up export, if an endpoint is configured. Must be called within
+    /// the Tokio runtime.
+    pub fn init() -> Result<Option<Otel>> {
+        if env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+            return Ok(None);
+        }
+        let resource = Resource::new([KeyValue::new("service.name", "deadbeefbot")]);
+        let traces = TracerProvider::builder()
+            .with_batch_exporter(
+                SpanExporter::builder().with_tonic().build()?,
+                runtime::Tokio,
+            )
+            .with_resource(resource.clone())
+            .build();
+        let reader = PeriodicReader::builder(
+            MetricExporter::builder().with_tonic().build()?,
+            runtime::Tokio,
+        )
+        .build();
+        let metrics = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+
+        let meter = metrics.meter("deadbeefbot");
+        meter
+            .u64_observable_counter("deadbeefbot.edits")
+            .with_callback(|c| c.observe(edit::edit_count(), &[]))
+            .build();
+        meter
+            .u64_observable_counter("deadbeefbot.backoffs")
+            .with_callback(|c| {
+                let (http, mw) = ratelimit::counts();
+                c.observe(http, &[KeyValue::new("kind", "http_429")]);
+                c.observe(mw, &[KeyValue::new("kind", "ratelimited")]);
+            })
+            .build();
+        meter
+            .u64_observable_counter("deadbeefbot.failed_pages")
+            .with_callback(|c| c.observe(runs::failed_count(), &[]))
+            .build();
+
+        Ok(Some(Otel { traces, metrics }))
+    }
+
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.traces.tracer("deadbeefbot"))
+    }
+
+    /// Sends whatever hasn't been exported yet.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.traces.shutdown() {
+            eprintln!("failed to flush traces: {e}");
+        }
+        if let Err(e) = self.metrics.shutdown() {
+            eprintln!("failed to flush metrics: {e}");
+        }
+    }
+}
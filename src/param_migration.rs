@@ -0,0 +1,337 @@
+//! Renames and merges template parameters according to a mapping file, for
+//! carrying out TfD-mandated infobox merges.
+//!
+//! A mapping file looks like:
+//!
+//! ```json
+//! {
+//!     "templates": ["Infobox old"],
+//!     "rename_to": "Infobox new",
+//!     "params": { "birthplace": "birth_place", "born_in": "birth_place" },
+//!     "transforms": { "birth_place": [{ "replace": { "from": "[[", "to": "" } }, "trim"] },
+//!     "remove": ["obsolete"],
+//!     "summary": "Merging per [[Wikipedia:Templates for discussion/Log/2024 January 1]]"
+//! }
+//! ```
+//!
+//! Several old names mapping to the same new name are merged; pages where
+//! they hold different non-empty values are skipped.
+
+use std::collections::HashMap;
+
+use color_eyre::eyre::{bail, Context};
+use fancy_regex::Regex;
+use parsoid::map::IndexMap;
+use parsoid::{Template, WikinodeIterator};
+use serde::Deserialize;
+use tracing::{debug, info};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{check_dirty_diff, save, EditFlags, TaskFlags};
+use crate::{
+    check_nobots, enwiki_parsoid, fetch_contents, policy, query_raw, run_lock, selection,
+    title_pattern, Parsoid, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("param_migration", EditFlags::BOT);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum TransformDef {
+    Replace {
+        from: String,
+        to: String,
+    },
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+    Prefix(String),
+    Suffix(String),
+    Map(HashMap<String, String>),
+    Trim,
+    Lowercase,
+    Uppercase,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    templates: Vec<String>,
+    rename_to: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, String>,
+    #[serde(default)]
+    transforms: HashMap<String, Vec<TransformDef>>,
+    #[serde(default)]
+    remove: Vec<String>,
+    summary: String,
+    #[serde(default)]
+    namespace: Option<String>,
+}
+
+pub enum Transform {
+    Replace { from: String, to: String },
+    Regex { re: Regex, replacement: String },
+    Prefix(String),
+    Suffix(String),
+    Map(HashMap<String, String>),
+    Trim,
+    Lowercase,
+    Uppercase,
+}
+
+impl Transform {
+    fn apply(&self, value: &str) -> Result<String> {
+        Ok(match self {
+            Transform::Replace { from, to } => value.replace(from, to),
+            Transform::Regex { re, replacement } => re
+                .try_replacen(value, 0, replacement.as_str())?
+                .into_owned(),
+            Transform::Prefix(p) if !value.trim().is_empty() => format!("{p}{value}"),
+            Transform::Suffix(s) if !value.trim().is_empty() => format!("{value}{s}"),
+            Transform::Prefix(_) | Transform::Suffix(_) => value.to_owned(),
+            Transform::Map(map) => map
+                .get(value.trim())
+                .cloned()
+                .unwrap_or_else(|| value.into()),
+            Transform::Trim => value.trim().to_owned(),
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::Uppercase => value.to_uppercase(),
+        })
+    }
+}
+
+pub struct Mapping {
+    pub templates: Vec<String>,
+    pub rename_to: Option<String>,
+    pub params: HashMap<String, String>,
+    pub transforms: HashMap<String, Vec<Transform>>,
+    pub remove: Vec<String>,
+    pub summary: String,
+    pub namespace: String,
+}
+
+fn normalize(name: &str) -> String {
+    name.trim()
+        .trim_start_matches("Template:")
+        .replace('_', " ")
+        .to_lowercase()
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> Result<Mapping> {
+        let file: MappingFile = serde_json::from_str(&checkpoint::read_required(path)?)
+            .with_context(|| format!("parsing {path}"))?;
+
+        let transforms = file
+            .transforms
+            .into_iter()
+            .map(|(param, defs)| -> Result<(String, Vec<Transform>)> {
+                let defs = defs
+                    .into_iter()
+                    .map(|def| -> Result<Transform> {
+                        Ok(match def {
+                            TransformDef::Replace { from, to } => Transform::Replace { from, to },
+                            TransformDef::Regex {
+                                pattern,
+                                replacement,
+                            } => Transform::Regex {
+                                re: Regex::new(&pattern)?,
+                                replacement,
+                            },
+                            TransformDef::Prefix(p) => Transform::Prefix(p),
+                            TransformDef::Suffix(s) => Transform::Suffix(s),
+                            TransformDef::Map(m) => Transform::Map(m),
+                            TransformDef::Trim => Transform::Trim,
+                            TransformDef::Lowercase => Transform::Lowercase,
+                            TransformDef::Uppercase => Transform::Uppercase,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Ok((param, defs))
+            })
+            .collect::<Result<_>>()?;
+
+        if file.templates.is_empty() {
+            bail!("{path} lists no templates");
+        }
+
+        Ok(Mapping {
+            templates: file.templates,
+            rename_to: file.rename_to,
+            params: file.params,
+            transforms,
+            remove: file.remove,
+            summary: file.summary,
+            namespace: file.namespace.unwrap_or_else(|| "0".into()),
+        })
+    }
+
+    /// Lines the migration may change: those naming one of the templates or
+    /// parameters involved.
+    fn intended(&self) -> Result<Regex> {
+        let names: Vec<_> = self
+            .templates
+            .iter()
+            .chain(&self.rename_to)
+            .chain(self.params.keys())
+            .chain(self.params.values())
+            .chain(self.transforms.keys())
+            .chain(&self.remove)
+            .map(|n| title_pattern(n.trim_start_matches("Template:")))
+            .collect();
+        Ok(Regex::new(&names.join("|"))?)
+    }
+
+    pub fn matches(&self, t: &Template) -> bool {
+        let name = normalize(&t.name());
+        self.templates.iter().any(|x| normalize(x) == name)
+    }
+
+    /// Computes the migrated parameters, keeping their original order.
+    ///
+    /// Returns `None` if nothing changes.
+    pub fn apply(
+        &self,
+        params: &IndexMap<String, String>,
+    ) -> Result<Option<IndexMap<String, String>>> {
+        let mut out: IndexMap<String, String> = IndexMap::new();
+        for (name, value) in params {
+            if self.remove.contains(name) {
+                continue;
+            }
+            let new_name = self.params.get(name).unwrap_or(name);
+            let mut value = value.clone();
+            for t in self.transforms.get(new_name).into_iter().flatten() {
+                value = t.apply(&value)?;
+            }
+            match out.get_mut(new_name) {
+                Some(existing) if existing.trim().is_empty() => *existing = value,
+                Some(existing) if value.trim().is_empty() || existing.trim() == value.trim() => {}
+                Some(existing) => {
+                    bail!("conflicting values for {new_name}: {existing:?} and {value:?}")
+                }
+                None => {
+                    out.insert(new_name.clone(), value);
+                }
+            }
+        }
+        if &out == params {
+            Ok(None)
+        } else {
+            Ok(Some(out))
+        }
+    }
+}
+
+/// Returns the number of transclusions changed.
+pub async fn treat(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    mapping: &Mapping,
+    title: &str,
+) -> Result<usize> {
+    let Some(page) = fetch_contents(client, ENWIKI_API, &[title]).await?.pop() else {
+        return Ok(0);
+    };
+    let code = parsoid
+        .get_revision(title, page.revid as u64)
+        .await?
+        .into_mutable();
+    let templates = code.filter_templates()?;
+
+    if templates.iter().any(check_nobots) {
+        return Ok(0);
+    }
+
+    let mut changed = 0;
+    for t in templates.iter().filter(|t| mapping.matches(t)) {
+        let renamed = match &mapping.rename_to {
+            Some(new) if normalize(new) != normalize(&t.name()) => {
+                t.set_name(new.clone())?;
+                true
+            }
+            _ => false,
+        };
+        if let Some(params) = mapping.apply(&t.params())? {
+            t.set_params(params)?;
+            changed += 1;
+        } else if renamed {
+            changed += 1;
+        }
+    }
+
+    if changed == 0 {
+        debug!("nothing to migrate on [[{title}]]");
+        return Ok(0);
+    }
+
+    let text = parsoid.transform_to_wikitext(&code).await?;
+    check_dirty_diff(title, &page.text, &text, &mapping.intended()?)?;
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        Some(&page.text),
+        text,
+        &*mapping.summary,
+        Some(page.revid),
+        &FLAGS,
+    )
+    .await?;
+    Ok(changed)
+}
+
+pub async fn main(mapping: &str) -> Result<()> {
+    let mapping = Mapping::load(mapping)?;
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+
+        let transclusions = async {
+            let mut pages = Vec::new();
+            for template in &mapping.templates {
+                let target = format!("Template:{}", template.trim_start_matches("Template:"));
+                let batches = query_raw(
+                    &client,
+                    ENWIKI_API,
+                    &[
+                        ("list", "embeddedin"),
+                        ("eititle", &target),
+                        ("einamespace", &mapping.namespace),
+                        ("eilimit", "max"),
+                    ],
+                )
+                .await?;
+                pages.extend(
+                    batches
+                        .iter()
+                        .flat_map(|b| b["embeddedin"].as_array().into_iter().flatten())
+                        .filter_map(|p| Some(p["title"].as_str()?.to_owned())),
+                );
+            }
+            pages.sort();
+            pages.dedup();
+            info!("{} pages transclude the templates", pages.len());
+            Ok(pages)
+        };
+        let pages = scope.titles(transclusions).await?;
+
+        let mut total = 0;
+        for title in pages {
+            let treated = || treat(&client, &parsoid, &mapping, &title);
+            match policy::page(FLAGS.task, &title, treated).await? {
+                Some(0) => continue,
+                Some(n) => total += n,
+                None => {}
+            }
+        }
+        info!("migrated {total} transclusions");
+
+        Ok(())
+    })
+    .await
+}
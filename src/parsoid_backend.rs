@@ -0,0 +1,242 @@
+//! Parsoid behind RESTBase, the core REST API or the action API.
+//!
+//! RESTBase (`/api/rest_v1`) is being retired in favour of the Parsoid
+//! endpoints in core (`/w/rest.php`). The action API backend fetches HTML with
+//! `action=parse&parsoid=1` and uses the core REST API for everything else,
+//! such as transforming HTML back to wikitext.
+
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use color_eyre::eyre::bail;
+use futures_util::{stream, StreamExt};
+use parsoid::{ImmutableWikicode, Wikicode};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::timing::{self, Phase};
+use crate::{maxlag, Result};
+
+/// How many transforms [`Parsoid::transform_all`] runs at once.
+const TRANSFORM_CONCURRENCY: usize = 4;
+
+/// Named as in `PARSOID_BACKEND` when configured.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParsoidBackend {
+    #[serde(rename = "restbase")]
+    RestBase,
+    #[serde(rename = "rest")]
+    CoreRest,
+    #[serde(rename = "action")]
+    ActionApi,
+}
+
+impl ParsoidBackend {
+    /// The backend named by `PARSOID_BACKEND`, if set.
+    pub fn from_env() -> Option<ParsoidBackend> {
+        match std::env::var("PARSOID_BACKEND").ok()?.as_str() {
+            "restbase" => Some(ParsoidBackend::RestBase),
+            "rest" => Some(ParsoidBackend::CoreRest),
+            "action" => Some(ParsoidBackend::ActionApi),
+            _ => None,
+        }
+    }
+
+    /// The REST base URL for a wiki, given its `api.php` URL.
+    fn rest_url(self, api_url: &str) -> String {
+        let script_path = api_url.trim_end_matches("api.php");
+        match self {
+            ParsoidBackend::RestBase => {
+                let origin = script_path.trim_end_matches('/').trim_end_matches("/w");
+                format!("{origin}/api/rest_v1")
+            }
+            ParsoidBackend::CoreRest | ParsoidBackend::ActionApi => {
+                format!("{script_path}rest.php")
+            }
+        }
+    }
+}
+
+/// Whether `e` means the endpoint is down or overloaded, rather than
+/// something wrong with the request.
+fn is_unavailable(e: &parsoid::Error) -> bool {
+    match e {
+        parsoid::Error::Http(e) => {
+            e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error())
+        }
+        _ => false,
+    }
+}
+
+/// A [`parsoid::Client`] whose [`get`](Parsoid::get) goes through the
+/// configured backend. Clones share connections.
+///
+/// Requests fail over to the next REST endpoint when one times out or
+/// returns a server error, and stay there until it fails too. The endpoints
+/// are the one for the backend, the other of RESTBase and the core REST API,
+/// and then any REST base URLs listed, comma-separated, in
+/// `PARSOID_FALLBACKS` (such as a local Parsoid).
+#[derive(Clone)]
+pub struct Parsoid {
+    endpoints: Arc<[(String, parsoid::Client)]>,
+    current: Arc<AtomicUsize>,
+    backend: ParsoidBackend,
+    api_url: String,
+    http: reqwest::Client,
+}
+
+impl Parsoid {
+    /// `PARSOID_BACKEND` takes precedence over `backend`.
+    pub fn new(api_url: &str, backend: ParsoidBackend) -> Result<Parsoid> {
+        let backend = ParsoidBackend::from_env().unwrap_or(backend);
+        let other = match backend {
+            ParsoidBackend::RestBase => ParsoidBackend::CoreRest,
+            ParsoidBackend::CoreRest | ParsoidBackend::ActionApi => ParsoidBackend::RestBase,
+        };
+        let mut urls = vec![backend.rest_url(api_url), other.rest_url(api_url)];
+        if let Ok(extra) = std::env::var("PARSOID_FALLBACKS") {
+            urls.extend(
+                extra
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|u| !u.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        let http = crate::http()?.parsoid.clone();
+        let endpoints = urls
+            .into_iter()
+            .map(|url| {
+                let client = parsoid::Client::new_with_client(&url, http.clone())?;
+                Ok((url, client))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Parsoid {
+            endpoints,
+            current: Arc::new(AtomicUsize::new(0)),
+            backend,
+            api_url: api_url.to_owned(),
+            http,
+        })
+    }
+
+    /// Runs `f` against the current endpoint, moving on to the next one each
+    /// time it is unavailable.
+    async fn failover<T, F, Fut>(&self, mut f: F) -> Result<T, parsoid::Error>
+    where
+        F: FnMut(parsoid::Client) -> Fut,
+        Fut: Future<Output = Result<T, parsoid::Error>>,
+    {
+        let n = self.endpoints.len();
+        let mut idx = self.current.load(Ordering::Relaxed);
+        let mut tries = 1;
+        loop {
+            let (url, client) = &self.endpoints[idx];
+            match f(client.clone()).await {
+                Err(e) if tries < n && is_unavailable(&e) => {
+                    idx = (idx + 1) % n;
+                    warn!(
+                        "{url} unavailable, failing over to {}: {e}",
+                        self.endpoints[idx].0
+                    );
+                    self.current.store(idx, Ordering::Relaxed);
+                    tries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    pub fn backend(&self) -> ParsoidBackend {
+        self.backend
+    }
+
+    async fn parse(&self, title: &str, section: Option<&str>) -> Result<Option<String>> {
+        let mut query = vec![
+            ("action", "parse"),
+            ("page", title),
+            ("parsoid", "1"),
+            ("prop", "text"),
+            ("format", "json"),
+            ("formatversion", "2"),
+        ];
+        if let Some(section) = section {
+            query.push(("section", section));
+        }
+        let res = maxlag::api(self.http.get(&self.api_url).query(&query)).await?;
+        if res["error"]["code"] == "missingtitle" {
+            return Ok(None);
+        }
+        match res["parse"]["text"].as_str() {
+            Some(html) => Ok(Some(html.to_owned())),
+            None => bail!("unexpected response: {}", res["error"]),
+        }
+    }
+
+    /// Fetches the current HTML of `title`.
+    ///
+    /// With the action API backend, failures other than a missing page fall
+    /// back to the REST API.
+    pub async fn get(&self, title: &str) -> Result<ImmutableWikicode, parsoid::Error> {
+        timing::phase(Phase::Fetch, self.get_inner(title)).await
+    }
+
+    async fn get_inner(&self, title: &str) -> Result<ImmutableWikicode, parsoid::Error> {
+        if self.backend == ParsoidBackend::ActionApi {
+            match self.parse(title, None).await {
+                Ok(Some(html)) => return Ok(ImmutableWikicode::new(&html)),
+                Ok(None) => return Err(parsoid::Error::PageDoesNotExist(title.to_owned())),
+                Err(e) => debug!("action=parse failed for [[{title}]], using REST: {e}"),
+            }
+        }
+        self.failover(|rest| async move { rest.get(title).await })
+            .await
+    }
+
+    pub async fn get_revision(
+        &self,
+        title: &str,
+        revid: u64,
+    ) -> Result<ImmutableWikicode, parsoid::Error> {
+        let get = self.failover(|rest| async move { rest.get_revision(title, revid).await });
+        timing::phase(Phase::Fetch, get).await
+    }
+
+    pub async fn transform_to_wikitext(&self, code: &Wikicode) -> Result<String, parsoid::Error> {
+        let transform = self.failover(|rest| async move { rest.transform_to_wikitext(code).await });
+        timing::phase(Phase::Parse, transform).await
+    }
+
+    /// Fetches the current HTML of only the lead section of `title`, or
+    /// `None` if the page doesn't exist. Much smaller than the whole page for
+    /// talk pages with long discussions; transformed back, it is the wikitext
+    /// of section 0.
+    ///
+    /// Always uses the action API, since the REST API can't fetch sections.
+    pub async fn get_lead(&self, title: &str) -> Result<Option<ImmutableWikicode>> {
+        Ok(timing::phase(Phase::Fetch, self.parse(title, Some("0")))
+            .await?
+            .map(|html| ImmutableWikicode::new(&html)))
+    }
+
+    /// Transforms each of `codes` back to wikitext, a few at a time over the
+    /// same connections. Results are in the order of `codes`.
+    pub async fn transform_all(&self, codes: &[Wikicode]) -> Vec<Result<String>> {
+        stream::iter(codes)
+            .map(|code| async move { Ok(self.transform_to_wikitext(code).await?) })
+            .buffered(TRANSFORM_CONCURRENCY)
+            .collect()
+            .await
+    }
+}
+
+impl Deref for Parsoid {
+    type Target = parsoid::Client;
+
+    /// The current endpoint.
+    fn deref(&self) -> &parsoid::Client {
+        &self.endpoints[self.current.load(Ordering::Relaxed)].1
+    }
+}
@@ -0,0 +1,170 @@
+//! Archives inactive peer reviews.
+//!
+//! Closing a review swaps `{{Peer review}}` on the talk page for
+//! `{{Old peer review}}`, which the articlehistory task can later fold into
+//! `{{Article history}}`.
+
+use chrono::{Duration, Utc};
+use color_eyre::eyre::{bail, Context, ContextCompat};
+use parsoid::WikinodeIterator;
+use tracing::info;
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::siteinfo::{SiteInfo, PROJECT, TALK};
+use crate::{
+    check_nobots, enwiki_parsoid, fetch_content, fetch_contents, last_edited, policy, run_lock,
+    search_titles, selection, Parsoid, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("peer_review", EditFlags::MINOR_BOT);
+
+/// Reviews that haven't been edited for this many days are closed.
+const INACTIVE_DAYS: i64 = 30;
+
+/// https://en.wikipedia.org/wiki/Special:WhatLinksHere?target=Template%3APeer+review&namespace=&hidetrans=1&hidelinks=1
+const ALIAS: &[&str] = &["peer review", "peerreview"];
+
+const ARCHIVE_TOP: &str = "{{subst:PR/archive}}";
+const ARCHIVE_BOTTOM: &str = "{{subst:PR/archive bottom}}";
+
+const SUMMARY: &str = "Archiving inactive peer review";
+
+pub async fn treat(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    site: &SiteInfo,
+    title: &str,
+) -> Result<()> {
+    let (TALK, subject) = site.split(title) else {
+        bail!("not a talk page");
+    };
+    let code = parsoid.get(title).await?.into_mutable();
+    let rev = code
+        .revision_id()
+        .with_context(|| format!("no revision of [[{title}]] from Parsoid"))?;
+    let templates = code.filter_templates()?;
+
+    if templates.iter().any(check_nobots) {
+        return Ok(());
+    }
+
+    let Some(pr) = templates.iter().find(|t| {
+        let name = t
+            .name()
+            .trim_start_matches("Template:")
+            .to_ascii_lowercase();
+        ALIAS.contains(&&*name)
+    }) else {
+        bail!("no peer review template");
+    };
+
+    let archive = pr.param("archive").unwrap_or_else(|| "1".into());
+    let reviewed = pr.param("reviewedname");
+    let review_page = format!(
+        "{}Peer review/{}/archive{archive}",
+        site.prefix(PROJECT),
+        reviewed.as_deref().unwrap_or(subject)
+    );
+
+    let last = last_edited(client, ENWIKI_API, &review_page).await?;
+    if Utc::now() - last < Duration::days(INACTIVE_DAYS) {
+        info!("[[{review_page}]] is still active");
+        return Ok(());
+    }
+
+    info!("Archiving [[{review_page}]], last edited {last}");
+
+    // close the review itself
+    let text = fetch_content(client, ENWIKI_API, &review_page).await?;
+    if !text.contains("PR/archive") {
+        save(
+            client,
+            PageSpec::Title(review_page.clone()),
+            Some(&text),
+            format!("{ARCHIVE_TOP}\n{}\n{ARCHIVE_BOTTOM}", text.trim()),
+            SUMMARY,
+            None,
+            &FLAGS,
+        )
+        .await?;
+    }
+
+    // add it to this month's archive list
+    let now = Utc::now();
+    let list = format!(
+        "{}Peer review/Archive/{}",
+        site.prefix(PROJECT),
+        now.format("%B %Y")
+    );
+    // a list that doesn't exist yet is started, but one that can't be read
+    // mustn't be overwritten
+    let current = fetch_contents(client, ENWIKI_API, &[list.as_str()])
+        .await?
+        .pop();
+    let old = current.as_ref().map_or("", |c| c.text.as_str()).to_owned();
+    let entry = format!("* [[{review_page}|{subject}]]");
+    if !old.contains(&entry) {
+        let mut text = old.clone();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&entry);
+        save(
+            client,
+            PageSpec::Title(list),
+            Some(&old),
+            text,
+            format!("Adding [[{review_page}]]"),
+            current.map(|c| c.revid),
+            &FLAGS,
+        )
+        .await?;
+    }
+
+    // replace {{Peer review}} with {{Old peer review}} on the talk page
+    pr.set_name("Old peer review".to_owned())?;
+    pr.set_param("archive", &archive)?;
+    pr.set_param("date", &now.format("%B %-d, %Y").to_string())?;
+
+    let text = parsoid.transform_to_wikitext(&code).await?;
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        None,
+        text,
+        format!("{SUMMARY} ([[{review_page}]])"),
+        Some(rev as u32),
+        &FLAGS,
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn main() -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+
+        let talk = TALK.to_string();
+        let search = search_titles(&client, ENWIKI_API, "hastemplate:\"Peer review\"", &talk);
+        let titles = scope
+            .titles(async { search.await.wrap_err("searching") })
+            .await?;
+
+        info!("found {} open peer reviews", titles.len());
+
+        for title in titles {
+            let treated = || treat(&client, &parsoid, &site, &title);
+            policy::page(FLAGS.task, &title, treated).await?;
+        }
+
+        Ok(())
+    })
+    .await
+}
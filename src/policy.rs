@@ -0,0 +1,173 @@
+//! What a task does when treating a page fails, by the kind of error.
+//!
+//! [`ERROR_POLICY`] maps error classes to actions, under `default` and task
+//! names, e.g.
+//!
+//! ```json
+//! {
+//!     "default": {"timeout": {"retry": 2}, "archive_429": "abort"},
+//!     "param_migration": {"template_parse": "abort"}
+//! }
+//! ```
+//!
+//! The classes are `timeout`, `parsoid_5xx`, `archive_429`, `template_parse`,
+//! `edit_conflict` and `other`, and the actions `skip`, which counts the page
+//! as failed and goes on, `abort`, which ends the run, and `{"retry": n}`,
+//! which treats the page again up to `n` times before skipping it. A task
+//! uses its own entry, then `default`, then [`BUILTIN`]. Optional.
+//!
+//! A [shut off](crate::shutoff) bot always ends the run.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Report;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::job::ShutOff;
+use crate::{checkpoint, runs, timing, Result};
+
+pub const ERROR_POLICY: &str = "error-policy.json";
+
+/// Wait before treating a page again, multiplied by the attempt.
+const RETRY_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    Timeout,
+    /// A server error from Parsoid's REST API.
+    #[serde(rename = "parsoid_5xx")]
+    Parsoid5xx,
+    /// archive.org still saying 429 after backing off.
+    #[serde(rename = "archive_429")]
+    Archive429,
+    /// Parsoid's HTML not having the templates it should.
+    TemplateParse,
+    /// The page changed after it was fetched. Treating it again fetches the
+    /// new revision and makes the change to that.
+    EditConflict,
+    Other,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Retry(u32),
+    Skip,
+    Abort,
+}
+
+/// Actions used unless configured otherwise, like before there was a
+/// policy, but giving servers that didn't answer another chance.
+pub const BUILTIN: &[(ErrorClass, Action)] = &[
+    (ErrorClass::Timeout, Action::Retry(2)),
+    (ErrorClass::Parsoid5xx, Action::Retry(2)),
+    (ErrorClass::Archive429, Action::Skip),
+    (ErrorClass::TemplateParse, Action::Skip),
+    (ErrorClass::EditConflict, Action::Retry(3)),
+    (ErrorClass::Other, Action::Skip),
+];
+
+type Policies = HashMap<String, HashMap<ErrorClass, Action>>;
+
+static POLICIES: LazyLock<Result<Policies, String>> =
+    LazyLock::new(|| match checkpoint::read_config(ERROR_POLICY) {
+        Ok(Some(s)) => serde_json::from_str(&s).map_err(|e| format!("invalid {ERROR_POLICY}: {e}")),
+        Ok(None) => Ok(HashMap::new()),
+        Err(e) => Err(format!("{e:#}")),
+    });
+
+fn is_parsoid(url: Option<&reqwest::Url>) -> bool {
+    url.is_some_and(|u| u.path().contains("/rest_v1/") || u.path().contains("/rest.php/"))
+}
+
+fn is_archive(url: Option<&reqwest::Url>) -> bool {
+    url.and_then(|u| u.host_str())
+        .is_some_and(|h| h == "archive.org" || h.ends_with(".archive.org"))
+}
+
+fn classify_http(e: &reqwest::Error, parsoid: bool) -> Option<ErrorClass> {
+    if e.is_timeout() {
+        return Some(ErrorClass::Timeout);
+    }
+    let status = e.status()?;
+    if status.is_server_error() && (parsoid || is_parsoid(e.url())) {
+        Some(ErrorClass::Parsoid5xx)
+    } else if status.as_u16() == 429 && is_archive(e.url()) {
+        Some(ErrorClass::Archive429)
+    } else {
+        None
+    }
+}
+
+pub fn classify(e: &Report) -> ErrorClass {
+    for cause in e.chain() {
+        if cause.to_string().contains("editconflict") {
+            return ErrorClass::EditConflict;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(class) = classify_http(e, false) {
+                return class;
+            }
+        }
+        match cause.downcast_ref::<parsoid::Error>() {
+            Some(parsoid::Error::Http(e)) => {
+                if let Some(class) = classify_http(e, true) {
+                    return class;
+                }
+            }
+            Some(_) => return ErrorClass::TemplateParse,
+            None => {}
+        }
+    }
+    ErrorClass::Other
+}
+
+/// What `task` does on errors of `class`.
+pub fn action(task: &str, class: ErrorClass) -> Result<Action> {
+    let policies = POLICIES.as_ref().map_err(|e| eyre!("{e}"))?;
+    let configured = [task, "default"]
+        .iter()
+        .find_map(|name| policies.get(*name)?.get(&class).copied());
+    Ok(configured
+        .or_else(|| BUILTIN.iter().find(|(c, _)| *c == class).map(|(_, a)| *a))
+        .unwrap_or(Action::Skip))
+}
+
+/// Treats `title` with `treat`, [timing](timing::page) it, and handles a
+/// failure as the policy of `task` says. Returns `None` if the page was
+/// skipped, and fails if the run should end.
+pub async fn page<T, F>(task: &str, title: &str, mut treat: impl FnMut() -> F) -> Result<Option<T>>
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let e = match timing::page(title, treat()).await {
+            Ok(t) => return Ok(Some(t)),
+            Err(e) => e,
+        };
+        if e.chain().any(|cause| cause.is::<ShutOff>()) {
+            return Err(e);
+        }
+        let class = classify(&e);
+        match action(task, class)? {
+            Action::Retry(n) if attempt < n => {
+                attempt += 1;
+                warn!("failed to treat [[{title}]], trying again ({attempt} of {n}): {e}");
+                tokio::time::sleep(RETRY_WAIT * attempt).await;
+            }
+            Action::Abort => return Err(e.wrap_err(format!("treating [[{title}]] ({class:?})"))),
+            Action::Retry(_) | Action::Skip => {
+                runs::page_failed();
+                warn!("failed to treat [[{title}]]: {e}");
+                return Ok(None);
+            }
+        }
+    }
+}
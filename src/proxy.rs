@@ -0,0 +1,40 @@
+//! Proxies for outgoing HTTP requests.
+//!
+//! Parsoid and archive.org traffic can each go through their own proxy, set
+//! in `PARSOID_PROXY` and `ARCHIVE_PROXY` as a URL such as
+//! `http://proxy:3128` or `socks5h://localhost:1080`, or `none` to connect
+//! directly. When a variable is unset, reqwest's usual `HTTPS_PROXY` and
+//! `ALL_PROXY` apply. The wiki API client is built by the `wiki` crate and
+//! only follows those.
+
+use std::env;
+
+use color_eyre::eyre::Context;
+use reqwest::{ClientBuilder, Proxy};
+
+use crate::Result;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Upstream {
+    Parsoid,
+    Archive,
+}
+
+impl Upstream {
+    fn var(self) -> &'static str {
+        match self {
+            Upstream::Parsoid => "PARSOID_PROXY",
+            Upstream::Archive => "ARCHIVE_PROXY",
+        }
+    }
+}
+
+/// Sets up `builder` to use the proxy configured for `upstream`.
+pub fn configure(builder: ClientBuilder, upstream: Upstream) -> Result<ClientBuilder> {
+    let var = upstream.var();
+    Ok(match env::var(var) {
+        Ok(url) if url.trim() == "none" => builder.no_proxy(),
+        Ok(url) => builder.proxy(Proxy::all(url.trim()).with_context(|| format!("parsing {var}"))?),
+        Err(_) => builder,
+    })
+}
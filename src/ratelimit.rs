@@ -0,0 +1,167 @@
+//! Backing off when a server says we are making requests too quickly.
+//!
+//! Two signals are handled here: HTTP 429 responses, from MediaWiki or
+//! archive.org, which are retried after their `Retry-After`; and MediaWiki's
+//! `ratelimited` API error, which arrives with a 200 and no hint of how long
+//! to wait. Both are about our own request rate, unlike maxlag, which is about
+//! the database replicas and is handled in [`maxlag`](crate::maxlag).
+//!
+//! Backoff is kept per host: a 429 from archive.org holds up later requests to
+//! archive.org, but not to the wikis. Hosts in [`HOST_INTERVALS`] are also
+//! paced, instead of each task sleeping before every request.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{health, Result};
+
+/// Attempts after the first before giving up.
+const MAX_RETRIES: u32 = 3;
+
+/// Used when a 429 has no usable `Retry-After`.
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+/// Longest `Retry-After` we are willing to honour.
+const MAX_WAIT: Duration = Duration::from_secs(600);
+
+/// Wait after the first `ratelimited` error, doubled on each retry.
+const RATELIMITED_WAIT: Duration = Duration::from_secs(60);
+
+/// Least time between two requests to these hosts.
+const HOST_INTERVALS: &[(&str, Duration)] = &[("web.archive.org", Duration::from_secs(2))];
+
+/// When each host may next be sent a request.
+static NOT_BEFORE: LazyLock<Mutex<HashMap<String, Instant>>> = LazyLock::new(Default::default);
+
+static TOO_MANY_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static RATELIMITED: AtomicU64 = AtomicU64::new(0);
+
+/// How many times each kind of backoff has triggered, as
+/// `(http_429, ratelimited)`.
+pub fn counts() -> (u64, u64) {
+    (
+        TOO_MANY_REQUESTS.load(Ordering::Relaxed),
+        RATELIMITED.load(Ordering::Relaxed),
+    )
+}
+
+pub fn log_counts() {
+    let (http, mw) = counts();
+    if http + mw > 0 {
+        info!("backed off {http} time(s) on HTTP 429 and {mw} time(s) on ratelimited errors");
+    }
+}
+
+/// The wait asked for by a `Retry-After` header, in either of its forms.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Waits until `host` may be sent a request, and takes the slot.
+async fn wait_turn(host: &str) {
+    let interval = HOST_INTERVALS
+        .iter()
+        .find(|(h, _)| *h == host)
+        .map_or(Duration::ZERO, |(_, i)| *i);
+    let at = {
+        let mut not_before = NOT_BEFORE.lock().unwrap();
+        let now = Instant::now();
+        let at = not_before.get(host).map_or(now, |&t| t.max(now));
+        not_before.insert(host.to_owned(), at + interval);
+        at
+    };
+    tokio::time::sleep_until(at).await;
+}
+
+/// Holds up requests to `host` for `wait`.
+fn back_off(host: &str, wait: Duration) {
+    let until = Instant::now() + wait;
+    let mut not_before = NOT_BEFORE.lock().unwrap();
+    let t = not_before.entry(host.to_owned()).or_insert(until);
+    *t = (*t).max(until);
+}
+
+/// Sends `req` once its host's backoff and pacing allow. Returns the host
+/// along with the response.
+async fn execute(req: RequestBuilder) -> Result<(String, Response)> {
+    let (client, request) = req.build_split();
+    let request = request?;
+    let host = request.url().host_str().unwrap_or_default().to_owned();
+    let is_api = request.url().path().ends_with("/api.php");
+    wait_turn(&host).await;
+    let resp = client.execute(request).await?;
+    if is_api && resp.status().is_success() {
+        health::api_contact();
+    }
+    Ok((host, resp))
+}
+
+/// Sends `req`, retrying after HTTP 429 responses. Requests with streaming
+/// bodies can't be retried and are sent once.
+pub async fn send(req: RequestBuilder) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let Some(retry) = req.try_clone().filter(|_| attempt < MAX_RETRIES) else {
+            return Ok(execute(req).await?.1);
+        };
+        let (host, resp) = execute(retry).await?;
+        if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(resp);
+        }
+        TOO_MANY_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        let wait = retry_after(resp.headers())
+            .unwrap_or(DEFAULT_WAIT)
+            .min(MAX_WAIT);
+        warn!(
+            "HTTP 429 from {}, holding off {host} for {wait:?}",
+            resp.url()
+        );
+        back_off(&host, wait);
+        attempt += 1;
+    }
+}
+
+pub fn is_ratelimited(e: &color_eyre::Report) -> bool {
+    e.chain()
+        .any(|cause| cause.to_string().contains("ratelimited"))
+}
+
+/// Runs `f`, retrying with a growing wait while it fails with MediaWiki's
+/// `ratelimited` error.
+pub async fn retry_ratelimited<T, F, Fut>(mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut wait = RATELIMITED_WAIT;
+    for _ in 0..MAX_RETRIES {
+        match f().await {
+            Err(e) if is_ratelimited(&e) => {
+                RATELIMITED.fetch_add(1, Ordering::Relaxed);
+                warn!("rate limited, retrying in {wait:?}");
+                tokio::time::sleep(wait).await;
+                wait *= 2;
+            }
+            res => return res,
+        }
+    }
+    f().await
+}
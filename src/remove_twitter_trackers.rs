@@ -1,340 +1,611 @@
 //! Removes twitter.com trackers in URLs.
+//!
+//! Pages are handled with the offline [`wikitext`] parser where possible, and
+//! through Parsoid otherwise.
 
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::sync::LazyLock;
 use std::time::Duration;
 
+use aho_corasick::AhoCorasick;
 use chrono::NaiveDateTime;
-use color_eyre::eyre::{eyre, ContextCompat};
-use fancy_regex::Regex;
+use color_eyre::eyre::{bail, eyre, ContextCompat};
+use futures_util::future::join_all;
 use futures_util::StreamExt;
 use kuchiki::traits::TendrilSink;
 use parsoid::WikinodeIterator;
-use reqwest::redirect::Policy;
+use regex::Regex;
+use serde::Deserialize;
 use tracing::{debug, info, warn};
 use url::Url;
-use wiki::api::QueryResponse;
-use wiki::req::search::{SearchGenerator, SearchInfo, SearchProp};
-use wiki::req::{Limit, PageSpec};
-
+use wiki::req::PageSpec;
+
+use crate::client::Client;
+use crate::dump::Outcome;
+use crate::edit::{check_dirty_diff, save, EditFlags, TaskFlags};
+use crate::large_pages::{is_large, log_skipped, Strategy};
+use crate::links::wayback_snapshots;
+use crate::timing::{self, Phase};
 use crate::{
-    check_nobots, parsoid_from_url, search_with_rev_ids, site_from_url, SearchResponseBody,
-    SearchResult,
+    accounts, check_nobots, check_nobots_wikitext, config, fetch_contents, http, policy, ratelimit,
+    reverts, run_lock, runs, search_with_rev_ids, selection, skip_list, template_name, wikitext,
+    Page, PageContent, Parsoid, ParsoidBackend,
 };
 
+const FLAGS: TaskFlags = TaskFlags::new("remove_twitter_trackers", EditFlags::MINOR_BOT);
+
 pub async fn main(site: &SiteCfg) -> color_eyre::Result<()> {
-    run(site).await?;
+    run(site).await?.log(site);
+    Ok(())
+}
+
+/// Runs on every site in `sites` at once. Each site has its own session and
+/// throttle, and a failure on one doesn't stop the others.
+pub async fn main_all(sites: &[SiteCfg]) -> color_eyre::Result<()> {
+    let runs = sites
+        .iter()
+        .map(|site| async move { (site, run(site).await) });
+    let mut failed = 0;
+    for (site, res) in join_all(runs).await {
+        match res {
+            Ok(report) => report.log(site),
+            Err(e) => {
+                warn!("run on {} failed: {e}", site.name);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} sites failed", sites.len());
+    }
     Ok(())
 }
 
+/// Outcome of a run on one site.
+#[derive(Default, Debug)]
+pub struct Report {
+    pub edited: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Report {
+    fn log(&self, site: &SiteCfg) {
+        info!(
+            "{}: {} edited, {} unchanged, {} failed",
+            site.name, self.edited, self.skipped, self.failed
+        );
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct EditMessage {
     pub links_fixed: usize,
     pub wayback_links_fixed: usize,
 }
 
+/// A site the task runs on.
+#[derive(Clone, Debug)]
 pub struct SiteCfg {
-    pub name: &'static str,
-    pub format: fn(EditMessage) -> String,
-    pub api_url: &'static str,
-    pub parsoid_url: &'static str,
+    pub name: String,
+    pub api_url: String,
+    pub parsoid: ParsoidBackend,
+    /// Token to use instead of the default one, if the file exists and the
+    /// task has no account in [`accounts`].
+    pub token_file: Option<String>,
+    /// The edit summary, a [template](config::fill) with the `{links}` fixed,
+    /// where `{wayback}` stands for [`wayback`](SiteCfg::wayback).
+    pub summary: String,
+    /// Put in the summary when archive links were fixed, a template with the
+    /// `{wayback_links}` fixed.
+    pub wayback: String,
 }
 
-macro_rules! pluralize {
-    ($x: expr) => {
-        if $x == 1 {
-            ""
-        } else {
-            "s"
-        }
-    };
+/// The task's options in [`config::SITES`].
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    summary: String,
+    #[serde(default)]
+    wayback: String,
 }
 
-pub static ENWIKI: SiteCfg = SiteCfg {
-    name: "English Wikipedia",
-    api_url: "https://en.wikipedia.org/w/api.php",
-    parsoid_url: "https://en.wikipedia.org/api/rest_v1",
-    format: |EditMessage {
-                 links_fixed,
-                 wayback_links_fixed,
-             }| {
-        let lpl = pluralize!(links_fixed);
-        let wpl = pluralize!(wayback_links_fixed);
-        let wayback = if wayback_links_fixed > 0 {
-            format!(", {wayback_links_fixed} archive link{wpl} fixed",)
+impl SiteCfg {
+    pub fn format(&self, msg: EditMessage) -> String {
+        let counts = [
+            ("links", msg.links_fixed),
+            ("wayback_links", msg.wayback_links_fixed),
+        ];
+        let wayback = if msg.wayback_links_fixed > 0 {
+            config::fill(&self.wayback, &counts)
         } else {
-            "".to_string()
+            String::new()
         };
+        config::fill(&self.summary, &counts).replace("{wayback}", &wayback)
+    }
+}
 
-        format!(
-            "Removing Twitter tracker params \
-            ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) \
-            ({links_fixed} link{lpl} fixed{wayback})"
-        )
-    },
-};
+pub static ENWIKI: LazyLock<SiteCfg> = LazyLock::new(|| SiteCfg {
+    name: "English Wikipedia".to_owned(),
+    api_url: "https://en.wikipedia.org/w/api.php".to_owned(),
+    parsoid: ParsoidBackend::RestBase,
+    token_file: Some("./token-enwiki.secret".to_owned()),
+    summary: "Removing Twitter tracker params \
+        ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) \
+        ({links} link{links_s} fixed{wayback})"
+        .to_owned(),
+    wayback: ", {wayback_links} archive link{wayback_links_s} fixed".to_owned(),
+});
 
-pub static ZHWIKI: SiteCfg = SiteCfg {
-    name: "Chinese Wikipedia",
-    api_url: "https://zh.wikipedia.org/w/api.php",
-    parsoid_url: "https://zh.wikipedia.org/api/rest_v1",
-    format: |EditMessage {
-                 links_fixed,
-                 wayback_links_fixed,
-             }| {
-        let wayback = if wayback_links_fixed != 0 {
-            format!("，同时修改{wayback_links_fixed}个存档链接")
-        } else {
-            String::new()
-        };
-        format!(
-            "BOT：已从{links_fixed}个Twitter外链删除追踪参数{wayback} \
+pub static ZHWIKI: LazyLock<SiteCfg> = LazyLock::new(|| SiteCfg {
+    name: "Chinese Wikipedia".to_owned(),
+    api_url: "https://zh.wikipedia.org/w/api.php".to_owned(),
+    parsoid: ParsoidBackend::RestBase,
+    token_file: Some("./token-zhwiki.secret".to_owned()),
+    summary: "BOT：已从{links}个Twitter外链删除追踪参数{wayback} \
         ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])"
-        )
-    },
-};
+        .to_owned(),
+    wayback: "，同时修改{wayback_links}个存档链接".to_owned(),
+});
+
+/// The sites the task runs on by key: [`ENWIKI`] as `en`, [`ZHWIKI`] as `zh`,
+/// and those in [`config::SITES`] with options for it, which replace the
+/// built-in ones they share a key with.
+fn by_key() -> color_eyre::Result<BTreeMap<String, SiteCfg>> {
+    let mut sites = BTreeMap::from([
+        ("en".to_owned(), ENWIKI.clone()),
+        ("zh".to_owned(), ZHWIKI.clone()),
+    ]);
+    for (key, site) in config::sites()? {
+        let Some(options) = site.task::<Options>(FLAGS.task)? else {
+            continue;
+        };
+        let site = SiteCfg {
+            name: site.name.clone(),
+            api_url: site.api_url.clone(),
+            parsoid: site.parsoid,
+            token_file: site.token_file.clone(),
+            summary: options.summary,
+            wayback: options.wayback,
+        };
+        sites.insert(key.clone(), site);
+    }
+    Ok(sites)
+}
+
+/// Every site the task runs on.
+pub fn sites() -> color_eyre::Result<Vec<SiteCfg>> {
+    Ok(by_key()?.into_values().collect())
+}
 
+/// The site with the key `key`.
+pub fn site(key: &str) -> color_eyre::Result<SiteCfg> {
+    let mut sites = by_key()?;
+    if let Some(site) = sites.remove(key) {
+        return Ok(site);
+    }
+    let keys: Vec<_> = sites.into_keys().collect();
+    bail!("no site {key}, only {}", keys.join(", "))
+}
+
+/// A tweet URL with a query, at the start of the text. [`find_urls`] finds
+/// them in a page.
 pub static RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r"(?<!\?url=|/|cache:)https?://(?:mobile\.)?(?:twitter|x)\.com/\w+/status/\d+\?[^\s}<|]+",
-    )
-    .unwrap()
+    Regex::new(r"^https?://(?:mobile\.)?(?:twitter|x)\.com/\w+/status/\d+\?[^\s}<|]+").unwrap()
 });
 
+/// The hosts of tweet URLs, found before trying [`RE`] so that most of a
+/// page is only scanned once.
+static HOSTS: LazyLock<AhoCorasick> =
+    LazyLock::new(|| AhoCorasick::new(["twitter.com/", "x.com/"]).unwrap());
+
+/// What comes before URLs that are part of another, like an archive's, which
+/// are left alone.
+const INSIDE_URL: &[&str] = &["?url=", "/", "cache:"];
+
 pub static WRE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
     r"https?://web\.archive\.org/web/([0-9]+)/(https?://(?:mobile\.)?(?:twitter|x)\.com/\w+/status/\d+(?:\?[^\s}<|]+)?)",
 ).unwrap()
 });
 
+/// Lines the task may change.
+static INTENDED: LazyLock<fancy_regex::Regex> =
+    LazyLock::new(|| fancy_regex::Regex::new(r"(?:twitter|x)\.com/|archive-date").unwrap());
+
 const SEARCH: &str =
     r"insource:/(twitter|x)\.com\/[a-zA-Z0-9]+\/status\/[0-9]+\/?\?([st]|cxt|ref_[a-z]+)=/";
 
-static BAD_PARAMS: &[&str] = &["cxt", "ref_src", "ref_url", "s", "t"];
+pub static BAD_PARAMS: &[&str] = &["cxt", "ref_src", "ref_url", "s", "t"];
 
-async fn treat(
-    site: &SiteCfg,
-    parsoid: &parsoid::Client,
+/// Removes the tracking parameters from `s`. The other parameters are kept
+/// as they were, in the same order, and URLs without trackers are returned
+/// unchanged.
+pub fn treat_url(s: &str) -> color_eyre::Result<String> {
+    let mut url = Url::parse(s)?;
+    let Some(query) = url.query() else {
+        return Ok(s.to_owned());
+    };
+    let pairs: Vec<_> = query.split('&').collect();
+    let kept: Vec<_> = pairs
+        .iter()
+        .copied()
+        .filter(|pair| {
+            form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .is_none_or(|(key, _)| !BAD_PARAMS.contains(&&*key))
+        })
+        .collect();
+    if kept.len() == pairs.len() {
+        return Ok(s.to_owned());
+    }
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&kept.join("&")));
+    }
+    Ok(url.into())
+}
+
+/// Where the scheme of a URL on the host found at `host` starts, if it is a
+/// tweet URL's.
+fn scheme_start(text: &str, host: usize) -> Option<usize> {
+    let before = &text[..host];
+    let before = before.strip_suffix("mobile.").unwrap_or(before);
+    let before = before.strip_suffix("://")?;
+    let before = before.strip_suffix('s').unwrap_or(before);
+    Some(before.strip_suffix("http")?.len())
+}
+
+/// The tweet URLs with queries in `text`, leaving out those that are part of
+/// another URL.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut found = Vec::new();
+    let mut end = 0;
+    for host in HOSTS.find_iter(text) {
+        let Some(start) = scheme_start(text, host.start()) else {
+            continue;
+        };
+        if start < end || INSIDE_URL.iter().any(|s| text[..start].ends_with(s)) {
+            continue;
+        }
+        if let Some(m) = RE.find(&text[start..]) {
+            end = start + m.end();
+            found.push(start..end);
+        }
+    }
+    found
+}
+
+/// Removes the trackers of the tweet URLs in `text`. Returns the new text and
+/// how many URLs changed.
+pub fn remove_trackers(text: &str) -> color_eyre::Result<(String, usize)> {
+    let mut newtext = text.to_owned();
+    let mut count = 0;
+    for range in find_urls(text).into_iter().rev() {
+        let url = &text[range.clone()];
+        let new_url = treat_url(url)?;
+        if new_url != url {
+            newtext.replace_range(range, &new_url);
+            count += 1;
+        }
+    }
+    Ok((newtext, count))
+}
+
+const CITATIONS: &[&str] = &["cite web", "cite tweet"];
+
+/// Finds a working snapshot of the tweet in `archive_url` without trackers.
+///
+/// Returns the new archive URL and its date.
+async fn fix_archive(
     client: &reqwest::Client,
-    wiki_client: &wiki::Bot,
-    mut page: SearchResult,
-) -> color_eyre::Result<()> {
-    fn treat_url(s: &str) -> color_eyre::Result<String> {
-        let mut url = Url::parse(s)?;
-        let mut s = form_urlencoded::Serializer::new(String::new());
-        let mut some = false;
-        for (key, value) in url.query_pairs() {
-            if !BAD_PARAMS.contains(&&*key) {
-                s.append_pair(&key, &value);
-                some = true;
+    archive_url: &str,
+) -> color_eyre::Result<Option<(String, String)>> {
+    let Some(captures) = WRE.captures(archive_url) else {
+        return Ok(None);
+    };
+    let timestamp = &captures[1];
+    let url = &captures[2];
+    let new_url = treat_url(url)?;
+    debug!(?url, ?new_url);
+    if new_url == url {
+        return Ok(None);
+    }
+
+    let timestamps = wayback_snapshots(client, &new_url).await?;
+
+    for new_timestamp in &timestamps {
+        // https://web.archive.org/web/20220624234724/https://twitter.com/MariahCarey/status/1314585670644641794
+        let actual_url = format!("https://web.archive.org/web/{new_timestamp}/{new_url}");
+        debug!(?timestamp, ?actual_url);
+
+        let res = async {
+            let get = ratelimit::send(client.get(&actual_url).timeout(Duration::from_secs(3)));
+            let text = timing::phase(Phase::External, get)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let html = kuchiki::parse_html().one(text);
+
+            let title = html
+                .select_first("title")
+                .map(|t| t.text_contents())
+                .map_err(|_| eyre!("title"))?;
+            if title.trim() == "Twitter" {
+                Err(eyre!("buggy url"))?;
             }
+
+            let _ = html
+                .select_first("[aria-label=\"Timeline: Conversation\"]")
+                .or_else(|_| html.select_first(".tweet[data-tweet-stat-initialized=\"true\"]"))
+                .map_err(|_| eyre!("main content"))?;
+
+            let time = WRE
+                .captures(&actual_url)
+                .and_then(|c| c.get(1))
+                .context("url should match regex")?
+                .as_str();
+
+            let time = NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S")?;
+            color_eyre::Result::<String>::Ok(time.format("%Y-%m-%d").to_string())
         }
-        if some {
-            url.set_query(Some(&s.finish()));
-        } else {
-            url.set_query(None);
+        .await;
+
+        match res {
+            Ok(date) => return Ok(Some((actual_url, date))),
+            Err(e) => {
+                debug!("did not fix: {}", e.to_string());
+            }
         }
-        Ok(url.into())
     }
 
-    let rev = page.revisions.pop().unwrap();
-    let page_id = page.pageid;
-    let rev_id = rev.revid;
-
-    let mut edit_msg = EditMessage::default();
+    Ok(None)
+}
 
-    debug!(?page);
+/// Fixes archive links using [`wikitext`], without asking Parsoid.
+///
+/// Returns `None` if the bot may not edit the page.
+async fn fix_archives_offline(
+    client: &reqwest::Client,
+    text: &str,
+    edit_msg: &mut EditMessage,
+) -> color_eyre::Result<Option<String>> {
+    if check_nobots_wikitext(text) {
+        return Ok(None);
+    }
+    let mut edits = Vec::new();
+    for template in wikitext::parse_templates(text)? {
+        if !CITATIONS.contains(&&*template.normalized_name()) {
+            continue;
+        }
+        let Some(param) = template.param("archive-url") else {
+            continue;
+        };
+        match fix_archive(client, &param.value).await {
+            Ok(Some((url, date))) => {
+                edits.push(template.set_param(text, "archive-url", &url));
+                edits.push(template.set_param(text, "archive-date", &date));
+                edit_msg.wayback_links_fixed += 1;
+            }
+            Ok(None) => {}
+            Err(e) => info!("did not fix archive: {e}"),
+        }
+    }
+    Ok(Some(wikitext::apply(text, edits)))
+}
 
+/// Fixes archive links through Parsoid, for pages [`wikitext`] can't handle.
+async fn fix_archives_parsoid(
+    parsoid: &Parsoid,
+    client: &reqwest::Client,
+    title: &str,
+    rev_id: u32,
+    edit_msg: &mut EditMessage,
+) -> color_eyre::Result<Option<String>> {
     let code = parsoid
-        .get_revision(&page.title, rev_id as u64)
+        .get_revision(title, rev_id as u64)
         .await?
         .into_mutable();
     for template in code.filter_templates()? {
         if check_nobots(&template) {
-            return Ok(());
+            return Ok(None);
         }
-
-        let edit_msg = &mut edit_msg;
-        let re: color_eyre::Result<()> = async move {
-            let name = template.name().to_lowercase();
-            if name != "template:cite web" && name != "template:cite tweet" {
-                return Ok(());
-            }
-            let Some(param) = template.param("archive-url") else {
-                return Ok(());
-            };
-            let Some(captures) = WRE.captures(&param)? else {
-                return Ok(());
-            };
-            let timestamp = &captures[1];
-            let url = &captures[2];
-            let new_url = treat_url(url)?;
-            debug!(?url, ?new_url);
-            if new_url == url {
-                return Ok(());
-            }
-
-            // https://web.archive.org/web/timemap/?url=https://twitter.com/MariahCarey/status/1314585670644641794&collapse=timestamp&fl=timestamp
-            let url = Url::parse_with_params(
-                "https://web.archive.org/web/timemap/",
-                [
-                    ("url", &*new_url),
-                    ("collapse", "timestamp"),
-                    ("fl", "timestamp"),
-                ],
-            )?;
-            let resp = client
-                .get(url)
-                .timeout(Duration::from_secs(3))
-                .send()
-                .await?;
-            debug!(?resp);
-            let resp = resp.error_for_status()?;
-            let timestamps = resp.text().await?;
-
-            for new_timestamp in timestamps.lines() {
-                // https://web.archive.org/web/20220624234724/https://twitter.com/MariahCarey/status/1314585670644641794
-                let actual_url = format!("https://web.archive.org/web/{new_timestamp}/{new_url}");
-                debug!(?timestamp, ?actual_url);
-
-                let res = async {
-                    // prevent spamming archive.org
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    let text = client
-                        .get(&actual_url)
-                        .timeout(Duration::from_secs(3))
-                        .send()
-                        .await?
-                        .error_for_status()?
-                        .text()
-                        .await?;
-                    let html = kuchiki::parse_html().one(text);
-
-                    let title = html
-                        .select_first("title")
-                        .map(|t| t.text_contents())
-                        .map_err(|_| eyre!("title"))?;
-                    if title.trim() == "Twitter" {
-                        Err(eyre!("buggy url"))?;
-                    }
-
-                    let _ = html
-                        .select_first("[aria-label=\"Timeline: Conversation\"]")
-                        .or_else(|_| {
-                            html.select_first(".tweet[data-tweet-stat-initialized=\"true\"]")
-                        })
-                        .map_err(|_| eyre!("main content"))?;
-
-                    let time = WRE
-                        .captures(&actual_url)?
-                        .and_then(|c| c.get(1))
-                        .context("url should match regex")?
-                        .as_str();
-
-                    let time = NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S")?;
-
-                    let date = time.format("%Y-%m-%d");
-                    template.set_param("archive-url", &actual_url).unwrap();
-                    template
-                        .set_param("archive-date", &date.to_string())
-                        .unwrap();
-                    color_eyre::Result::<()>::Ok(())
-                }
-                .await;
-
-                match res {
-                    Ok(()) => {
-                        edit_msg.wayback_links_fixed += 1;
-                        break;
-                    }
-                    Err(e) => {
-                        debug!("did not fix: {}", e.to_string());
-                    }
-                }
+        if !CITATIONS.contains(&&*template_name(&template)) {
+            continue;
+        }
+        let Some(param) = template.param("archive-url") else {
+            continue;
+        };
+        match fix_archive(client, &param).await {
+            Ok(Some((url, date))) => {
+                template.set_param("archive-url", &url)?;
+                template.set_param("archive-date", &date)?;
+                edit_msg.wayback_links_fixed += 1;
             }
-
-            Ok(())
+            Ok(None) => {}
+            Err(e) => info!("did not fix archive: {e}"),
         }
-        .await;
+    }
+    Ok(Some(parsoid.transform_to_wikitext(&code).await?))
+}
 
-        if let Err(e) = re {
-            info!("did not fix archive: {e}");
+/// What [`treat`] would do with `text`, without going online. Archive links
+/// are left alone, since checking them needs the Wayback Machine.
+pub fn simulate(text: &str) -> color_eyre::Result<Outcome> {
+    if check_nobots_wikitext(text) {
+        return Ok(Outcome::Skipped("nobots"));
+    }
+    if let Err(e) = wikitext::parse_templates(text) {
+        if !is_large(text) {
+            debug!("would fall back to parsoid: {e}");
+            return Ok(Outcome::Online("parsoid fallback"));
+        }
+        if Strategy::from_env() == Strategy::Skip {
+            return Ok(Outcome::Skipped("too large"));
         }
     }
+    let (_, links_fixed) = remove_trackers(text)?;
+    Ok(match links_fixed {
+        0 => Outcome::Unchanged,
+        n => Outcome::Changed(n),
+    })
+}
 
-    let text = parsoid.transform_to_wikitext(&code).await?;
-    let mut newtext = text.clone();
+/// Returns whether the page was edited. `prefetched` is the page's content if
+/// it was fetched with others, and is fetched here otherwise.
+async fn treat(
+    site: &SiteCfg,
+    parsoid: &Parsoid,
+    client: &reqwest::Client,
+    wiki_client: &Client,
+    page: &Page,
+    prefetched: Option<&PageContent>,
+) -> color_eyre::Result<bool> {
+    debug!(?page);
+    let page_id = page.pageid;
+    // saved by page ID, which neither the skip list nor reverts can be checked against
+    if skip_list::skips(FLAGS.task, &page.title) {
+        runs::skipped("skip list");
+        return Ok(false);
+    }
+    if reverts::reverted(&page.title) {
+        runs::skipped("reverted");
+        return Ok(false);
+    }
 
-    let matches: Vec<_> = RE.find_iter(&text).collect();
-    for m in matches.into_iter().rev() {
-        let m = m?;
-        let new_url = treat_url(m.as_str())?;
+    let fetched;
+    let content = match prefetched {
+        Some(content) => content,
+        None => {
+            let Some(content) = wiki_client
+                .call(|bot| {
+                    let title = page.title.clone();
+                    async move { fetch_contents(&bot, &site.api_url, &[title.as_str()]).await }
+                })
+                .await?
+                .pop()
+            else {
+                return Ok(false);
+            };
+            fetched = content;
+            &fetched
+        }
+    };
+    let rev_id = content.revid;
 
-        if new_url != m.as_str() {
-            // yay!
-            newtext.replace_range(m.range(), &new_url);
-            edit_msg.links_fixed += 1;
+    let mut edit_msg = EditMessage::default();
+    let text = match fix_archives_offline(client, &content.text, &mut edit_msg).await {
+        Ok(text) => text,
+        Err(e) if is_large(&content.text) => match Strategy::from_env() {
+            Strategy::Skip => {
+                debug!("can't fix archives on {} offline: {e}", page.title);
+                log_skipped(&page.title, &content.text)?;
+                return Ok(false);
+            }
+            Strategy::Wikitext => {
+                debug!("only fixing links on {}: {e}", page.title);
+                edit_msg = EditMessage::default();
+                (!check_nobots_wikitext(&content.text)).then(|| content.text.clone())
+            }
+        },
+        Err(e) => {
+            debug!("falling back to parsoid for {}: {e}", page.title);
+            edit_msg = EditMessage::default();
+            fix_archives_parsoid(parsoid, client, &page.title, rev_id, &mut edit_msg).await?
         }
-    }
+    };
+    let Some(text) = text else {
+        return Ok(false);
+    };
+
+    let (newtext, links_fixed) = remove_trackers(&text)?;
+    edit_msg.links_fixed += links_fixed;
 
     debug!(?edit_msg);
     if edit_msg.links_fixed + edit_msg.wayback_links_fixed > 0 {
+        check_dirty_diff(&page.title, &content.text, &newtext, &INTENDED)?;
+        let summary = site.format(edit_msg);
         wiki_client
-            .build_edit(PageSpec::PageId(page_id))
-            .text(newtext)
-            .summary((site.format)(edit_msg))
-            .baserevid(rev_id)
-            .minor()
-            .bot()
-            .send()
+            .call(|bot| {
+                let text = newtext.clone();
+                let summary = summary.clone();
+                let old = content.text.as_str();
+                async move {
+                    save(
+                        &bot,
+                        PageSpec::PageId(page_id),
+                        Some(old),
+                        text,
+                        summary,
+                        Some(rev_id),
+                        &FLAGS,
+                    )
+                    .await?;
+                    Ok(())
+                }
+            })
             .await?;
 
-        // TODO remove this
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        return Ok(true);
     }
 
-    Ok(())
+    Ok(false)
 }
 
-async fn run(site: &SiteCfg) -> color_eyre::Result<()> {
+async fn run(site: &SiteCfg) -> color_eyre::Result<Report> {
     info!("Running on {}", site.name);
 
-    let client = site_from_url(site.api_url).await?;
-    let parsoid = parsoid_from_url(site.parsoid_url)?;
-
-    let c = reqwest::Client::builder()
-        .redirect(Policy::none())
-        .timeout(Duration::from_secs(5))
-        .build()?;
-
-    let mut stream = search_with_rev_ids(
-        &client,
-        SearchGenerator {
-            search: SEARCH.into(),
-            namespace: Some("0".into()),
-            limit: Limit::Value(20), // content too big
-            offset: None,
-            info: SearchInfo::empty(),
-            prop: SearchProp::empty(),
-            // search: "7YzahhfuteRXHs5EtZcP".into(),
-            // namespace: "2".into(),
-        },
-    )
-    .boxed();
-
-    while let Some(it) = stream.next().await {
-        let it = it?;
-        let Ok(res): Result<QueryResponse<SearchResponseBody>, _> = serde_json::from_value(it)
-        else {
-            warn!("stream ended?");
-            break;
-        };
-        for page in res.query.pages {
-            let title = page.title.clone();
-            if let Err(e) = treat(site, &parsoid, &c, &client, page).await {
-                warn!("failed to treat {title}: {e}");
+    // an account of the task's own comes before the site's
+    let token_file = accounts::token_file(&FLAGS)?.or(site.token_file.as_deref());
+    let client = Client::with_token_file(&site.api_url, token_file).await?;
+    let parsoid = Parsoid::new(&site.api_url, site.parsoid)?;
+
+    let bot = client.bot().await;
+    // each site has its own lock, so that runs on different sites can overlap
+    let host = Url::parse(&site.api_url)?
+        .host_str()
+        .unwrap_or_default()
+        .to_owned();
+    let task = format!("{}-{host}", FLAGS.task);
+    run_lock::locked(&bot, &site.api_url, &task, async {
+        let scope = selection::get().scope(&bot, &site.api_url).await?;
+        let c = http()?.archive.clone();
+
+        // content too big for more at once
+        let search = search_with_rev_ids(&bot, &site.api_url, SEARCH, "0", 20);
+        // a search batch at a time, so that their contents are fetched together
+        let mut batches = scope.pages(&bot, &site.api_url, search).chunks(20);
+
+        let mut report = Report::default();
+        while let Some(batch) = batches.next().await {
+            let pages = batch.into_iter().collect::<color_eyre::Result<Vec<_>>>()?;
+            let titles: Vec<_> = pages.iter().map(|p| p.title.as_str()).collect();
+            let fetched = client
+                .call(|bot| {
+                    let titles = titles.clone();
+                    async move { fetch_contents(&bot, &site.api_url, &titles).await }
+                })
+                .await;
+            let contents = fetched.unwrap_or_else(|e| {
+                warn!("failed to fetch batch starting at [[{}]]: {e}", titles[0]);
+                Vec::new()
+            });
+            for page in &pages {
+                // only for the first try, a retry needs the latest revision
+                let mut prefetched = contents.iter().find(|c| c.pageid == page.pageid);
+                let treated = || treat(site, &parsoid, &c, &client, page, prefetched.take());
+                match policy::page(FLAGS.task, &page.title, treated).await? {
+                    Some(true) => report.edited += 1,
+                    Some(false) => report.skipped += 1,
+                    None => report.failed += 1,
+                }
             }
         }
-    }
 
-    Ok(())
+        Ok(report)
+    })
+    .await
 }
@@ -0,0 +1,207 @@
+//! Pages where someone reverted an edit of the bot, which it doesn't edit
+//! again: bot policy expects a reverted change to be discussed, not put back
+//! by the next run.
+//!
+//! When a task starts, the bot's edits of the last [`WINDOW_DAYS`] days from
+//! the [`audit`] log are looked up on its wiki. Those MediaWiki has tagged as
+//! reverted, by anyone but a bot, are recorded in [`REVERTED`] in the [`state`]
+//! store, so the page stays skipped even after the edit ages out.
+//! [`edit::pre_save`](crate::edit::pre_save) doesn't save recorded pages, and
+//! counts them as skipped in the run's report.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::selection::normalize;
+use crate::{audit, query_batch, query_raw, runs, state, Result};
+
+const REVERTED: &str = "reverted.jsonl";
+
+/// How far back edits are looked up. MediaWiki only tags edits reverted
+/// within a few edits of them, so older ones rarely are.
+const WINDOW_DAYS: i64 = 30;
+
+/// Tags of edits that revert others.
+const REVERT_TAGS: [&str; 3] = ["mw-undo", "mw-rollback", "mw-manual-revert"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Reverted {
+    pub at: DateTime<Utc>,
+    /// The host of the wiki, e.g. `en.wikipedia.org`.
+    pub wiki: String,
+    pub title: String,
+    /// The edit of the bot that was reverted.
+    pub revid: u64,
+    pub by: String,
+}
+
+tokio::task_local! {
+    /// Recorded titles on the wiki the current task runs on.
+    static PAGES: HashSet<String>;
+}
+
+fn tags(rev: &Value) -> impl Iterator<Item = &str> {
+    rev["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// Who reverted `revid` of `title`, unless it was a bot.
+async fn reverter(
+    client: &wiki::Bot,
+    api_url: &str,
+    title: &str,
+    revid: u64,
+) -> Result<Option<String>> {
+    let revid = revid.to_string();
+    let (later, _) = query_batch(
+        client,
+        api_url,
+        &[
+            ("prop", "revisions"),
+            ("titles", title),
+            ("rvstartid", &revid),
+            ("rvdir", "newer"),
+            ("rvprop", "ids|user|tags"),
+            ("rvlimit", "50"),
+        ],
+        &[],
+    )
+    .await?;
+    let by = later["pages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|p| p["revisions"].as_array().into_iter().flatten())
+        .find(|rev| tags(rev).any(|t| REVERT_TAGS.contains(&t)))
+        .and_then(|rev| rev["user"].as_str());
+    let Some(by) = by else {
+        return Ok(None);
+    };
+    let (users, _) = query_batch(
+        client,
+        api_url,
+        &[("list", "users"), ("ususers", by), ("usprop", "groups")],
+        &[],
+    )
+    .await?;
+    let bot = users["users"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|u| u["groups"].as_array().into_iter().flatten())
+        .any(|g| g == "bot");
+    Ok((!bot).then(|| by.to_owned()))
+}
+
+/// The bot's recent edits on `wiki` that were reverted, leaving out pages in
+/// `known`.
+async fn find(
+    client: &wiki::Bot,
+    api_url: &str,
+    wiki: &str,
+    known: &HashSet<String>,
+) -> Result<Vec<Reverted>> {
+    let since = Utc::now() - TimeDelta::days(WINDOW_DAYS);
+    // the log is oldest first, so the last edit of each page wins
+    let mut latest = HashMap::new();
+    for edit in audit::history()? {
+        if edit.at < since || edit.wiki.as_deref() != Some(wiki) || known.contains(&edit.title) {
+            continue;
+        }
+        if let Some(revid) = edit.revid {
+            latest.insert(edit.title, revid);
+        }
+    }
+    let revids: Vec<_> = latest.values().map(u64::to_string).collect();
+    let mut found = Vec::new();
+    for chunk in revids.chunks(50) {
+        let batches = query_raw(
+            client,
+            api_url,
+            &[
+                ("prop", "revisions"),
+                ("revids", &chunk.join("|")),
+                ("rvprop", "ids|tags"),
+            ],
+        )
+        .await?;
+        for page in batches
+            .iter()
+            .flat_map(|b| b["pages"].as_array().into_iter().flatten())
+        {
+            let title = page["title"].as_str().unwrap_or_default();
+            for rev in page["revisions"].as_array().into_iter().flatten() {
+                let Some(revid) = rev["revid"].as_u64() else {
+                    continue;
+                };
+                if latest.get(title) != Some(&revid) || !tags(rev).any(|t| t == "mw-reverted") {
+                    continue;
+                }
+                if let Some(by) = reverter(client, api_url, title, revid).await? {
+                    found.push(Reverted {
+                        at: Utc::now(),
+                        wiki: wiki.to_owned(),
+                        title: title.to_owned(),
+                        revid,
+                        by,
+                    });
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Runs `run` leaving alone the pages on the wiki at `api_url` where an edit
+/// of the bot was reverted, after looking for new ones. Failing to look is
+/// only logged, and the pages recorded before are still left alone.
+pub async fn avoided<T>(client: &wiki::Bot, api_url: &str, run: impl Future<Output = T>) -> T {
+    let wiki = Url::parse(api_url)
+        .ok()
+        .and_then(|url| Some(url.host_str()?.to_owned()))
+        .unwrap_or_default();
+    let mut pages: HashSet<_> = match state::read_all::<Reverted>(REVERTED) {
+        Ok(records) => records
+            .into_iter()
+            .filter(|r| r.wiki == wiki)
+            .map(|r| r.title)
+            .collect(),
+        Err(e) => {
+            warn!("failed to read the reverted pages: {e}");
+            HashSet::new()
+        }
+    };
+    match find(client, api_url, &wiki, &pages).await {
+        Ok(found) => {
+            for reverted in found {
+                info!(
+                    "[[{}]] was reverted by {}, not editing it again",
+                    reverted.title, reverted.by
+                );
+                runs::note(&format!("reverted [[{}]]", reverted.title), &reverted.by);
+                if let Err(e) = state::append(REVERTED, &reverted) {
+                    warn!("failed to record the revert of [[{}]]: {e}", reverted.title);
+                }
+                pages.insert(reverted.title);
+            }
+        }
+        Err(e) => warn!("failed to look for reverted edits: {e}"),
+    }
+    let pages = pages.iter().map(|t| normalize(t)).collect();
+    PAGES.scope(pages, run).await
+}
+
+/// Whether an edit of the bot to `title` was reverted.
+pub fn reverted(title: &str) -> bool {
+    let title = normalize(title);
+    PAGES.try_with(|p| p.contains(&title)).unwrap_or(false)
+}
@@ -0,0 +1,254 @@
+//! Keeping two copies of the same task from running at once, which would have
+//! them work through the same pages and edit each of them twice.
+//!
+//! The lock is a file, `<task>.lock`, in the working directory. That only
+//! helps when every copy runs on one host, so when `RUN_LOCK_PAGE` is set to a
+//! page prefix such as `User:DeadbeefBot/lock`, the lock is the page
+//! `<prefix>/<task>` on the wiki instead. Either way it holds the host and
+//! process that took it, and a lock whose process is gone (or, on the wiki,
+//! that is older than [`STALE_AFTER`]) is taken over with a warning.
+//!
+//! Both are taken in one step, so that copies started together can't both
+//! get the lock: the file is created only if it doesn't exist, or replaced by
+//! one copy at a time, and the page only if it doesn't exist or is still the
+//! revision that was read. The page is edited directly rather than saved like
+//! a task's edits, so that it is taken in `--dry-run` and `--list-only` runs
+//! too.
+
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::{bail, ContextCompat};
+use tracing::{info, warn};
+
+use crate::client::post_edit;
+use crate::{fetch_contents, reverts, skip_list, throttle, trial, Result};
+
+/// How long an on-wiki lock is honoured without its holder releasing it.
+const STALE_AFTER: TimeDelta = TimeDelta::hours(24);
+
+/// Who holds a lock, as written to it.
+struct Holder {
+    host: String,
+    pid: u32,
+    since: DateTime<Utc>,
+}
+
+impl Holder {
+    fn me() -> Holder {
+        let host = fs::read_to_string("/etc/hostname")
+            .ok()
+            .or_else(|| env::var("HOSTNAME").ok())
+            .map_or_else(|| "unknown".to_owned(), |h| h.trim().to_owned());
+        Holder {
+            host,
+            pid: process::id(),
+            since: Utc::now(),
+        }
+    }
+
+    fn parse(s: &str) -> Option<Holder> {
+        let mut parts = s.split_whitespace();
+        Some(Holder {
+            host: parts.next()?.to_owned(),
+            pid: parts.next()?.parse().ok()?,
+            since: DateTime::parse_from_rfc3339(parts.next()?)
+                .ok()?
+                .with_timezone(&Utc),
+        })
+    }
+
+    fn render(&self) -> String {
+        format!("{} {} {}", self.host, self.pid, self.since.to_rfc3339())
+    }
+
+    /// Whether the holder may still be running. Processes on other hosts
+    /// can't be checked, so only the age of the lock counts for them.
+    fn is_alive(&self) -> bool {
+        if self.host == Holder::me().host && cfg!(target_os = "linux") {
+            return fs::exists(format!("/proc/{}", self.pid)).unwrap_or(true);
+        }
+        Utc::now() - self.since < STALE_AFTER
+    }
+}
+
+pub enum RunLock {
+    File(PathBuf),
+    Page {
+        api_url: String,
+        title: String,
+        /// The revision that took the lock.
+        revid: u64,
+    },
+}
+
+/// Creates the lock file at `path` for `me`, failing if it exists.
+fn create_new(path: &Path, me: &Holder) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(me.render().as_bytes())
+}
+
+/// Creates the lock file at `path` for `task`, taking it over if its holder
+/// is gone.
+fn create_file(path: &Path, task: &str, me: &Holder) -> Result<()> {
+    loop {
+        match create_new(path, me) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        // empty while its creator is still writing to it
+        let text = fs::read_to_string(path).unwrap_or_default();
+        let Some(holder) = Holder::parse(&text) else {
+            bail!(
+                "{} is held, remove it if {task} isn't running",
+                path.display()
+            );
+        };
+        if holder.is_alive() {
+            bail!("{task} is already running as process {}", holder.pid);
+        }
+        if take_over(path, task, me, &text)? {
+            warn!("took over {} from process {}", path.display(), holder.pid);
+            return Ok(());
+        }
+    }
+}
+
+/// Replaces the lock file at `path` with one for `me`, if it still says
+/// `stale`. Copies that find the same stale lock would otherwise each remove
+/// it, maybe after another has already taken it again, so only the one that
+/// creates `<task>.lock.takeover` first goes on.
+fn take_over(path: &Path, task: &str, me: &Holder, stale: &str) -> Result<bool> {
+    let guard = path.with_extension("lock.takeover");
+    match create_new(&guard, me) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => bail!(
+            "{task} is being started by another process, remove {} if it isn't",
+            guard.display()
+        ),
+        Err(e) => return Err(e.into()),
+    }
+    let taken = (|| {
+        match fs::read_to_string(path) {
+            Ok(text) if text == stale => fs::remove_file(path)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            res => return res.map(|_| false),
+        }
+        // someone found it gone and created it meanwhile
+        match create_new(path, me) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            res => res.map(|()| true),
+        }
+    })();
+    fs::remove_file(&guard)?;
+    Ok(taken?)
+}
+
+/// Sets the lock page `title` on the wiki at `api_url` to `text`, if it is
+/// still at `baserevid`, or else doesn't exist. Returns the new revision.
+async fn edit_page(
+    client: &wiki::Bot,
+    api_url: &str,
+    title: &str,
+    text: &str,
+    summary: &str,
+    baserevid: Option<u64>,
+) -> Result<u64> {
+    let base = baserevid.map(|rev| rev.to_string());
+    let mut form = vec![
+        ("title", title),
+        ("text", text),
+        ("summary", summary),
+        ("minor", "1"),
+        ("bot", "1"),
+        ("assert", "bot"),
+    ];
+    match &base {
+        Some(base) => form.extend([("baserevid", base.as_str()), ("nocreate", "1")]),
+        None => form.push(("createonly", "1")),
+    }
+    let res = post_edit(client, api_url, &form).await?;
+    res["edit"]["newrevid"]
+        .as_u64()
+        .or_else(|| res["edit"]["oldrevid"].as_u64())
+        .with_context(|| format!("no revision for [[{title}]]"))
+}
+
+impl RunLock {
+    /// Takes the lock for `task` on the wiki at `api_url`, failing if another
+    /// copy holds it.
+    pub async fn acquire(client: &wiki::Bot, api_url: &str, task: &str) -> Result<RunLock> {
+        let me = Holder::me();
+        let Ok(prefix) = env::var("RUN_LOCK_PAGE") else {
+            let path = PathBuf::from(format!("{task}.lock"));
+            create_file(&path, task, &me)?;
+            return Ok(RunLock::File(path));
+        };
+
+        let title = format!("{prefix}/{task}");
+        let current = fetch_contents(client, api_url, &[title.as_str()])
+            .await?
+            .pop();
+        if let Some(holder) = current.as_ref().and_then(|c| Holder::parse(&c.text)) {
+            if holder.is_alive() {
+                bail!(
+                    "{task} is already running on {} since {}, see [[{title}]]",
+                    holder.host,
+                    holder.since
+                );
+            }
+            warn!("taking over [[{title}]] from {}", holder.host);
+        }
+        // an edit conflict, or the page existing, means someone else just
+        // took it
+        let base = current.map(|c| u64::from(c.revid));
+        let summary = format!("Locking {task}");
+        let revid = edit_page(client, api_url, &title, &me.render(), &summary, base).await?;
+        Ok(RunLock::Page {
+            api_url: api_url.to_owned(),
+            title,
+            revid,
+        })
+    }
+
+    pub async fn release(self, client: &wiki::Bot) -> Result<()> {
+        match &self {
+            RunLock::File(path) => fs::remove_file(path)?,
+            RunLock::Page {
+                api_url,
+                title,
+                revid,
+            } => {
+                edit_page(client, api_url, title, "", "Unlocking", Some(*revid)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `run` while holding the lock for `task`, releasing it afterwards
+/// whether or not `run` succeeded. Pages on the wiki's
+/// [`skip_list`](crate::skip_list), and those where an edit of the bot was
+/// [`reverts`](crate::reverts)ed, aren't saved meanwhile, and the edits of a
+/// [`trial`](crate::trial) are listed afterwards.
+pub async fn locked<T>(
+    client: &wiki::Bot,
+    api_url: &str,
+    task: &str,
+    run: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let lock = RunLock::acquire(client, api_url, task).await?;
+    info!("holding the run lock for {task}");
+    let run = throttle::on_wiki(api_url, trial::listed(client, run));
+    let run = reverts::avoided(client, api_url, run);
+    let res = skip_list::enforced(client, api_url, run).await;
+    if let Err(e) = lock.release(client).await {
+        warn!("failed to release the run lock for {task}: {e}");
+    }
+    res
+}
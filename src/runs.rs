@@ -0,0 +1,252 @@
+//! A history of runs in the [`state`] store, so that activity reports and BRFA
+//! follow-ups don't need the logs.
+//!
+//! Tasks count pages they fail on or skip with [`page_failed`] and
+//! [`skipped`]; edits are counted by [`edit::save`](crate::edit::save). The
+//! counters are shared, so the report of a run that overlapped another also
+//! has the other's pages. Anything else needed to reproduce a run, like a
+//! shuffle seed, goes in its [`note`]s.
+//!
+//! A [`checkpoint`](crate::checkpoint)ed run that is resumed records another
+//! report with the same ID, which [`history`] adds to the first.
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use color_eyre::eyre::{bail, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::edit::edit_count;
+use crate::{state, Result};
+
+const RUNS: &str = "runs.jsonl";
+
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static SKIPPED: LazyLock<Mutex<BTreeMap<&'static str, u64>>> = LazyLock::new(Default::default);
+static NOTES: LazyLock<Mutex<BTreeMap<String, String>>> = LazyLock::new(Default::default);
+
+/// Counts a page the task gave up on because of an error.
+pub fn page_failed() {
+    FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn failed_count() -> u64 {
+    FAILED.load(Ordering::Relaxed)
+}
+
+/// Counts a page left alone on purpose, e.g. for `{{nobots}}`.
+pub fn skipped(reason: &'static str) {
+    *SKIPPED.lock().unwrap().entry(reason).or_default() += 1;
+}
+
+/// Adds `key` to the report of the current run.
+pub fn note(key: &str, value: impl ToString) {
+    NOTES
+        .lock()
+        .unwrap()
+        .insert(key.to_owned(), value.to_string());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunReport {
+    pub id: String,
+    pub task: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub edits: u64,
+    pub failed: u64,
+    #[serde(default)]
+    pub skipped: BTreeMap<String, u64>,
+    /// Why the run ended early, if it did.
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub notes: BTreeMap<String, String>,
+}
+
+struct Counts {
+    edits: u64,
+    failed: u64,
+    skipped: BTreeMap<&'static str, u64>,
+}
+
+impl Counts {
+    fn now() -> Counts {
+        Counts {
+            edits: edit_count(),
+            failed: failed_count(),
+            skipped: SKIPPED.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// The ID of a run of `task` starting at `started`.
+pub fn run_id(task: &str, started: DateTime<Utc>) -> String {
+    format!("{task}-{}", started.format("%Y%m%dT%H%M%S"))
+}
+
+/// Runs `run` as a run of `task`, and records how it went.
+pub async fn recorded(
+    task: &str,
+    run: impl Future<Output = Result<()>>,
+) -> (RunReport, Result<()>) {
+    recorded_as(run_id(task, Utc::now()), task, run).await
+}
+
+/// Like [`recorded`], with the ID of the run given.
+pub async fn recorded_as(
+    id: String,
+    task: &str,
+    run: impl Future<Output = Result<()>>,
+) -> (RunReport, Result<()>) {
+    let started = Utc::now();
+    let before = Counts::now();
+    let res = run.await;
+    let after = Counts::now();
+    let report = RunReport {
+        id,
+        task: task.to_owned(),
+        started,
+        finished: Utc::now(),
+        edits: after.edits - before.edits,
+        failed: after.failed - before.failed,
+        skipped: after
+            .skipped
+            .iter()
+            .map(|(&reason, &n)| (reason, n - before.skipped.get(reason).unwrap_or(&0)))
+            .filter(|&(_, n)| n > 0)
+            .map(|(reason, n)| (reason.to_owned(), n))
+            .collect(),
+        error: res.as_ref().err().map(|e| format!("{e:#}")),
+        notes: mem::take(&mut *NOTES.lock().unwrap()),
+    };
+    if let Err(e) = state::append(RUNS, &report) {
+        warn!("failed to record the run: {e}");
+    }
+    (report, res)
+}
+
+impl RunReport {
+    /// Adds a resumed part of the run to its report.
+    fn append(&mut self, resumed: RunReport) {
+        self.finished = resumed.finished;
+        self.edits += resumed.edits;
+        self.failed += resumed.failed;
+        for (reason, n) in resumed.skipped {
+            *self.skipped.entry(reason).or_default() += n;
+        }
+        self.error = resumed.error;
+        self.notes.extend(resumed.notes);
+    }
+}
+
+/// Every run, oldest first, with resumed runs as one.
+pub fn history() -> Result<Vec<RunReport>> {
+    let mut runs: Vec<RunReport> = Vec::new();
+    let mut at = HashMap::new();
+    for run in state::read_all::<RunReport>(RUNS)? {
+        match at.get(&run.id) {
+            Some(&i) => runs[i].append(run),
+            None => {
+                at.insert(run.id.clone(), runs.len());
+                runs.push(run);
+            }
+        }
+    }
+    Ok(runs)
+}
+
+/// Parses a period such as `30d`, `12h` or `2w`.
+pub fn parse_period(s: &str) -> Result<TimeDelta> {
+    let (n, unit) = s.split_at(s.char_indices().last().map_or(0, |(i, _)| i));
+    let period: fn(i64) -> TimeDelta = match unit {
+        "h" => TimeDelta::hours,
+        "d" => TimeDelta::days,
+        "w" => TimeDelta::weeks,
+        _ => bail!("periods end in h, d or w, not {s}"),
+    };
+    let n = n.parse().with_context(|| format!("invalid period {s}"))?;
+    Ok(period(n))
+}
+
+#[derive(Default)]
+struct Totals {
+    runs: u64,
+    failed_runs: u64,
+    edits: u64,
+    failed: u64,
+    skipped: BTreeMap<String, u64>,
+}
+
+impl Totals {
+    fn add(&mut self, run: &RunReport) {
+        self.runs += 1;
+        self.failed_runs += u64::from(run.error.is_some());
+        self.edits += run.edits;
+        self.failed += run.failed;
+        for (reason, n) in &run.skipped {
+            *self.skipped.entry(reason.clone()).or_default() += n;
+        }
+    }
+
+    fn skips(&self) -> String {
+        let skips: Vec<_> = self
+            .skipped
+            .iter()
+            .map(|(reason, n)| format!("{reason} {n}"))
+            .collect();
+        skips.join(", ")
+    }
+}
+
+/// Prints edits per day, error rates and skip reasons by task, for runs of
+/// tasks whose names contain `task` that started in the last `since`.
+pub fn print_stats(task: Option<&str>, since: Option<TimeDelta>) -> Result<()> {
+    let cutoff = since.map(|since| Utc::now() - since);
+    let runs: Vec<_> = history()?
+        .into_iter()
+        .filter(|r| task.is_none_or(|t| r.task.contains(t)))
+        .filter(|r| cutoff.is_none_or(|c| r.started >= c))
+        .collect();
+    let first = runs
+        .iter()
+        .map(|r| r.started)
+        .min()
+        .context("no runs recorded")?;
+
+    let mut by_task: BTreeMap<&str, (Totals, BTreeMap<NaiveDate, Totals>)> = BTreeMap::new();
+    for run in &runs {
+        let (totals, days) = by_task.entry(run.task.as_str()).or_default();
+        totals.add(run);
+        days.entry(run.started.date_naive()).or_default().add(run);
+    }
+
+    let days = ((Utc::now() - cutoff.unwrap_or(first)).num_hours() as f64 / 24.0).max(1.0);
+    for (task, (totals, by_day)) in by_task {
+        let pages = totals.edits + totals.failed + totals.skipped.values().sum::<u64>();
+        let error_rate = 100.0 * totals.failed as f64 / pages.max(1) as f64;
+        println!(
+            "{task}: {} run(s), {} failed; {} edit(s), {:.1}/day; {error_rate:.1}% of pages failed",
+            totals.runs,
+            totals.failed_runs,
+            totals.edits,
+            totals.edits as f64 / days,
+        );
+        if !totals.skipped.is_empty() {
+            println!("  skipped: {}", totals.skips());
+        }
+        for (day, t) in by_day {
+            println!(
+                "  {day}  {:>5} edit(s)  {:>4} failed  {}",
+                t.edits,
+                t.failed,
+                t.skips()
+            );
+        }
+    }
+    Ok(())
+}
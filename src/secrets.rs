@@ -0,0 +1,38 @@
+//! Where secrets like OAuth tokens are read from, so that deployments don't
+//! need them in the working directory.
+//!
+//! A secret file is looked for in `$CREDENTIALS_DIRECTORY`, where systemd
+//! puts credentials given with `LoadCredential=`, then in [`MOUNTED`], where
+//! Docker and Kubernetes put secrets, and only then at its path. The first
+//! two go by the file name alone, so `./token-enwiki.secret` can be loaded as
+//! a credential named `token-enwiki.secret`.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use color_eyre::eyre::{bail, Context};
+
+use crate::Result;
+
+const MOUNTED: &str = "/run/secrets";
+
+/// Where the secret file `path` is, if anywhere.
+pub fn find(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let dirs = env::var_os("CREDENTIALS_DIRECTORY")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from(MOUNTED)]);
+    let stored = path
+        .file_name()
+        .and_then(|name| dirs.map(|dir| dir.join(name)).find(|p| p.is_file()));
+    stored.or_else(|| path.exists().then(|| path.to_owned()))
+}
+
+/// The contents of the secret file `path`, wherever it is.
+pub fn read(path: &str) -> Result<String> {
+    let Some(found) = find(path) else {
+        bail!("no secret {path}");
+    };
+    fs::read_to_string(&found).with_context(|| format!("reading {}", found.display()))
+}
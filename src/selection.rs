@@ -0,0 +1,346 @@
+//! Narrowing a run down to some pages from the command line, for looking into
+//! a reported problem without running over everything.
+//!
+//! `--page <title>`, which can be repeated, makes a task treat those pages
+//! instead of the ones its source finds. `--file <path>` does the same for
+//! every title in a file, one per line, like PetScan's plain text export.
+//!
+//! `--namespace <names>` leaves only pages in those namespaces, given by name
+//! or number and separated by commas, like `--namespace Draft,118`.
+//!
+//! `--from <title>` and `--to <title>` leave only titles that sort from the
+//! first on and before the second, by their bytes and with any namespace
+//! prefix, so that `--to N` on one host and `--from N` on another split a
+//! list between them.
+//!
+//! `--offset <n>` skips the first pages and `--limit <n>` stops after that
+//! many, so that `--offset 400 --limit 200` treats the next 200 pages after a
+//! run that treated 400. Both count pages left after the other flags.
+//!
+//! A [`checkpoint`](crate::checkpoint)ed run that is resumed leaves out the
+//! pages it already treated, after `--offset` and `--limit`, so that these
+//! count the same pages as before.
+//!
+//! Tasks whose source is what makes a page eligible, like a database query
+//! for orphaned talk pages, only treat the given pages if their source finds
+//! them too. Reports aren't saved from narrowed runs, since they would only
+//! cover some pages.
+
+use std::collections::{BTreeSet, HashSet};
+use std::future::Future;
+use std::sync::OnceLock;
+
+use color_eyre::eyre::{bail, Context};
+use futures_util::stream::{self, LocalBoxStream};
+use futures_util::{future, Stream, StreamExt, TryStreamExt};
+
+use crate::siteinfo::SiteInfo;
+use crate::source::PageSource;
+use crate::{checkpoint, fetch_contents, Page, Result, Revision};
+
+static SELECTION: OnceLock<Selection> = OnceLock::new();
+
+#[derive(Default, Debug)]
+pub struct Selection {
+    /// Titles given with `--page` or listed in a `--file`.
+    pages: Vec<String>,
+    /// Namespaces given with `--namespace`, by name or number.
+    namespaces: Vec<String>,
+    /// Where the titles given with `--from` and `--to` start and end.
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    offset: usize,
+    /// Pages treated before a resumed run was interrupted.
+    done: HashSet<String>,
+}
+
+/// The flags that narrow a run, which all take a value.
+pub const FLAGS: &[&str] = &[
+    "--page",
+    "--file",
+    "--namespace",
+    "--from",
+    "--to",
+    "--limit",
+    "--offset",
+];
+
+fn count(flag: &str, value: &str) -> Result<usize> {
+    value
+        .parse()
+        .with_context(|| format!("{flag} takes a number, not {value}"))
+}
+
+/// Titles compare equal after this, whichever way they were written.
+pub fn normalize(title: &str) -> String {
+    let title = title.trim().replace('_', " ");
+    let mut chars = title.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => title,
+    }
+}
+
+impl Selection {
+    /// Parses the selection flags out of a command line. `others` are the
+    /// flags read elsewhere, which are ignored, and any other `--` flag is an
+    /// error.
+    pub fn from_args(args: &[String], others: &[&str]) -> Result<Selection> {
+        let mut selection = Selection::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flag = arg.as_str();
+            if !FLAGS.contains(&flag) {
+                if flag.starts_with("--") && !others.contains(&flag) {
+                    bail!("unknown flag {flag}");
+                }
+                continue;
+            }
+            let Some(value) = args.next() else {
+                bail!("{flag} needs a value");
+            };
+            match flag {
+                "--page" => selection.pages.push(normalize(value)),
+                "--file" => {
+                    let list = checkpoint::read_required(value)?;
+                    let titles = list.lines().map(str::trim).filter(|l| !l.is_empty());
+                    selection.pages.extend(titles.map(normalize));
+                }
+                "--namespace" => {
+                    let names = value.split(',').map(str::trim).filter(|n| !n.is_empty());
+                    selection.namespaces.extend(names.map(str::to_owned));
+                }
+                "--from" => selection.from = Some(normalize(value)),
+                "--to" => selection.to = Some(normalize(value)),
+                "--limit" => selection.limit = Some(count(flag, value)?),
+                "--offset" => selection.offset = count(flag, value)?,
+                _ => unreachable!(),
+            }
+        }
+        Ok(selection)
+    }
+
+    /// Whether the run was narrowed down at all.
+    pub fn is_narrowed(&self) -> bool {
+        !self.pages.is_empty()
+            || !self.namespaces.is_empty()
+            || self.from.is_some()
+            || self.to.is_some()
+            || self.limit.is_some()
+            || self.offset > 0
+            || !self.done.is_empty()
+    }
+
+    /// Whether `title` sorts from `--from` on and before `--to`.
+    fn in_range(&self, title: &str) -> bool {
+        let title = normalize(title);
+        self.from.as_ref().is_none_or(|from| &title >= from)
+            && self.to.as_ref().is_none_or(|to| &title < to)
+    }
+
+    /// Leaves out `titles`, which a resumed run already treated.
+    pub fn skip_done(&mut self, titles: impl IntoIterator<Item = String>) {
+        self.done.extend(titles.into_iter().map(|t| normalize(&t)));
+    }
+
+    /// The selection on the wiki at `api_url`, with its namespaces resolved.
+    pub async fn scope(&self, client: &wiki::Bot, api_url: &str) -> Result<Scope<'_>> {
+        if self.namespaces.is_empty() {
+            return Ok(Scope {
+                selection: self,
+                namespaces: None,
+            });
+        }
+        let site = SiteInfo::fetch(client, api_url).await?;
+        let mut namespaces = BTreeSet::new();
+        for name in &self.namespaces {
+            let Some(ns) = site.namespace(name) else {
+                bail!("no namespace {name} on {api_url}");
+            };
+            namespaces.insert(ns);
+        }
+        Ok(Scope {
+            selection: self,
+            namespaces: Some((site, namespaces)),
+        })
+    }
+}
+
+/// The selection on one wiki.
+pub struct Scope<'s> {
+    selection: &'s Selection,
+    namespaces: Option<(SiteInfo, BTreeSet<i64>)>,
+}
+
+impl Scope<'_> {
+    /// Whether the run was narrowed down at all.
+    pub fn is_narrowed(&self) -> bool {
+        self.selection.is_narrowed()
+    }
+
+    /// `items` without those before `--offset` or after `--limit`.
+    pub fn window<T>(&self, items: impl IntoIterator<Item = T>) -> impl Iterator<Item = T> {
+        let limit = self.selection.limit.unwrap_or(usize::MAX);
+        items.into_iter().skip(self.selection.offset).take(limit)
+    }
+
+    /// Whether `title` wasn't treated yet by a resumed run.
+    pub fn todo(&self, title: &str) -> bool {
+        self.selection.done.is_empty() || !self.selection.done.contains(&normalize(title))
+    }
+
+    /// Whether `title` is in the namespaces and range given.
+    fn matches(&self, title: &str) -> bool {
+        let in_namespace = match &self.namespaces {
+            Some((site, namespaces)) => namespaces.contains(&site.split(title).0),
+            None => true,
+        };
+        in_namespace && self.selection.in_range(title)
+    }
+
+    /// The titles to treat: the selected pages if there are any, those from
+    /// `source` otherwise.
+    pub async fn titles(
+        &self,
+        source: impl Future<Output = Result<Vec<String>>>,
+    ) -> Result<Vec<String>> {
+        let titles = match &*self.selection.pages {
+            [] => source.await?,
+            pages => pages.to_vec(),
+        };
+        let titles = titles.into_iter().filter(|t| self.matches(t));
+        Ok(self.window(titles).filter(|t| self.todo(t)).collect())
+    }
+
+    /// Whether `title` may be treated, leaving `--offset` and `--limit` to
+    /// [`window`](Scope::window).
+    pub fn selects(&self, title: &str) -> bool {
+        let pages = &self.selection.pages;
+        self.matches(title) && (pages.is_empty() || pages.contains(&normalize(title)))
+    }
+
+    /// The titles from a source that decides which pages are eligible, left
+    /// with only the selected ones.
+    pub fn filter(&self, titles: Vec<String>) -> Vec<String> {
+        let selected = titles.into_iter().filter(|t| self.selects(t));
+        self.window(selected).filter(|t| self.todo(t)).collect()
+    }
+
+    /// Like [`titles`](Scope::titles), for sources read batch by batch.
+    pub fn source<S: PageSource>(&self, source: S) -> Selected<'_, S> {
+        Selected {
+            scope: self,
+            inner: source,
+            given: false,
+            seen: 0,
+        }
+    }
+
+    /// Like [`titles`](Scope::titles), for sources that give pages with
+    /// their latest revision.
+    pub fn pages<'a>(
+        &'a self,
+        client: &'a wiki::Bot,
+        api_url: &'a str,
+        source: impl Stream<Item = Result<Page>> + 'a,
+    ) -> LocalBoxStream<'a, Result<Page>> {
+        let selection = self.selection;
+        let end = selection
+            .offset
+            .saturating_add(selection.limit.unwrap_or(usize::MAX));
+        let pages = if selection.pages.is_empty() {
+            source.boxed_local()
+        } else {
+            self.given_pages(client, api_url)
+        };
+        // errors go through, without counting towards `--offset` or `--limit`
+        pages
+            .try_filter(|p| future::ready(self.matches(&p.title)))
+            .scan(0, move |seen, page| {
+                if *seen >= end {
+                    return future::ready(None);
+                }
+                let before = page.is_ok() && {
+                    *seen += 1;
+                    *seen <= selection.offset
+                };
+                future::ready(Some((!before).then_some(page)))
+            })
+            .filter_map(future::ready)
+            .try_filter(|p| future::ready(self.todo(&p.title)))
+            .boxed_local()
+    }
+
+    fn given_pages<'a>(
+        &'a self,
+        client: &'a wiki::Bot,
+        api_url: &'a str,
+    ) -> LocalBoxStream<'a, Result<Page>> {
+        stream::once(async move {
+            let titles: Vec<_> = self.selection.pages.iter().map(String::as_str).collect();
+            let contents = fetch_contents(client, api_url, &titles).await?;
+            Ok(stream::iter(contents.into_iter().map(|c| {
+                Ok(Page {
+                    pageid: c.pageid,
+                    title: c.title,
+                    revisions: vec![Revision { revid: c.revid }],
+                })
+            })))
+        })
+        .try_flatten()
+        .boxed_local()
+    }
+}
+
+/// A [`PageSource`] that gives the selected pages instead of those from
+/// `inner`, if there are any.
+pub struct Selected<'s, S> {
+    scope: &'s Scope<'s>,
+    pub inner: S,
+    given: bool,
+    /// Pages given so far, counting those before `--offset`.
+    seen: usize,
+}
+
+impl<S: PageSource> PageSource for Selected<'_, S> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        let selection = self.scope.selection;
+        let end = selection
+            .offset
+            .saturating_add(selection.limit.unwrap_or(usize::MAX));
+        if self.seen >= end {
+            return Ok(None);
+        }
+        let pages = &selection.pages;
+        let batch = if pages.is_empty() {
+            self.inner.next_batch().await?
+        } else if self.given {
+            None
+        } else {
+            self.given = true;
+            Some(pages.clone())
+        };
+        let Some(batch) = batch else {
+            return Ok(None);
+        };
+        let mut titles = Vec::new();
+        for title in batch.into_iter().filter(|t| self.scope.matches(t)) {
+            self.seen += 1;
+            if self.seen > selection.offset && self.seen <= end && self.scope.todo(&title) {
+                titles.push(title);
+            }
+        }
+        Ok(Some(titles))
+    }
+}
+
+/// Sets the selection for this run. Only the first call has an effect.
+pub fn set(selection: Selection) {
+    let _ = SELECTION.set(selection);
+}
+
+/// The selection for this run, which selects everything unless [`set`].
+pub fn get() -> &'static Selection {
+    SELECTION.get_or_init(Selection::default)
+}
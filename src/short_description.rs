@@ -0,0 +1,164 @@
+//! Adds `{{Short description}}` to articles lacking one, using the Wikidata
+//! description.
+//!
+//! In supervised mode, a random sample of every batch is shown to the
+//! operator, who approves or rejects the whole batch.
+
+use std::io::stdin;
+use std::process;
+
+use color_eyre::eyre::bail;
+use parsoid::WikinodeIterator;
+use rand::rng;
+use rand::seq::IndexedRandom;
+use tracing::{debug, info};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::siteinfo::SiteInfo;
+use crate::source::{PageSource, ReplicaSource};
+use crate::wikidata::{self, Wikidata};
+use crate::{
+    check_nobots, enwiki_parsoid, job, policy, run_lock, selection, Parsoid, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("short_description", EditFlags::MINOR_BOT);
+
+/// Per [[WP:SDFORMAT]], descriptions should be about 40 characters.
+const MAX_LEN: usize = 40;
+
+const SAMPLE: usize = 5;
+
+/// Descriptions containing any of these are Wikidata-internal or too generic.
+const BLACKLIST: &[&str] = &[
+    "wikimedia",
+    "wikipedia",
+    "disambiguation",
+    "topics referred to by the same term",
+    "list article",
+    "category",
+    "template",
+    "scientific article",
+    "family name",
+    "given name",
+];
+
+const SQL: &str = "
+SELECT page_namespace, page_title
+FROM page
+LEFT JOIN page_props ON pp_page = page_id AND pp_propname = 'wikibase-shortdesc'
+WHERE page_namespace = 0
+  AND page_is_redirect = 0
+  AND pp_page IS NULL
+LIMIT 10000";
+
+const SUMMARY: &str = "Adding [[WP:SHORTDESC|short description]] from [[WP:WIKIDATA|Wikidata]]";
+
+/// Cleans up a Wikidata description, or rejects it.
+pub fn filter(desc: &str) -> Option<String> {
+    let desc = desc.trim();
+    if desc.is_empty() || desc.chars().count() > MAX_LEN {
+        return None;
+    }
+    let lower = desc.to_lowercase();
+    if BLACKLIST.iter().any(|b| lower.contains(b)) {
+        return None;
+    }
+    // would break the template
+    if desc.contains(['{', '}', '|', '[', ']', '<', '>']) {
+        return None;
+    }
+    let mut chars = desc.chars();
+    let first = chars.next()?;
+    Some(first.to_uppercase().chain(chars).collect())
+}
+
+pub async fn treat(client: &wiki::Bot, parsoid: &Parsoid, title: &str, desc: &str) -> Result<()> {
+    let code = parsoid.get(title).await?.into_mutable();
+    let rev = code.revision_id().unwrap();
+    for template in code.filter_templates()? {
+        if check_nobots(&template) {
+            return Ok(());
+        }
+        if template
+            .name()
+            .eq_ignore_ascii_case("Template:Short description")
+        {
+            debug!("[[{title}]] already has a short description");
+            return Ok(());
+        }
+    }
+
+    let text = parsoid.transform_to_wikitext(&code).await?;
+    let text = format!("{{{{Short description|{desc}}}}}\n{}", text.trim_start());
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        None,
+        text,
+        SUMMARY,
+        Some(rev as u32),
+        &FLAGS,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Shows a random sample of the batch, returning whether it was approved.
+fn approve(proposals: &[(String, String)]) -> Result<bool> {
+    println!("Sample of {} proposed descriptions:", proposals.len());
+    for (title, desc) in proposals.choose_multiple(&mut rng(), SAMPLE) {
+        println!("  [[{title}]]: {desc}");
+    }
+    println!("Save this batch? [y/N/q(uit)]");
+    let answer = stdin().lines().next().transpose()?.unwrap_or_default();
+    match &*answer.trim().to_ascii_lowercase() {
+        "y" => Ok(true),
+        "q" | "quit" => process::exit(0),
+        _ => Ok(false),
+    }
+}
+
+pub async fn main(supervised: bool) -> Result<()> {
+    if supervised && job::enabled() {
+        bail!("--supervised needs someone to approve batches, which a job doesn't have");
+    }
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+        let wikidata = Wikidata::new()?;
+
+        let site = SiteInfo::fetch(&client, ENWIKI_API).await?;
+        let pages = ReplicaSource::new("enwiki", SQL, &site)
+            .collect_all()
+            .await?;
+        info!("{} articles without a short description", pages.len());
+        let pages = scope.filter(pages);
+
+        for batch in pages.chunks(wikidata::BATCH) {
+            let titles: Vec<_> = batch.iter().map(String::as_str).collect();
+            let descriptions = wikidata.descriptions("enwiki", &titles, "en").await?;
+            let proposals: Vec<_> = titles
+                .iter()
+                .filter_map(|&t| Some((t.to_owned(), filter(descriptions.get(t)?)?)))
+                .collect();
+            if proposals.is_empty() {
+                continue;
+            }
+            if supervised && !approve(&proposals)? {
+                info!("batch rejected");
+                continue;
+            }
+            for (title, desc) in proposals {
+                let treated = || treat(&client, &parsoid, &title, &desc);
+                policy::page(FLAGS.task, &title, treated).await?;
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,51 @@
+//! The emergency stop bot policy asks for: an on-wiki page anyone can fill in
+//! to stop the bot, without a shell on the host.
+//!
+//! Before each save, [`edit::pre_save`](crate::edit::pre_save) reads [`PAGE`],
+//! or the page `SHUTOFF_PAGE` names, on the wiki being edited. If there is
+//! anything on it, like `stop`, the save fails with [`ShutOff`], which ends the
+//! run of the task instead of skipping the page, and a `--job` run exits with
+//! [`SHUT_OFF`](crate::job::SHUT_OFF). Blanking the page lets the bot edit
+//! again, and so does a missing page. A page that can't be read fails the
+//! save too, as the bot may have been stopped.
+
+use std::env;
+
+use color_eyre::eyre::{Context, ContextCompat};
+use tracing::warn;
+
+use crate::job::ShutOff;
+use crate::{query_raw, Result};
+
+pub const PAGE: &str = "User:DeadbeefBot/stop";
+
+fn page() -> String {
+    env::var("SHUTOFF_PAGE").unwrap_or_else(|_| PAGE.to_owned())
+}
+
+/// Fails if the stop page on the wiki at `api_url` isn't empty, or can't be
+/// read.
+pub async fn check(client: &wiki::Bot, api_url: &str) -> Result<()> {
+    let page = page();
+    let params = [
+        ("prop", "revisions"),
+        ("titles", page.as_str()),
+        ("rvprop", "content"),
+        ("rvslots", "main"),
+    ];
+    let batches = query_raw(client, api_url, &params)
+        .await
+        .with_context(|| format!("not saving, can't read [[{page}]]"))?;
+    let found = &batches.first().context("no query result")?["pages"][0];
+    if found.get("missing").is_some() {
+        return Ok(());
+    }
+    let text = found["revisions"][0]["slots"]["main"]["content"]
+        .as_str()
+        .with_context(|| format!("not saving, no content in [[{page}]]"))?;
+    let Some(reason) = text.lines().map(str::trim).find(|l| !l.is_empty()) else {
+        return Ok(());
+    };
+    warn!("stopping, [[{page}]] says {reason:?}");
+    Err(ShutOff(format!("[[{page}]] says {reason:?}")).into())
+}
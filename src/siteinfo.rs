@@ -0,0 +1,157 @@
+//! Namespace names and other details of a wiki, from `meta=siteinfo`.
+//!
+//! Titles are split and built with the wiki's own namespace names, so that
+//! tasks don't assume English prefixes like `Talk:`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use color_eyre::eyre::ContextCompat;
+use serde::Deserialize;
+
+use crate::{query_raw, Result};
+
+pub const MAIN: i64 = 0;
+pub const TALK: i64 = 1;
+pub const PROJECT: i64 = 4;
+
+#[derive(Deserialize, Clone, Debug)]
+struct Namespace {
+    id: i64,
+    name: String,
+    canonical: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Alias {
+    id: i64,
+    alias: String,
+}
+
+#[derive(Deserialize)]
+struct General {
+    server: String,
+    scriptpath: String,
+    articlepath: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct SiteInfo {
+    /// e.g. `https://en.wikipedia.org`
+    pub server: String,
+    /// e.g. `/w`
+    pub script_path: String,
+    /// e.g. `/wiki/$1`
+    pub article_path: String,
+    namespaces: BTreeMap<i64, Namespace>,
+    /// Local names, canonical names and aliases, lowercased.
+    by_name: HashMap<String, i64>,
+}
+
+fn key(name: &str) -> String {
+    name.trim().replace('_', " ").to_lowercase()
+}
+
+impl SiteInfo {
+    pub async fn fetch(client: &wiki::Bot, api_url: &str) -> Result<SiteInfo> {
+        let batches = query_raw(
+            client,
+            api_url,
+            &[
+                ("meta", "siteinfo"),
+                ("siprop", "general|namespaces|namespacealiases"),
+            ],
+        )
+        .await?;
+        let query = batches.first().context("empty siteinfo response")?;
+        let general: General = serde_json::from_value(query["general"].clone())?;
+        let namespaces: HashMap<String, Namespace> =
+            serde_json::from_value(query["namespaces"].clone())?;
+        let aliases: Vec<Alias> = serde_json::from_value(query["namespacealiases"].clone())?;
+
+        let mut by_name = HashMap::new();
+        for ns in namespaces.values() {
+            by_name.insert(key(&ns.name), ns.id);
+            if let Some(canonical) = &ns.canonical {
+                by_name.insert(key(canonical), ns.id);
+            }
+        }
+        for a in aliases {
+            by_name.insert(key(&a.alias), a.id);
+        }
+        let server = match general.server.strip_prefix("//") {
+            Some(host) => format!("https://{host}"),
+            None => general.server,
+        };
+        Ok(SiteInfo {
+            server,
+            script_path: general.scriptpath,
+            article_path: general.articlepath,
+            namespaces: namespaces.into_values().map(|ns| (ns.id, ns)).collect(),
+            by_name,
+        })
+    }
+
+    /// The local prefix for namespace `ns`, including the colon, or an empty
+    /// string for the main namespace and unknown namespaces.
+    pub fn prefix(&self, ns: i64) -> String {
+        match self.namespaces.get(&ns) {
+            Some(n) if !n.name.is_empty() => format!("{}:", n.name),
+            _ => String::new(),
+        }
+    }
+
+    /// `name` in namespace `ns`, with underscores turned into spaces.
+    pub fn title(&self, ns: i64, name: &str) -> String {
+        format!("{}{}", self.prefix(ns), name.replace('_', " "))
+    }
+
+    /// The namespace called `name`, which may also be its number, or `None`
+    /// if the wiki has no such namespace. The main namespace is `0`, `Main`
+    /// or `Article`.
+    pub fn namespace(&self, name: &str) -> Option<i64> {
+        if let Ok(ns) = name.trim().parse() {
+            return self.namespaces.contains_key(&ns).then_some(ns);
+        }
+        match &*key(name) {
+            "" | "main" | "(main)" | "article" => Some(MAIN),
+            name => self.by_name.get(name).copied(),
+        }
+    }
+
+    /// The namespace of `title` and the rest of the title after its prefix.
+    pub fn split<'t>(&self, title: &'t str) -> (i64, &'t str) {
+        if let Some((prefix, rest)) = title.split_once(':') {
+            if let Some(&ns) = self.by_name.get(&key(prefix)) {
+                return (ns, rest.trim_start());
+            }
+        }
+        (MAIN, title)
+    }
+
+    /// The talk page belonging to `title`, or `title` if it is one.
+    pub fn talk_page(&self, title: &str) -> String {
+        match self.split(title) {
+            (ns, _) if ns < 0 || ns % 2 == 1 => title.to_owned(),
+            (ns, rest) => self.title(ns + 1, rest),
+        }
+    }
+
+    /// The subject page of `title`, or `title` if it is one.
+    pub fn subject_page(&self, title: &str) -> String {
+        match self.split(title) {
+            (ns, rest) if ns > 0 && ns % 2 == 1 => self.title(ns - 1, rest),
+            _ => title.to_owned(),
+        }
+    }
+
+    /// The URL of `title` on the wiki.
+    pub fn page_url(&self, title: &str) -> String {
+        let title = urlencoding::encode(&title.replace(' ', "_")).replace("%3A", ":");
+        format!("{}{}", self.server, self.article_path.replace("$1", &title))
+    }
+
+    /// The base URL of the core REST API.
+    pub fn rest_url(&self) -> String {
+        format!("{}{}/rest.php", self.server, self.script_path)
+    }
+}
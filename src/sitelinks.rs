@@ -0,0 +1,206 @@
+//! Reports disagreements between local interlanguage links and Wikidata
+//! sitelinks for the pages in a category.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use fancy_regex::Regex;
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::source::{CategorySource, PageSource};
+use crate::wikidata::{self, Wikidata};
+use crate::{fetch_content, query_raw, run_lock, selection, Result, ENWIKI_API};
+
+const FLAGS: TaskFlags = TaskFlags::new("sitelinks", EditFlags::BOT);
+
+const REPORT: &str = "User:DeadbeefBot/Interlanguage link conflicts";
+
+static LANGLINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[\[\s*([a-z][a-z-]*[a-z])\s*:\s*([^\]|\n]+?)\s*\]\]").unwrap());
+
+pub enum Problem {
+    /// Local link and sitelink point at different pages.
+    Conflict { wikidata: String },
+    /// Wikidata has no sitelink for that language.
+    Orphaned,
+    /// The page has local links but no Wikidata item.
+    NoItem,
+}
+
+pub struct Row {
+    pub title: String,
+    pub item: Option<String>,
+    pub lang: String,
+    pub local: String,
+    pub problem: Problem,
+}
+
+/// Interwiki prefixes that are language links on this wiki.
+async fn language_prefixes(client: &wiki::Bot) -> Result<HashSet<String>> {
+    let batches = query_raw(
+        client,
+        ENWIKI_API,
+        &[
+            ("meta", "siteinfo"),
+            ("siprop", "interwikimap"),
+            ("sifilteriw", "local"),
+        ],
+    )
+    .await?;
+    Ok(batches
+        .iter()
+        .flat_map(|b| b["interwikimap"].as_array().into_iter().flatten())
+        .filter(|iw| iw.get("language").is_some())
+        .filter_map(|iw| Some(iw["prefix"].as_str()?.to_owned()))
+        .collect())
+}
+
+/// `be-tarask` → `be_x_oldwiki`, following Wikidata's site IDs.
+fn site_id(lang: &str) -> String {
+    match lang {
+        "be-tarask" => "be_x_oldwiki".into(),
+        _ => format!("{}wiki", lang.replace('-', "_")),
+    }
+}
+
+fn same_title(a: &str, b: &str) -> bool {
+    let norm = |s: &str| {
+        let s = s.trim().replace('_', " ");
+        let mut chars = s.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+    norm(a) == norm(b)
+}
+
+pub fn local_links(text: &str, prefixes: &HashSet<String>) -> Result<BTreeMap<String, String>> {
+    let mut links = BTreeMap::new();
+    for c in LANGLINK.captures_iter(text) {
+        let c = c?;
+        if prefixes.contains(&c[1]) {
+            links.insert(c[1].to_owned(), c[2].to_owned());
+        }
+    }
+    Ok(links)
+}
+
+pub async fn check(
+    client: &wiki::Bot,
+    wikidata: &Wikidata,
+    prefixes: &HashSet<String>,
+    titles: &[&str],
+) -> Result<Vec<Row>> {
+    let entities = wikidata
+        .entities_for_titles("enwiki", titles, "sitelinks", None)
+        .await?;
+    let mut rows = Vec::new();
+
+    for &title in titles {
+        let local = local_links(&fetch_content(client, ENWIKI_API, title).await?, prefixes)?;
+        if local.is_empty() {
+            continue;
+        }
+        let entity = entities.iter().find(|e| {
+            e.sitelinks
+                .get("enwiki")
+                .is_some_and(|s| same_title(&s.title, title))
+        });
+        for (lang, local) in local {
+            let problem = match entity {
+                None => Problem::NoItem,
+                Some(e) => match e.sitelinks.get(&site_id(&lang)) {
+                    None => Problem::Orphaned,
+                    Some(s) if same_title(&s.title, &local) => continue,
+                    Some(s) => Problem::Conflict {
+                        wikidata: s.title.clone(),
+                    },
+                },
+            };
+            rows.push(Row {
+                title: title.to_owned(),
+                item: entity.map(|e| e.id.clone()),
+                lang,
+                local,
+                problem,
+            });
+        }
+    }
+    Ok(rows)
+}
+
+pub fn render(category: &str, rows: &[Row]) -> String {
+    let mut s = format!(
+        "Interlanguage link problems in [[:{category}]], as of ~~~~~.\n\n\
+        {{| class=\"wikitable sortable\"\n\
+        ! Page !! Item !! Language !! Local link !! Wikidata sitelink !! Problem\n"
+    );
+    for row in rows {
+        let item = row
+            .item
+            .as_deref()
+            .map_or_else(String::new, |q| format!("[[d:{q}|{q}]]"));
+        let (wikidata, problem) = match &row.problem {
+            Problem::Conflict { wikidata } => (format!("[[:{}:{wikidata}]]", row.lang), "conflict"),
+            Problem::Orphaned => (String::new(), "missing on Wikidata"),
+            Problem::NoItem => (String::new(), "no item"),
+        };
+        let _ = write!(
+            s,
+            "|-\n| [[{}]] || {item} || {} || [[:{}:{}]] || {wikidata} || {problem}\n",
+            row.title, row.lang, row.lang, row.local
+        );
+    }
+    s.push_str("|}\n");
+    s
+}
+
+pub async fn main(category: &str) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let wikidata = Wikidata::new()?;
+        let prefixes = language_prefixes(&client).await?;
+
+        let pages = CategorySource::new(&client, ENWIKI_API, category)
+            .namespace("0")
+            .collect_all()
+            .await?;
+        let pages = scope.filter(pages);
+        info!("checking {} pages in [[{category}]]", pages.len());
+
+        let mut rows = Vec::new();
+        for batch in pages.chunks(wikidata::BATCH) {
+            let titles: Vec<_> = batch.iter().map(String::as_str).collect();
+            match check(&client, &wikidata, &prefixes, &titles).await {
+                Ok(r) => rows.extend(r),
+                Err(e) => warn!("failed to check batch starting at [[{}]]: {e}", titles[0]),
+            }
+        }
+
+        info!("{} problems found", rows.len());
+        if scope.is_narrowed() {
+            info!("not updating [[{REPORT}]] from a narrowed run");
+            return Ok(());
+        }
+        save(
+            &client,
+            PageSpec::Title(REPORT.to_owned()),
+            None,
+            render(category, &rows),
+            format!("Updating report ({} entries)", rows.len()),
+            None,
+            &FLAGS,
+        )
+        .await?;
+
+        Ok(())
+    })
+    .await
+}
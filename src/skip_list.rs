@@ -0,0 +1,167 @@
+//! Pages the bot must never edit, whatever a task finds on them: pages where
+//! editors have objected to its edits, or where a task is known to go wrong.
+//!
+//! They are listed in `--skip-file <path>`, one title per line, and linked from
+//! [`PAGE`] on each wiki, which anyone can add to so that an article can be
+//! opted out without `{{bots}}` on it. Links under a heading naming a task,
+//! like `== param_migration ==`, only apply to that task. A `*` in a title
+//! stands for any text, so `[[List of *]]` skips every list. Both are checked
+//! by [`edit::pre_save`](crate::edit::pre_save), so a listed page isn't saved
+//! even if a task treats it.
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::OnceLock;
+
+use color_eyre::eyre::{bail, Context};
+use tracing::info;
+
+use crate::selection::normalize;
+use crate::{checkpoint, fetch_contents, Result};
+
+pub const PAGE: &str = "User:DeadbeefBot/Skip list";
+
+/// Titles to skip, and patterns of them.
+#[derive(Default, Debug)]
+struct List {
+    titles: HashSet<String>,
+    patterns: Vec<String>,
+}
+
+impl List {
+    fn add(&mut self, title: String) {
+        if title.contains('*') {
+            self.patterns.push(title);
+        } else {
+            self.titles.insert(title);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.titles.len() + self.patterns.len()
+    }
+
+    fn contains(&self, title: &str) -> bool {
+        self.titles.contains(title) || self.patterns.iter().any(|p| matches(p, title))
+    }
+}
+
+/// Whether `title` is matched by `pattern`, where `*` stands for any text.
+fn matches(pattern: &str, title: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = title.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        let Some(at) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[at + part.len()..];
+    }
+    rest.ends_with(last)
+}
+
+/// What [`PAGE`] lists, for every task and for some.
+#[derive(Default, Debug)]
+struct OnWiki {
+    all: List,
+    tasks: HashMap<String, List>,
+}
+
+impl OnWiki {
+    fn parse(text: &str) -> OnWiki {
+        let mut on_wiki = OnWiki::default();
+        let mut task = None;
+        for line in text.lines().map(str::trim) {
+            if line.len() > 1 && line.starts_with('=') && line.ends_with('=') {
+                task = Some(line.trim_matches('=').trim().replace(' ', "_"));
+                continue;
+            }
+            let list = match &task {
+                Some(task) => on_wiki.tasks.entry(task.clone()).or_default(),
+                None => &mut on_wiki.all,
+            };
+            for title in links(line) {
+                list.add(title);
+            }
+        }
+        on_wiki
+    }
+
+    fn len(&self) -> usize {
+        self.all.len() + self.tasks.values().map(List::len).sum::<usize>()
+    }
+}
+
+/// Titles from `--skip-file`, on every wiki.
+static FILE: OnceLock<List> = OnceLock::new();
+
+tokio::task_local! {
+    /// Titles linked from [`PAGE`] on the wiki the current task runs on.
+    static ON_WIKI: OnWiki;
+}
+
+/// The titles linked from `text`, leaving out anything after a `|`.
+fn links(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split("[[")
+        .skip(1)
+        .filter_map(|l| l.split_once("]]"))
+        .filter_map(|(link, _)| {
+            let title = link.split('|').next()?.trim().trim_start_matches(':');
+            (!title.is_empty()).then(|| normalize(title))
+        })
+}
+
+/// Reads `--skip-file` out of a command line, ignoring the rest. Only the
+/// first call has an effect.
+pub fn from_args(args: &[String]) -> Result<()> {
+    let mut titles = List::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--skip-file" {
+            continue;
+        }
+        let Some(path) = args.next() else {
+            bail!("--skip-file needs a value");
+        };
+        let list = checkpoint::read_required(path)?;
+        let lines = list.lines().map(str::trim).filter(|l| !l.is_empty());
+        for line in lines {
+            titles.add(normalize(line));
+        }
+    }
+    let _ = FILE.set(titles);
+    Ok(())
+}
+
+/// Runs `run` with the skip list of the wiki at `api_url`. A missing [`PAGE`]
+/// lists nothing, but one that can't be read fails the run, as the pages on it
+/// would be edited otherwise.
+pub async fn enforced<T>(
+    client: &wiki::Bot,
+    api_url: &str,
+    run: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let page = fetch_contents(client, api_url, &[PAGE])
+        .await
+        .with_context(|| format!("not running, can't read [[{PAGE}]]"))?
+        .pop();
+    let on_wiki = page.map_or_else(OnWiki::default, |page| OnWiki::parse(&page.text));
+    if on_wiki.len() > 0 {
+        info!("{} page(s) on [[{PAGE}]]", on_wiki.len());
+    }
+    ON_WIKI.scope(on_wiki, run).await
+}
+
+/// Whether `title` must be left alone by `task`.
+pub fn skips(task: &str, title: &str) -> bool {
+    let title = normalize(title);
+    FILE.get().is_some_and(|f| f.contains(&title))
+        || ON_WIKI
+            .try_with(|l| {
+                l.all.contains(&title) || l.tasks.get(task).is_some_and(|t| t.contains(&title))
+            })
+            .unwrap_or(false)
+}
@@ -0,0 +1,32 @@
+//! Sources of pages for tasks to work through.
+
+use crate::Result;
+
+pub mod category;
+pub mod contribs;
+pub mod petscan;
+pub mod quarry;
+pub mod replica;
+pub mod search;
+
+pub use category::CategorySource;
+pub use contribs::ContribsSource;
+pub use petscan::PetScanSource;
+pub use quarry::QuarrySource;
+pub use replica::ReplicaSource;
+pub use search::{SearchSort, SearchSource};
+
+#[allow(async_fn_in_trait)]
+pub trait PageSource {
+    /// Fetches the next batch of titles, or `None` once the source is exhausted.
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>>;
+
+    /// Drains the source into a single list.
+    async fn collect_all(&mut self) -> Result<Vec<String>> {
+        let mut titles = Vec::new();
+        while let Some(batch) = self.next_batch().await? {
+            titles.extend(batch);
+        }
+        Ok(titles)
+    }
+}
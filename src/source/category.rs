@@ -0,0 +1,54 @@
+use super::PageSource;
+use crate::{query_raw, Result};
+
+/// Yields the members of a category.
+pub struct CategorySource<'a> {
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    category: String,
+    namespace: Option<String>,
+    done: bool,
+}
+
+impl<'a> CategorySource<'a> {
+    pub fn new(client: &'a wiki::Bot, api_url: &'a str, category: impl Into<String>) -> Self {
+        CategorySource {
+            client,
+            api_url,
+            category: category.into(),
+            namespace: None,
+            done: false,
+        }
+    }
+
+    /// Restricts members to these namespaces, e.g. `"0|10"`.
+    pub fn namespace(mut self, ns: impl Into<String>) -> Self {
+        self.namespace = Some(ns.into());
+        self
+    }
+}
+
+impl PageSource for CategorySource<'_> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let mut params = vec![
+            ("list", "categorymembers"),
+            ("cmtitle", &*self.category),
+            ("cmlimit", "max"),
+        ];
+        if let Some(ns) = &self.namespace {
+            params.push(("cmnamespace", ns));
+        }
+        let batches = query_raw(self.client, self.api_url, &params).await?;
+        Ok(Some(
+            batches
+                .iter()
+                .flat_map(|b| b["categorymembers"].as_array().into_iter().flatten())
+                .filter_map(|m| Some(m["title"].as_str()?.to_owned()))
+                .collect(),
+        ))
+    }
+}
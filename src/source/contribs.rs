@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use super::PageSource;
+use crate::{query_batch, Continue, Result};
+
+/// Yields the pages a user has edited, newest edits first. Each page is
+/// yielded once, however many times it was edited.
+pub struct ContribsSource<'a> {
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    user: String,
+    namespace: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    seen: HashSet<String>,
+    cont: Continue,
+    done: bool,
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl<'a> ContribsSource<'a> {
+    pub fn new(client: &'a wiki::Bot, api_url: &'a str, user: impl Into<String>) -> Self {
+        ContribsSource {
+            client,
+            api_url,
+            user: user.into(),
+            namespace: None,
+            since: None,
+            until: None,
+            seen: HashSet::new(),
+            cont: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Restricts contributions to these namespaces, e.g. `"0|10"`.
+    pub fn namespace(mut self, ns: impl Into<String>) -> Self {
+        self.namespace = Some(ns.into());
+        self
+    }
+
+    /// Leaves out edits made before `t`.
+    pub fn since(mut self, t: DateTime<Utc>) -> Self {
+        self.since = Some(timestamp(t));
+        self
+    }
+
+    /// Leaves out edits made after `t`.
+    pub fn until(mut self, t: DateTime<Utc>) -> Self {
+        self.until = Some(timestamp(t));
+        self
+    }
+}
+
+impl PageSource for ContribsSource<'_> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.done {
+            return Ok(None);
+        }
+        // Newest first, so `ucstart` is the later bound.
+        let mut params = vec![
+            ("list", "usercontribs"),
+            ("ucuser", &*self.user),
+            ("uclimit", "max"),
+            ("ucprop", "title"),
+        ];
+        if let Some(ns) = &self.namespace {
+            params.push(("ucnamespace", ns));
+        }
+        if let Some(until) = &self.until {
+            params.push(("ucstart", until));
+        }
+        if let Some(since) = &self.since {
+            params.push(("ucend", since));
+        }
+        let (batch, next) = query_batch(self.client, self.api_url, &params, &self.cont).await?;
+        match next {
+            Some(next) => self.cont = next,
+            None => self.done = true,
+        }
+        Ok(Some(
+            batch["usercontribs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|c| c["title"].as_str())
+                .filter(|t| self.seen.insert(t.to_string()))
+                .map(str::to_owned)
+                .collect(),
+        ))
+    }
+}
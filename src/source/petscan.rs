@@ -0,0 +1,203 @@
+//! Worklists from [PetScan](https://petscan.wmflabs.org), through its JSON
+//! output.
+//!
+//! PetScan queries can take minutes and sometimes time out, so fetches are
+//! retried, and the result can be cached on disk to make reruns cheap.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use super::PageSource;
+use crate::{ratelimit, Result, CONTENT_BATCH};
+
+const CACHE_DIR: &str = "petscan-cache";
+
+/// Attempts before giving up on a query.
+const ATTEMPTS: u32 = 3;
+
+const RETRY_WAIT: Duration = Duration::from_secs(30);
+
+/// A page in a PetScan result.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PetScanPage {
+    pub id: u64,
+    /// Without the namespace prefix, with underscores.
+    pub title: String,
+    pub namespace: i64,
+    /// The local namespace name.
+    pub nstext: String,
+    /// The Wikidata item, when the query asks for it.
+    #[serde(default)]
+    pub q: Option<String>,
+    /// Extra fields requested with PetScan's metadata options.
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+impl PetScanPage {
+    /// The full title, with spaces.
+    pub fn full_title(&self) -> String {
+        let title = self.title.replace('_', " ");
+        if self.nstext.is_empty() {
+            title
+        } else {
+            format!("{}:{title}", self.nstext)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Output {
+    #[serde(rename = "*")]
+    combinations: Vec<Combination>,
+}
+
+#[derive(Deserialize)]
+struct Combination {
+    a: Pages,
+}
+
+#[derive(Deserialize)]
+struct Pages {
+    #[serde(rename = "*")]
+    pages: Vec<PetScanPage>,
+}
+
+/// `url`, which may be a `psid=` link or a full query, set up for JSON
+/// output.
+pub fn json_url(url: &str) -> Result<Url> {
+    let mut url = Url::parse(url).with_context(|| format!("parsing PetScan URL {url}"))?;
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != "format" && k != "doit")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("format", "json")
+        .append_pair("doit", "1");
+    Ok(url)
+}
+
+/// Runs a PetScan query, retrying on failure.
+pub async fn fetch(http: &reqwest::Client, url: &Url) -> Result<Vec<PetScanPage>> {
+    let mut attempt = 1;
+    loop {
+        let res: Result<_> = async {
+            let out: Output = ratelimit::send(http.get(url.clone()))
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(out
+                .combinations
+                .into_iter()
+                .flat_map(|c| c.a.pages)
+                .collect())
+        }
+        .await;
+        match res {
+            Err(e) if attempt < ATTEMPTS => {
+                warn!("PetScan query failed, retrying in {RETRY_WAIT:?}: {e}");
+                tokio::time::sleep(RETRY_WAIT).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+fn cache_path(url: &Url) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.as_str().hash(&mut hasher);
+    PathBuf::from(CACHE_DIR).join(format!("{:016x}.json", hasher.finish()))
+}
+
+/// The cached result for `url`, if there is one younger than `max_age`.
+fn read_cache(url: &Url, max_age: Duration) -> Option<Vec<PetScanPage>> {
+    let path = cache_path(url);
+    let age = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())?;
+    if age > max_age {
+        return None;
+    }
+    let pages = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+    debug!("using cached PetScan result from {}", path.display());
+    Some(pages)
+}
+
+fn write_cache(url: &Url, pages: &[PetScanPage]) -> Result<()> {
+    fs::create_dir_all(CACHE_DIR)?;
+    fs::write(cache_path(url), serde_json::to_vec(pages)?)?;
+    Ok(())
+}
+
+/// Yields the pages of a PetScan query, [`CONTENT_BATCH`] at a time.
+pub struct PetScanSource<'a> {
+    http: &'a reqwest::Client,
+    url: String,
+    max_age: Option<Duration>,
+    pages: Option<VecDeque<PetScanPage>>,
+}
+
+impl<'a> PetScanSource<'a> {
+    pub fn new(http: &'a reqwest::Client, url: impl Into<String>) -> Self {
+        PetScanSource {
+            http,
+            url: url.into(),
+            max_age: None,
+            pages: None,
+        }
+    }
+
+    /// Reuses a result fetched less than `max_age` ago, and caches new ones.
+    pub fn cache(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Runs the query, or takes it from the cache.
+    pub async fn pages(&self) -> Result<Vec<PetScanPage>> {
+        let url = json_url(&self.url)?;
+        if let Some(pages) = self.max_age.and_then(|age| read_cache(&url, age)) {
+            return Ok(pages);
+        }
+        let pages = fetch(self.http, &url)
+            .await
+            .map_err(|e| eyre!("running PetScan query {}: {e}", self.url))?;
+        info!("got {} pages from PetScan", pages.len());
+        if self.max_age.is_some() {
+            if let Err(e) = write_cache(&url, &pages) {
+                warn!("failed to cache PetScan result: {e}");
+            }
+        }
+        Ok(pages)
+    }
+}
+
+impl PageSource for PetScanSource<'_> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.pages.is_none() {
+            self.pages = Some(self.pages().await?.into());
+        }
+        let pages = self.pages.as_mut().unwrap();
+        if pages.is_empty() {
+            return Ok(None);
+        }
+        let n = pages.len().min(CONTENT_BATCH);
+        Ok(Some(pages.drain(..n).map(|p| p.full_title()).collect()))
+    }
+}
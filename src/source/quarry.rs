@@ -0,0 +1,116 @@
+//! Result sets of saved queries on [Quarry](https://quarry.wmcloud.org).
+//!
+//! The latest run of the query is used, so rerun it on Quarry to refresh the
+//! worklist. Columns are recognised by name:
+//!
+//! - `page_namespace` and `page_title`, as stored in the replicas
+//! - `page_title` or `title` alone, a full title
+//! - `page_id`, looked up through the API
+
+use color_eyre::eyre::{bail, eyre};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::PageSource;
+use crate::siteinfo::SiteInfo;
+use crate::{query_raw, ratelimit, Result, CONTENT_BATCH};
+
+#[derive(Deserialize)]
+struct ResultSet {
+    headers: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+pub fn result_url(query_id: u64) -> String {
+    format!("https://quarry.wmcloud.org/query/{query_id}/result/latest/0/json")
+}
+
+fn as_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Yields the pages in the latest result of a Quarry query.
+pub struct QuarrySource<'a> {
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    query_id: u64,
+    site: SiteInfo,
+    done: bool,
+}
+
+impl<'a> QuarrySource<'a> {
+    /// `site` is the wiki the query ran against; page ids are resolved on
+    /// `api_url`.
+    pub fn new(client: &'a wiki::Bot, api_url: &'a str, query_id: u64, site: &SiteInfo) -> Self {
+        QuarrySource {
+            client,
+            api_url,
+            query_id,
+            site: site.clone(),
+            done: false,
+        }
+    }
+
+    async fn titles_of_ids(&self, ids: &[String]) -> Result<Vec<String>> {
+        let mut titles = Vec::new();
+        for chunk in ids.chunks(CONTENT_BATCH) {
+            let ids = chunk.join("|");
+            let batches = query_raw(self.client, self.api_url, &[("pageids", &ids)]).await?;
+            titles.extend(
+                batches
+                    .iter()
+                    .flat_map(|b| b["pages"].as_array().into_iter().flatten())
+                    .filter(|p| p.get("missing").is_none())
+                    .filter_map(|p| Some(p["title"].as_str()?.to_owned())),
+            );
+        }
+        Ok(titles)
+    }
+}
+
+impl PageSource for QuarrySource<'_> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let set: ResultSet = ratelimit::send(self.client.client.get(result_url(self.query_id)))
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(|e| eyre!("reading result of Quarry query {}: {e}", self.query_id))?;
+        let column = |name: &str| set.headers.iter().position(|h| h == name);
+        let cells = |i: usize| {
+            set.rows
+                .iter()
+                .filter_map(move |r| r.get(i).and_then(as_string))
+        };
+
+        let titles = match (column("page_namespace"), column("page_title")) {
+            (Some(ns), Some(t)) => set
+                .rows
+                .iter()
+                .filter_map(|r| {
+                    let ns = r.get(ns)?.as_i64()?;
+                    Some(self.site.title(ns, &as_string(r.get(t)?)?))
+                })
+                .collect(),
+            (None, Some(t)) => cells(t).map(|t| t.replace('_', " ")).collect(),
+            _ => match (column("title"), column("page_id")) {
+                (Some(t), _) => cells(t).map(|t| t.replace('_', " ")).collect(),
+                (None, Some(id)) => self.titles_of_ids(&cells(id).collect::<Vec<_>>()).await?,
+                (None, None) => bail!(
+                    "Quarry query {} has none of the columns page_namespace, page_title, title \
+                     or page_id",
+                    self.query_id
+                ),
+            },
+        };
+        Ok(Some(titles))
+    }
+}
@@ -0,0 +1,96 @@
+//! Queries against the Toolforge wiki replicas.
+//!
+//! Credentials are read from `~/replica.my.cnf`, which Toolforge provisions
+//! for every tool account.
+
+use std::{env, fs};
+
+use color_eyre::eyre::{eyre, Context};
+use mysql_async::prelude::{FromRow, Queryable};
+use mysql_async::{Conn, OptsBuilder};
+
+use super::PageSource;
+use crate::siteinfo::SiteInfo;
+use crate::Result;
+
+struct Credentials {
+    user: String,
+    password: String,
+}
+
+fn credentials() -> Result<Credentials> {
+    let home = env::var("HOME").context("HOME is not set")?;
+    let path = format!("{home}/replica.my.cnf");
+    let cnf = fs::read_to_string(&path).with_context(|| format!("reading {path}"))?;
+    let get = |key: &str| {
+        cnf.lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().trim_matches('\'').to_owned())
+            .ok_or_else(|| eyre!("{key} missing from {path}"))
+    };
+    Ok(Credentials {
+        user: get("user")?,
+        password: get("password")?,
+    })
+}
+
+/// Connects to the analytics replica of `db`, e.g. `enwiki`.
+pub async fn connect(db: &str) -> Result<Conn> {
+    let Credentials { user, password } = credentials()?;
+    let opts = OptsBuilder::default()
+        .ip_or_hostname(format!("{db}.analytics.db.svc.wikimedia.cloud"))
+        .user(Some(user))
+        .pass(Some(password))
+        .db_name(Some(format!("{db}_p")));
+    Ok(Conn::new(opts).await?)
+}
+
+/// Runs a single query against the replica of `db`.
+pub async fn query<T: FromRow + Send + 'static>(db: &str, sql: &str) -> Result<Vec<T>> {
+    let mut conn = connect(db).await?;
+    let rows = conn.query(sql).await?;
+    conn.disconnect().await?;
+    Ok(rows)
+}
+
+/// Titles in the replica are stored with underscores and without their
+/// namespace prefix.
+pub fn title(site: &SiteInfo, ns: i64, title: &[u8]) -> String {
+    site.title(ns, &String::from_utf8_lossy(title))
+}
+
+/// Yields the pages returned by an SQL query selecting `(namespace, title)`.
+pub struct ReplicaSource {
+    db: String,
+    sql: String,
+    site: SiteInfo,
+    done: bool,
+}
+
+impl ReplicaSource {
+    /// `site` is the wiki behind `db`, for its namespace names.
+    pub fn new(db: impl Into<String>, sql: impl Into<String>, site: &SiteInfo) -> Self {
+        ReplicaSource {
+            db: db.into(),
+            sql: sql.into(),
+            site: site.clone(),
+            done: false,
+        }
+    }
+}
+
+impl PageSource for ReplicaSource {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let rows: Vec<(i64, Vec<u8>)> = query(&self.db, &self.sql).await?;
+        Ok(Some(
+            rows.iter()
+                .map(|(ns, t)| title(&self.site, *ns, t))
+                .collect(),
+        ))
+    }
+}
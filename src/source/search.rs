@@ -0,0 +1,156 @@
+use tracing::info;
+
+use super::PageSource;
+use crate::{query_batch, Result};
+
+/// Orders for search results, as accepted by `srsort`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchSort {
+    Relevance,
+    JustMatch,
+    None,
+    Random,
+    UserRandom,
+    IncomingLinksAsc,
+    IncomingLinksDesc,
+    CreateTimestampAsc,
+    CreateTimestampDesc,
+    LastEditAsc,
+    LastEditDesc,
+}
+
+impl SearchSort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SearchSort::Relevance => "relevance",
+            SearchSort::JustMatch => "just_match",
+            SearchSort::None => "none",
+            SearchSort::Random => "random",
+            SearchSort::UserRandom => "user_random",
+            SearchSort::IncomingLinksAsc => "incoming_links_asc",
+            SearchSort::IncomingLinksDesc => "incoming_links_desc",
+            SearchSort::CreateTimestampAsc => "create_timestamp_asc",
+            SearchSort::CreateTimestampDesc => "create_timestamp_desc",
+            SearchSort::LastEditAsc => "last_edit_asc",
+            SearchSort::LastEditDesc => "last_edit_desc",
+        }
+    }
+}
+
+/// Yields search results one API batch at a time.
+///
+/// With a deterministic [`sort`](SearchSource::sort), such as
+/// [`SearchSort::CreateTimestampAsc`], a long run can be resumed from
+/// [`offset`](SearchSource::offset) later.
+pub struct SearchSource<'a> {
+    client: &'a wiki::Bot,
+    api_url: &'a str,
+    search: String,
+    namespace: Option<String>,
+    sort: Option<SearchSort>,
+    info: String,
+    offset: u64,
+    total_hits: Option<u64>,
+    done: bool,
+}
+
+impl<'a> SearchSource<'a> {
+    pub fn new(client: &'a wiki::Bot, api_url: &'a str, search: impl Into<String>) -> Self {
+        SearchSource {
+            client,
+            api_url,
+            search: search.into(),
+            namespace: None,
+            sort: None,
+            info: "totalhits".into(),
+            offset: 0,
+            total_hits: None,
+            done: false,
+        }
+    }
+
+    /// Restricts results to these namespaces, e.g. `"0|10"`.
+    pub fn namespace(mut self, ns: impl Into<String>) -> Self {
+        self.namespace = Some(ns.into());
+        self
+    }
+
+    pub fn sort(mut self, sort: SearchSort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Metadata to return, as accepted by `srinfo`, e.g.
+    /// `"totalhits|suggestion"`. Defaults to `totalhits`.
+    pub fn info(mut self, info: impl Into<String>) -> Self {
+        self.info = info.into();
+        self
+    }
+
+    /// Skips the first `offset` results.
+    pub fn start_at(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// How many results have been yielded, counting any skipped with
+    /// [`start_at`](SearchSource::start_at).
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The total number of matches, once the first batch is in and if
+    /// `totalhits` was requested.
+    pub fn total_hits(&self) -> Option<u64> {
+        self.total_hits
+    }
+}
+
+impl PageSource for SearchSource<'_> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<String>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let offset = self.offset.to_string();
+        let mut params = vec![
+            ("list", "search"),
+            ("srsearch", &*self.search),
+            ("srlimit", "max"),
+            ("srprop", ""),
+            ("srinfo", &*self.info),
+            ("sroffset", &offset),
+        ];
+        if let Some(ns) = &self.namespace {
+            params.push(("srnamespace", ns));
+        }
+        if let Some(sort) = self.sort {
+            params.push(("srsort", sort.as_str()));
+        }
+        let (batch, next) = query_batch(self.client, self.api_url, &params, &[]).await?;
+        if self.total_hits.is_none() {
+            self.total_hits = batch["searchinfo"]["totalhits"].as_u64();
+            if let Some(total) = self.total_hits {
+                info!("{total} results for {}", self.search);
+            }
+        }
+        let titles: Vec<_> = batch["search"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| Some(r["title"].as_str()?.to_owned()))
+            .collect();
+        match next
+            .iter()
+            .flatten()
+            .find(|(k, _)| k == "sroffset")
+            .and_then(|(_, v)| v.parse().ok())
+        {
+            Some(next) => self.offset = next,
+            None => {
+                self.offset += titles.len() as u64;
+                self.done = true;
+            }
+        }
+        Ok(Some(titles))
+    }
+}
@@ -0,0 +1,44 @@
+//! What the bot remembers between runs, kept as JSON lines under `STATE_DIR`
+//! (`state` by default), one file per kind of record.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Result;
+
+pub fn dir() -> PathBuf {
+    std::env::var_os("STATE_DIR").map_or_else(|| PathBuf::from("state"), PathBuf::from)
+}
+
+/// Adds `record` to the end of `file`.
+pub fn append<T: Serialize>(file: &str, record: &T) -> Result<()> {
+    let dir = dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(file);
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    writeln!(f, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Every record in `file`, oldest first. A missing file has none.
+pub fn read_all<T: DeserializeOwned>(file: &str) -> Result<Vec<T>> {
+    let path = dir().join(file);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).with_context(|| format!("in {}", path.display())))
+        .collect()
+}
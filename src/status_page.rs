@@ -0,0 +1,76 @@
+//! Keeps an on-wiki status page up to date, so that editors can see the bot
+//! is alive without asking.
+//!
+//! When `STATUS_PAGE` is set, e.g. to `User:DeadbeefBot/status`, every run
+//! updates its task's row there, a call to [`ROW`] with the time of the run,
+//! how it ended, its edit count and the next scheduled run. Rows of other
+//! tasks are kept.
+
+use std::env;
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::runs::RunReport;
+use crate::{fetch_content, Result, ENWIKI_API};
+
+const FLAGS: TaskFlags = TaskFlags::new("status_page", EditFlags::MINOR_BOT);
+
+/// The template each row calls.
+const ROW: &str = "User:DeadbeefBot/status/row";
+
+fn row(run: &RunReport, next: Option<DateTime<Utc>>) -> String {
+    let time = |t: DateTime<Utc>| t.format("%Y-%m-%d %H:%M UTC").to_string();
+    format!(
+        "{{{{{ROW}|task={}|last_run={}|result={}|edits={}|next={}}}}}",
+        run.task,
+        time(run.finished),
+        if run.error.is_some() { "failed" } else { "ok" },
+        run.edits,
+        next.map(time).unwrap_or_default(),
+    )
+}
+
+/// `text` with the row for `run` replaced or added, rows sorted by task.
+fn update_rows(text: &str, run: &RunReport, next: Option<DateTime<Utc>>) -> String {
+    let own = format!("{{{{{ROW}|task={}|", run.task);
+    let mut rows: Vec<_> = text
+        .lines()
+        .filter(|l| l.starts_with(&format!("{{{{{ROW}|")) && !l.starts_with(&own))
+        .map(str::to_owned)
+        .collect();
+    rows.push(row(run, next));
+    rows.sort();
+    rows.join("\n")
+}
+
+async fn try_update(title: &str, run: &RunReport, next: Option<DateTime<Utc>>) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+    let text = fetch_content(&client, ENWIKI_API, title)
+        .await
+        .unwrap_or_default();
+    let summary = format!("Status after running {}", run.task);
+    save(
+        &client,
+        PageSpec::Title(title.to_owned()),
+        Some(&text),
+        update_rows(&text, run, next),
+        summary,
+        None,
+        &FLAGS,
+    )
+    .await
+}
+
+/// Records `run` on `STATUS_PAGE`, if it is set. Failures are only logged.
+pub async fn update(run: &RunReport, next: Option<DateTime<Utc>>) {
+    let Ok(title) = env::var("STATUS_PAGE") else {
+        return;
+    };
+    if let Err(e) = try_update(&title, run, next).await {
+        warn!("failed to update [[{title}]]: {e}");
+    }
+}
@@ -0,0 +1,45 @@
+//! Pausing while the bot has new messages, as many bots on the English
+//! Wikipedia do, so that someone raising a problem on its talk page doesn't
+//! have to watch it go on editing.
+//!
+//! Before each save, [`edit::pre_save`](crate::edit::pre_save) asks the wiki
+//! being edited whether the bot has new messages. If it does, the run waits,
+//! logging an error every [`RECHECK`], until someone has read them, and then
+//! goes on.
+
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::{query_batch, throttle, Result};
+
+/// How often to look again while waiting.
+const RECHECK: Duration = Duration::from_secs(300);
+
+async fn has_messages(client: &wiki::Bot, api_url: &str) -> Result<bool> {
+    let (query, _) = query_batch(
+        client,
+        api_url,
+        &[("meta", "userinfo"), ("uiprop", "hasmsg")],
+        &[],
+    )
+    .await?;
+    Ok(query["userinfo"]["messages"].as_bool().unwrap_or(false))
+}
+
+/// Waits while the bot has new messages on the wiki the current task edits.
+pub async fn wait_for_read(client: &wiki::Bot) -> Result<()> {
+    let Some(api_url) = throttle::api_url() else {
+        return Ok(());
+    };
+    let mut waited = false;
+    while has_messages(client, &api_url).await? {
+        error!("the bot has new messages on its talk page, not editing until they are read");
+        waited = true;
+        tokio::time::sleep(RECHECK).await;
+    }
+    if waited {
+        info!("the new messages were read, editing again");
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Tasks by name, for running them from configuration rather than from their
+//! own binaries.
+
+use color_eyre::eyre::{bail, ContextCompat};
+
+use crate::{
+    articlehistory, bare_refs, category_redirects, dab_redirects, deletion_alerts,
+    domain_migration, https_upgrade, non_free, orphaned_talk, param_migration, peer_review,
+    remove_twitter_trackers, short_description, sitelinks, typos, wikiproject_tagging, Result,
+};
+
+/// The PetScan query `task2` runs on.
+const ARTICLEHISTORY_PETSCAN: &str = "https://petscan.wmflabs.org/?psid=26656482";
+
+pub const NAMES: &[&str] = &[
+    "articlehistory",
+    "bare_refs",
+    "category_redirects",
+    "dab_redirects",
+    "deletion_alerts",
+    "domain_migration",
+    "https_upgrade",
+    "non_free",
+    "orphaned_talk",
+    "param_migration",
+    "peer_review",
+    "remove_twitter_trackers",
+    "short_description",
+    "sitelinks",
+    "typos",
+    "wikiproject_tagging",
+];
+
+/// Runs the task called `name`, with `args` as its binary would take them.
+pub async fn run(name: &str, args: &[String]) -> Result<()> {
+    let arg = |usage: &str| {
+        args.first()
+            .map(String::as_str)
+            .with_context(|| format!("usage: {name} {usage}"))
+    };
+    let flag = |f: &str| args.iter().any(|a| a == f);
+    let opt = |f: &str| {
+        let at = args.iter().position(|a| a == f)?;
+        args.get(at + 1).map(String::as_str)
+    };
+    match name {
+        "articlehistory" => {
+            let petscan = args
+                .first()
+                .filter(|a| !a.starts_with("--"))
+                .map_or(ARTICLEHISTORY_PETSCAN, String::as_str);
+            let seed = match opt("--seed") {
+                Some(seed) => Some(seed.parse().ok().context("--seed takes a number")?),
+                None => None,
+            };
+            articlehistory::main(petscan, seed).await
+        }
+        "bare_refs" => bare_refs::main().await,
+        "category_redirects" => category_redirects::main().await,
+        "dab_redirects" => dab_redirects::main(flag("--tag")).await,
+        "deletion_alerts" => deletion_alerts::main().await,
+        "domain_migration" => domain_migration::main(arg("<mapping.json>")?).await,
+        "https_upgrade" => https_upgrade::main(arg("<allowlist.txt>")?).await,
+        "non_free" => non_free::main().await,
+        "orphaned_talk" => orphaned_talk::main(flag("--tag")).await,
+        "param_migration" => param_migration::main(arg("<mapping.json>")?).await,
+        "peer_review" => peer_review::main().await,
+        "remove_twitter_trackers" => match args.first().map(String::as_str) {
+            Some("all") | None => {
+                remove_twitter_trackers::main_all(&remove_twitter_trackers::sites()?).await
+            }
+            Some(key) => remove_twitter_trackers::main(&remove_twitter_trackers::site(key)?).await,
+        },
+        "short_description" => short_description::main(flag("--supervised")).await,
+        "sitelinks" => sitelinks::main(arg("<category>")?).await,
+        "typos" => typos::main(arg("<search query>")?).await,
+        "wikiproject_tagging" => wikiproject_tagging::main().await,
+        _ => bail!("no task named {name}"),
+    }
+}
@@ -0,0 +1,232 @@
+//! How fast and when tasks edit, per wiki.
+//!
+//! Bot policy differs between wikis, so the pace of edits comes from a named
+//! profile rather than a fixed sleep. [`RATE_PROFILES`] can add or change
+//! profiles and assign them to wikis by API host, and limit editing on a wiki
+//! to some hours of the day in UTC, e.g.
+//!
+//! ```json
+//! {
+//!     "profiles": {"trial": {"edits_per_minute": 1}},
+//!     "wikis": {"zh.wikipedia.org": "trial"},
+//!     "hours": {"el.wikipedia.org": [{"from": "22:00", "to": "06:00"}]}
+//! }
+//! ```
+//!
+//! Wikis not listed use the `default` profile, and edit at any hour. Edits made
+//! as part of a [trial](crate::trial) use the `trial` profile on any wiki.
+//! Outside of a wiki's hours, [`edit::pre_save`](crate::edit::pre_save) waits
+//! for the next window, while reads go on. Optional, and read again on
+//! [`reload`].
+//!
+//! Every edit waits for a [`permit`] first. Permits are handed out per wiki
+//! rather than per task, so tasks running at once share the wiki's pace.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{NaiveTime, TimeDelta, Utc};
+use color_eyre::eyre::{eyre, ContextCompat};
+use serde::{Deserialize, Deserializer};
+use tokio::time::Instant;
+use tracing::info;
+use url::Url;
+
+use crate::{checkpoint, trial, Result};
+
+pub const RATE_PROFILES: &str = "rate-profiles.json";
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub edits_per_minute: f64,
+}
+
+impl Profile {
+    pub const fn per_minute(edits_per_minute: f64) -> Profile {
+        Profile { edits_per_minute }
+    }
+
+    pub fn interval(self) -> Duration {
+        Duration::from_secs_f64(60.0 / self.edits_per_minute)
+    }
+}
+
+/// Profiles available without any configuration.
+const BUILTIN: &[(&str, Profile)] = &[
+    ("default", Profile::per_minute(10.0)),
+    ("slow", Profile::per_minute(2.0)),
+    ("trial", Profile::per_minute(1.0)),
+];
+
+/// Hours of the day in UTC, from `from` until `to`, which may be on the next
+/// day.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    #[serde(deserialize_with = "hh_mm")]
+    pub from: NaiveTime,
+    #[serde(deserialize_with = "hh_mm")]
+    pub to: NaiveTime,
+}
+
+fn hh_mm<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(d)?;
+    NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+impl Window {
+    /// How long until the window opens after `now`, zero if it is open.
+    fn until_open(self, now: NaiveTime) -> TimeDelta {
+        let open = if self.from <= self.to {
+            self.from <= now && now < self.to
+        } else {
+            now >= self.from || now < self.to
+        };
+        if open {
+            return TimeDelta::zero();
+        }
+        let wait = self.from - now;
+        if wait < TimeDelta::zero() {
+            wait + TimeDelta::days(1)
+        } else {
+            wait
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    /// Profile names by API host.
+    #[serde(default)]
+    wikis: HashMap<String, String>,
+    /// When each wiki may be edited, by API host.
+    #[serde(default)]
+    hours: HashMap<String, Vec<Window>>,
+}
+
+/// The configuration in `text`, the contents of [`RATE_PROFILES`] if there is
+/// one.
+fn parse(text: Option<&str>) -> Result<Config, String> {
+    let config: Config = match text {
+        Some(s) => serde_json::from_str(s).map_err(|e| format!("invalid {RATE_PROFILES}: {e}"))?,
+        None => return Ok(Config::default()),
+    };
+    for (name, profile) in &config.profiles {
+        let rate = profile.edits_per_minute;
+        if !(rate > 0.0 && rate.is_finite()) {
+            return Err(format!(
+                "invalid {RATE_PROFILES}: profile {name} has {rate} edits per minute"
+            ));
+        }
+    }
+    Ok(config)
+}
+
+fn load() -> Result<Config, String> {
+    let text = checkpoint::read_config(RATE_PROFILES).map_err(|e| format!("{e:#}"))?;
+    parse(text.as_deref())
+}
+
+tokio::task_local! {
+    /// The API URL of the wiki the current task edits.
+    static WIKI: String;
+}
+
+static CONFIG: LazyLock<RwLock<Result<Config, String>>> = LazyLock::new(|| RwLock::new(load()));
+
+/// When each wiki may next be edited, by API host.
+static NEXT_EDIT: LazyLock<Mutex<HashMap<String, Instant>>> = LazyLock::new(Default::default);
+
+/// Reads [`RATE_PROFILES`] again. If it has become invalid, the profiles read
+/// before stay in use.
+pub fn reload() -> Result<()> {
+    let config = load().map_err(|e| eyre!("{e}"))?;
+    *CONFIG.write().unwrap() = Ok(config);
+    Ok(())
+}
+
+fn host(api_url: &str) -> Result<String> {
+    Url::parse(api_url)?
+        .host_str()
+        .map(str::to_owned)
+        .with_context(|| format!("no host in {api_url}"))
+}
+
+/// The profile for the wiki at `api_url`.
+pub fn profile(api_url: &str) -> Result<Profile> {
+    let config = CONFIG.read().unwrap();
+    let config = config.as_ref().map_err(|e| eyre!("{e}"))?;
+    let name = if trial::in_trial() {
+        "trial"
+    } else {
+        config
+            .wikis
+            .get(&host(api_url)?)
+            .map_or("default", String::as_str)
+    };
+    config
+        .profiles
+        .get(name)
+        .copied()
+        .or_else(|| BUILTIN.iter().find(|(n, _)| *n == name).map(|(_, p)| *p))
+        .with_context(|| format!("no rate profile named {name}"))
+}
+
+/// Waits for a turn to edit the wiki at `api_url`. Turns on a wiki come one
+/// interval of its profile apart, whichever task takes them.
+pub async fn permit(api_url: &str) -> Result<()> {
+    let interval = profile(api_url)?.interval();
+    let host = host(api_url)?;
+    let turn = {
+        let mut next = NEXT_EDIT.lock().unwrap();
+        let now = Instant::now();
+        let turn = next.get(&host).map_or(now, |&at| at.max(now));
+        next.insert(host, turn + interval);
+        turn
+    };
+    tokio::time::sleep_until(turn).await;
+    Ok(())
+}
+
+/// Runs `run` as editing the wiki at `api_url`.
+pub async fn on_wiki<T>(api_url: &str, run: impl Future<Output = T>) -> T {
+    WIKI.scope(api_url.to_owned(), run).await
+}
+
+/// The API URL of the wiki the current task edits, if known.
+pub fn api_url() -> Option<String> {
+    WIKI.try_with(Clone::clone).ok()
+}
+
+/// The host of the wiki the current task edits, if known.
+pub fn wiki() -> Option<String> {
+    host(&api_url()?).ok()
+}
+
+/// Waits until the wiki the current task edits may be edited.
+pub async fn edit_hours() -> Result<()> {
+    let Some(wiki) = wiki() else {
+        return Ok(());
+    };
+    let windows = {
+        let config = CONFIG.read().unwrap();
+        let config = config.as_ref().map_err(|e| eyre!("{e}"))?;
+        config.hours.get(&wiki).cloned().unwrap_or_default()
+    };
+    let now = Utc::now().time();
+    let Some(wait) = windows.iter().map(|w| w.until_open(now)).min() else {
+        return Ok(());
+    };
+    let wait = wait.to_std()?;
+    if !wait.is_zero() {
+        info!("waiting {wait:?} for the edit hours of {wiki}");
+        tokio::time::sleep(wait).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Where the time goes on each page.
+//!
+//! Tasks wrap the treatment of a page in [`page`], which opens a `page` span
+//! and adds up the time spent in each [`Phase`] while it runs. The slowest
+//! pages are logged at the end of the run, and pages treated without an
+//! error are [`checkpoint`](crate::checkpoint)ed.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug_span, info, info_span, Instrument};
+
+use crate::{checkpoint, dashboard};
+
+/// How many of the slowest pages are kept.
+const SLOWEST: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Getting wikitext or HTML.
+    Fetch,
+    /// Converting between HTML and wikitext.
+    Parse,
+    /// Asking sites other than the wikis, like archive.org.
+    External,
+    Save,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Timings {
+    pub fetch: Duration,
+    pub parse: Duration,
+    pub external: Duration,
+    pub save: Duration,
+}
+
+impl Timings {
+    fn add(&mut self, phase: Phase, d: Duration) {
+        *match phase {
+            Phase::Fetch => &mut self.fetch,
+            Phase::Parse => &mut self.parse,
+            Phase::External => &mut self.external,
+            Phase::Save => &mut self.save,
+        } += d;
+    }
+}
+
+struct Current {
+    title: String,
+    timings: RefCell<Timings>,
+}
+
+tokio::task_local! {
+    static CURRENT: Current;
+}
+
+/// The slowest pages so far, slowest first.
+static SLOWEST_PAGES: LazyLock<Mutex<Vec<(String, Duration, Timings)>>> =
+    LazyLock::new(Default::default);
+
+/// Treats `title` with `treat`, timing it.
+pub async fn page<T, E>(title: &str, treat: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let start = Instant::now();
+    let current = Current {
+        title: title.to_owned(),
+        timings: RefCell::new(Timings::default()),
+    };
+    dashboard::started(title);
+    let (res, timings) = CURRENT
+        .scope(current, async {
+            let res = treat.instrument(info_span!("page", title)).await;
+            (res, CURRENT.with(|c| *c.timings.borrow()))
+        })
+        .await;
+    dashboard::finished(title);
+    let total = start.elapsed();
+    let mut slowest = SLOWEST_PAGES.lock().unwrap();
+    let at = slowest.partition_point(|(_, d, _)| *d >= total);
+    if at < SLOWEST {
+        slowest.insert(at, (title.to_owned(), total, timings));
+        slowest.truncate(SLOWEST);
+    }
+    drop(slowest);
+    if res.is_ok() {
+        checkpoint::done(title);
+    }
+    res
+}
+
+/// Runs `f` as part of `phase` of the current page, if there is one.
+pub async fn phase<T>(phase: Phase, f: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let res = f.instrument(debug_span!("phase", ?phase)).await;
+    let _ = CURRENT.try_with(|c| c.timings.borrow_mut().add(phase, start.elapsed()));
+    res
+}
+
+/// The title of the page being treated, if there is one.
+pub fn current_page() -> Option<String> {
+    CURRENT.try_with(|c| c.title.clone()).ok()
+}
+
+pub fn log_slowest() {
+    let slowest = SLOWEST_PAGES.lock().unwrap();
+    if slowest.is_empty() {
+        return;
+    }
+    let mut table = format!(
+        "slowest pages:\n{:>8} {:>8} {:>8} {:>8} {:>8}  page",
+        "total", "fetch", "parse", "external", "save"
+    );
+    let secs = |d: Duration| format!("{:.1}s", d.as_secs_f64());
+    for (title, total, t) in slowest.iter() {
+        table += &format!(
+            "\n{:>8} {:>8} {:>8} {:>8} {:>8}  [[{title}]]",
+            secs(*total),
+            secs(t.fetch),
+            secs(t.parse),
+            secs(t.external),
+            secs(t.save)
+        );
+    }
+    info!("{table}");
+}
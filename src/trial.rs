@@ -0,0 +1,200 @@
+//! `--trial` runs, for the trials a BRFA approves before a task is.
+//!
+//! [`TRIALS`] describes the trial of each task, e.g.
+//!
+//! ```json
+//! {"bare_refs": {"brfa": "Wikipedia:Bots/Requests for approval/DeadbeefBot 5", "edits": 50}}
+//! ```
+//!
+//! In a trial run, [`edit::pre_save`](crate::edit::pre_save) adds the trial's
+//! `marker`, `(trial)` unless given, to every summary, and refuses to save once
+//! the task has made the edits the trial allows, counting those of earlier
+//! trial runs. The trial's edits are listed on [`PAGE`]`/<task>` at the end of
+//! each run, for BAG to review. Saving a page for a task without a trial fails,
+//! so that a typo doesn't make an unmarked, uncapped run. Saves made outside of
+//! a page, like reports, aren't part of trials.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{bail, eyre};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use wiki::req::PageSpec;
+
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::{checkpoint, state, timing, Result};
+
+pub const TRIALS: &str = "trials.json";
+
+/// Where each task's trial edits are listed.
+pub const PAGE: &str = "User:DeadbeefBot/Trials";
+
+/// The trial's edits, as a [`state`] file.
+const EDITS: &str = "trial-edits.jsonl";
+
+const FLAGS: TaskFlags = TaskFlags::new("trial", EditFlags::BOT);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Trial {
+    /// The title of the BRFA.
+    pub brfa: String,
+    /// How many edits the trial allows.
+    pub edits: u64,
+    #[serde(default = "default_marker")]
+    pub marker: String,
+}
+
+fn default_marker() -> String {
+    "(trial)".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrialEdit {
+    at: DateTime<Utc>,
+    task: String,
+    title: String,
+    revid: Option<u64>,
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+static TRIAL_LIST: LazyLock<Result<HashMap<String, Trial>, String>> =
+    LazyLock::new(|| match checkpoint::read_config(TRIALS) {
+        Ok(Some(s)) => serde_json::from_str(&s).map_err(|e| format!("invalid {TRIALS}: {e}")),
+        Ok(None) => Ok(HashMap::new()),
+        Err(e) => Err(format!("{e:#}")),
+    });
+
+/// Edits made or being made in each task's trial, by tasks that saved in
+/// this run.
+static MADE: LazyLock<Mutex<HashMap<String, u64>>> = LazyLock::new(Default::default);
+
+/// Reads `--trial` out of a command line. Only the first call has an effect.
+pub fn from_args(args: &[String]) {
+    let _ = ENABLED.set(args.iter().any(|a| a == "--trial"));
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// The trial of `task`.
+pub fn get(task: &str) -> Result<&'static Trial> {
+    let trials = TRIAL_LIST.as_ref().map_err(|e| eyre!("{e}"))?;
+    trials
+        .get(task)
+        .ok_or_else(|| eyre!("no trial for {task} in {TRIALS}"))
+}
+
+fn edits(task: &str) -> Result<Vec<TrialEdit>> {
+    let edits = state::read_all::<TrialEdit>(EDITS)?;
+    Ok(edits.into_iter().filter(|e| e.task == task).collect())
+}
+
+/// Whether an edit by a task now is part of its trial.
+pub fn in_trial() -> bool {
+    enabled() && timing::current_page().is_some()
+}
+
+/// `summary` for an edit by `task`, marked as part of its trial in a trial
+/// run. Fails if the trial has no edits left.
+pub fn summary(task: &str, summary: String) -> Result<String> {
+    if !in_trial() {
+        return Ok(summary);
+    }
+    let trial = get(task)?;
+    let mut made = MADE.lock().unwrap();
+    let made = match made.entry(task.to_owned()) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => e.insert(edits(task)?.len() as u64),
+    };
+    // counted before saving, so that concurrent saves can't go over
+    if *made >= trial.edits {
+        bail!(
+            "the trial of {task} has made all {} of its edits",
+            trial.edits
+        );
+    }
+    *made += 1;
+    Ok(format!("{summary} {}", trial.marker))
+}
+
+/// Records an edit by `task` in its trial, from the API's response to it.
+pub fn record(task: &str, res: &Value) {
+    if !in_trial() {
+        return;
+    }
+    let edit = &res["edit"];
+    let record = TrialEdit {
+        at: Utc::now(),
+        task: task.to_owned(),
+        title: edit["title"].as_str().unwrap_or_default().to_owned(),
+        revid: edit["newrevid"].as_u64(),
+    };
+    if let Err(e) = state::append(EDITS, &record) {
+        warn!(
+            "failed to record the trial edit to [[{}]]: {e}",
+            record.title
+        );
+    }
+}
+
+fn render(trial: &Trial, edits: &[TrialEdit]) -> String {
+    let mut s = format!(
+        "Edits made for the trial in [[{}]], as of ~~~~~. {} of {}.\n\n",
+        trial.brfa,
+        edits.len(),
+        trial.edits
+    );
+    for edit in edits {
+        let _ = match edit.revid {
+            Some(revid) => writeln!(s, "# [[Special:Diff/{revid}|{}]]", edit.title),
+            None => writeln!(s, "# [[{}]] (no change)", edit.title),
+        };
+    }
+    s
+}
+
+async fn list(client: &wiki::Bot, task: &str) -> Result<()> {
+    let trial = get(task)?;
+    let edits = edits(task)?;
+    let title = format!("{PAGE}/{task}");
+    save(
+        client,
+        PageSpec::Title(title.clone()),
+        None,
+        render(trial, &edits),
+        format!("Listing trial edits for [[{}]]", trial.brfa),
+        None,
+        &FLAGS,
+    )
+    .await?;
+    info!(
+        "listed {} trial edit(s) of {task} on [[{title}]]",
+        edits.len()
+    );
+    Ok(())
+}
+
+/// Runs `run`, then lists the edits of each trial that saved during it.
+/// Failing to list them is only logged.
+pub async fn listed<T>(client: &wiki::Bot, run: impl Future<Output = T>) -> T {
+    let res = run.await;
+    if !enabled() {
+        return res;
+    }
+    let tasks: Vec<_> = MADE.lock().unwrap().keys().cloned().collect();
+    for task in tasks {
+        if let Err(e) = list(client, &task).await {
+            warn!("failed to list the trial edits of {task}: {e}");
+        }
+    }
+    res
+}
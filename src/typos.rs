@@ -0,0 +1,262 @@
+//! Fixes typos using an on-wiki ruleset in the AutoWikiBrowser format:
+//!
+//! ```text
+//! <Typo word="Abandoned" find="\b([Aa])bandonned\b" replace="$1bandoned" />
+//! ```
+//!
+//! Rules are only applied to running prose: text inside links, templates,
+//! extension tags, code, quotations and italics is left alone.
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::Context;
+use fancy_regex::Regex;
+use parsoid::WikinodeIterator;
+use tracing::{debug, info, warn};
+use wiki::req::PageSpec;
+
+use crate::accounts::bot_for;
+use crate::edit::{save, EditFlags, TaskFlags};
+use crate::source::{PageSource, SearchSort, SearchSource};
+use crate::{
+    check_nobots, enwiki_parsoid, fetch_content, fetch_contents, policy, run_lock, selection,
+    worklist, Parsoid, Result, CONTENT_BATCH, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("typos", EditFlags::MINOR_BOT);
+
+pub const RULESET: &str = "Wikipedia:AutoWikiBrowser/Typos";
+
+/// Elements whose contents are never prose we should touch.
+const SKIP_ELEMENTS: &[&str] = &[
+    "a",
+    "q",
+    "blockquote",
+    "code",
+    "pre",
+    "tt",
+    "kbd",
+    "samp",
+    "var",
+    "i",
+    "cite",
+    "math",
+];
+
+pub struct Rule {
+    pub word: String,
+    pub find: Regex,
+    pub replace: String,
+}
+
+fn attr<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{name}=\""))? + name.len() + 2;
+    let len = line[start..].find('"')?;
+    Some(&line[start..start + len])
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// AWB uses .NET replacement syntax, where `$1bandoned` means group 1
+/// followed by `bandoned`. The regex crate would read a group named
+/// `1bandoned`.
+fn convert_replacement(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && chars.peek().is_some_and(char::is_ascii_digit) {
+            out.push_str("${");
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                out.push(d);
+            }
+            out.push('}');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parses a ruleset page, skipping disabled or invalid rules.
+pub fn parse_rules(text: &str) -> Vec<Rule> {
+    let mut in_comment = false;
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        // rules are commented out to disable them
+        if line.contains("<!--") {
+            in_comment = true;
+        }
+        let commented = in_comment;
+        if line.contains("-->") {
+            in_comment = false;
+        }
+        if commented || !line.trim_start().starts_with("<Typo ") || line.contains("disabled=") {
+            continue;
+        }
+        let (Some(word), Some(find), Some(replace)) = (
+            attr(line, "word"),
+            attr(line, "find"),
+            attr(line, "replace"),
+        ) else {
+            continue;
+        };
+        match Regex::new(&unescape(find)) {
+            Ok(find) => rules.push(Rule {
+                word: unescape(word),
+                find,
+                replace: convert_replacement(&unescape(replace)),
+            }),
+            Err(e) => debug!("skipping rule for {word}: {e}"),
+        }
+    }
+    rules
+}
+
+/// Applies every rule to the text outside double quotes, counting hits.
+fn apply(rules: &[Rule], text: &str, counts: &mut BTreeMap<String, usize>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    for (i, segment) in text.split('"').enumerate() {
+        if i > 0 {
+            out.push('"');
+        }
+        // odd segments are quoted
+        if i % 2 == 1 {
+            out.push_str(segment);
+            continue;
+        }
+        let mut segment = segment.to_owned();
+        for rule in rules {
+            let hits = rule.find.find_iter(&segment).count();
+            if hits == 0 {
+                continue;
+            }
+            let new = rule.find.try_replacen(&segment, 0, rule.replace.as_str())?;
+            if new != segment {
+                *counts.entry(rule.word.clone()).or_default() += hits;
+                segment = new.into_owned();
+            }
+        }
+        out.push_str(&segment);
+    }
+    Ok(out)
+}
+
+pub fn summary(counts: &BTreeMap<String, usize>) -> String {
+    let fixes = counts
+        .iter()
+        .map(|(word, n)| format!("{word} ({n})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("[[WP:AWB/T|Typo fixing]]: {fixes}")
+}
+
+pub async fn treat(
+    client: &wiki::Bot,
+    parsoid: &Parsoid,
+    rules: &[Rule],
+    title: &str,
+) -> Result<()> {
+    let code = parsoid.get(title).await?.into_mutable();
+    let rev = code.revision_id().unwrap();
+    if code.filter_templates()?.iter().any(check_nobots) {
+        return Ok(());
+    }
+
+    let mut counts = BTreeMap::new();
+    let text_nodes: Vec<_> = code.as_node().descendants().text_nodes().collect();
+    for node in text_nodes {
+        let is_prose = node.as_node().ancestors().all(|a| {
+            let Some(el) = a.as_element() else {
+                return true;
+            };
+            let attrs = el.attributes.borrow();
+            // template output and extension tags carry these
+            !SKIP_ELEMENTS.contains(&&*el.name.local)
+                && !attrs.contains("typeof")
+                && !attrs.get("about").is_some_and(|a| a.starts_with("#mwt"))
+        });
+        if !is_prose {
+            continue;
+        }
+        let new = apply(rules, &node.borrow(), &mut counts)?;
+        *node.borrow_mut() = new;
+    }
+
+    if counts.is_empty() {
+        return Ok(());
+    }
+    for (word, &n) in &counts {
+        worklist::hit(word, n);
+    }
+
+    let text = parsoid.transform_to_wikitext(&code).await?;
+    info!(
+        "fixing {} typo(s) on [[{title}]]",
+        counts.values().sum::<usize>()
+    );
+    save(
+        client,
+        PageSpec::Title(title.to_owned()),
+        None,
+        text,
+        summary(&counts),
+        Some(rev as u32),
+        &FLAGS,
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn main(search: &str) -> Result<()> {
+    let client = bot_for(ENWIKI_API, &FLAGS).await?;
+
+    run_lock::locked(&client, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&client, ENWIKI_API).await?;
+        let parsoid = enwiki_parsoid()?;
+
+        let rules = parse_rules(&fetch_content(&client, ENWIKI_API, RULESET).await?);
+        info!("loaded {} rules from [[{RULESET}]]", rules.len());
+
+        // Oldest pages first, so that the offset stays meaningful while the run
+        // goes on and new pages match the search.
+        let search = SearchSource::new(&client, ENWIKI_API, search)
+            .namespace("0")
+            .sort(SearchSort::CreateTimestampAsc);
+        let mut source = scope.source(search);
+        while let Some(titles) = source.next_batch().await.wrap_err("searching")? {
+            for chunk in titles.chunks(CONTENT_BATCH) {
+                let chunk: Vec<_> = chunk.iter().map(String::as_str).collect();
+                let pages = match fetch_contents(&client, ENWIKI_API, &chunk).await {
+                    Ok(pages) => pages,
+                    Err(e) => {
+                        warn!("failed to fetch batch starting at [[{}]]: {e}", chunk[0]);
+                        continue;
+                    }
+                };
+                for page in pages {
+                    if !rules
+                        .iter()
+                        .any(|r| r.find.is_match(&page.text).unwrap_or(true))
+                    {
+                        debug!("no rule matches [[{}]]", page.title);
+                        continue;
+                    }
+                    let treated = || treat(&client, &parsoid, &rules, &page.title);
+                    policy::page(FLAGS.task, &page.title, treated).await?;
+                }
+            }
+            info!(
+                "done with search results up to offset {}",
+                source.inner.offset()
+            );
+        }
+
+        Ok(())
+    })
+    .await
+}
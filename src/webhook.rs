@@ -0,0 +1,87 @@
+//! Posts run summaries and fatal errors to a chat room, so that failures are
+//! noticed without tailing logs.
+//!
+//! `WEBHOOK_URL` is a Discord webhook, or a Matrix generic webhook such as
+//! hookshot's. The format follows the URL, and can be forced with
+//! `WEBHOOK_FORMAT` set to `discord` or `matrix`. Nothing is posted when
+//! `WEBHOOK_URL` is unset, and a failed post is only logged.
+
+use std::env;
+
+use color_eyre::eyre::bail;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::runs::RunReport;
+use crate::{http, ratelimit, Result};
+
+/// Discord rejects longer messages.
+const MAX_LEN: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Discord,
+    Matrix,
+}
+
+impl Format {
+    fn for_url(url: &str) -> Result<Format> {
+        Ok(match env::var("WEBHOOK_FORMAT").as_deref() {
+            Ok("discord") => Format::Discord,
+            Ok("matrix") => Format::Matrix,
+            Ok(other) => bail!("unknown WEBHOOK_FORMAT {other}"),
+            Err(_) if url.contains("discord.com/") || url.contains("discordapp.com/") => {
+                Format::Discord
+            }
+            Err(_) => Format::Matrix,
+        })
+    }
+
+    fn body(self, text: &str) -> Value {
+        match self {
+            Format::Discord => json!({ "content": text, "username": "DeadbeefBot" }),
+            Format::Matrix => json!({ "text": text, "username": "DeadbeefBot" }),
+        }
+    }
+}
+
+fn truncate(text: &str) -> &str {
+    match text.char_indices().nth(MAX_LEN - 1) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
+}
+
+async fn try_post(url: &str, text: &str) -> Result<()> {
+    let body = Format::for_url(url)?.body(truncate(text));
+    ratelimit::send(http()?.external.post(url).json(&body))
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Posts `text` to `WEBHOOK_URL`, if it is set.
+pub async fn post(text: &str) {
+    let Ok(url) = env::var("WEBHOOK_URL") else {
+        return;
+    };
+    if let Err(e) = try_post(&url, text).await {
+        warn!("failed to post to the webhook: {e}");
+    }
+}
+
+/// Posts how a run went.
+pub async fn report(run: &RunReport) {
+    let RunReport {
+        task,
+        edits,
+        failed,
+        ..
+    } = run;
+    let minutes = (run.finished - run.started).num_minutes();
+    let text = match &run.error {
+        None => format!("{task} finished after {minutes} min: {edits} edit(s), {failed} failed"),
+        Some(e) => format!("{task} failed after {minutes} min and {edits} edit(s): {e}"),
+    };
+    post(&text).await;
+}
@@ -0,0 +1,114 @@
+//! A small client for the Wikidata API.
+
+use std::collections::HashMap;
+
+use color_eyre::eyre::bail;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{http, maxlag, Result};
+
+const API: &str = "https://www.wikidata.org/w/api.php";
+
+/// `wbgetentities` accepts at most this many titles per request.
+pub const BATCH: usize = 50;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Term {
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Sitelink {
+    pub site: String,
+    pub title: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Entity {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub labels: HashMap<String, Term>,
+    #[serde(default)]
+    pub descriptions: HashMap<String, Term>,
+    #[serde(default)]
+    pub sitelinks: HashMap<String, Sitelink>,
+}
+
+#[derive(Deserialize)]
+struct EntitiesResponse {
+    #[serde(default)]
+    entities: HashMap<String, Entity>,
+    error: Option<Value>,
+}
+
+#[derive(Clone)]
+pub struct Wikidata {
+    client: reqwest::Client,
+}
+
+impl Wikidata {
+    pub fn new() -> Result<Self> {
+        Ok(Wikidata {
+            client: http()?.wikimedia.clone(),
+        })
+    }
+
+    /// Fetches the items linked to `titles` on `site` (e.g. `enwiki`).
+    ///
+    /// Titles without an item are left out of the result.
+    pub async fn entities_for_titles(
+        &self,
+        site: &str,
+        titles: &[&str],
+        props: &str,
+        languages: Option<&str>,
+    ) -> Result<Vec<Entity>> {
+        let mut entities = Vec::new();
+        for chunk in titles.chunks(BATCH) {
+            let mut req = self.client.get(API).query(&[
+                ("action", "wbgetentities"),
+                ("format", "json"),
+                ("sites", site),
+                ("titles", &chunk.join("|")),
+                ("props", props),
+            ]);
+            if let Some(languages) = languages {
+                req = req.query(&[("languages", languages), ("sitefilter", site)]);
+            }
+            let res: EntitiesResponse = serde_json::from_value(maxlag::api(req).await?)?;
+            if let Some(e) = res.error {
+                bail!("wikidata error: {e}");
+            }
+            // missing titles are keyed by negative numbers
+            entities.extend(
+                res.entities
+                    .into_iter()
+                    .filter(|(k, _)| k.starts_with('Q'))
+                    .map(|(_, v)| v),
+            );
+        }
+        Ok(entities)
+    }
+
+    /// Descriptions in `lang` for `titles` on `site`, keyed by local title.
+    pub async fn descriptions(
+        &self,
+        site: &str,
+        titles: &[&str],
+        lang: &str,
+    ) -> Result<HashMap<String, String>> {
+        let entities = self
+            .entities_for_titles(site, titles, "descriptions|sitelinks", Some(lang))
+            .await?;
+        Ok(entities
+            .into_iter()
+            .filter_map(|mut e| {
+                let title = e.sitelinks.remove(site)?.title;
+                let desc = e.descriptions.remove(lang)?.value;
+                Some((title, desc))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,272 @@
+//! Tags talk pages with WikiProject banners based on category membership.
+//!
+//! Projects opt in by listing a configuration page on [`INDEX`]. Each
+//! configuration page holds a JSON [`ProjectCfg`].
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+use wiki::req::PageSpec;
+
+use crate::client::Client;
+use crate::edit::{after_edit, save, EditFlags, TaskFlags};
+use crate::siteinfo::SiteInfo;
+use crate::{
+    accounts, check_nobots_wikitext, fetch_content, policy, query_raw, run_lock, selection,
+    wikitext, Result, ENWIKI_API,
+};
+
+const FLAGS: TaskFlags = TaskFlags::new("wikiproject_tagging", EditFlags::MINOR_BOT);
+
+/// List of opted-in projects, one `[[link]]` to a configuration page per line.
+const INDEX: &str = "User:DeadbeefBot/WikiProject tagging";
+
+#[derive(Deserialize, Debug)]
+pub struct ProjectCfg {
+    /// Banner template name, without the `Template:` prefix.
+    pub banner: String,
+    /// Pages in any of these category trees are candidates.
+    pub categories: Vec<String>,
+    /// Candidates must also be in every one of these category trees.
+    #[serde(default)]
+    pub intersect: Vec<String>,
+    /// Pages in any of these category trees are never tagged.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default = "default_depth")]
+    pub depth: u32,
+    /// Parameters to add to the banner, e.g. `class`.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+fn default_depth() -> u32 {
+    3
+}
+
+fn normalize(title: &str) -> String {
+    let title = title.trim().replace('_', " ");
+    let title = title.trim_start_matches("Template:");
+    let mut chars = title.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// All articles in the category tree rooted at `root`, down to `depth` levels.
+async fn category_tree(client: &wiki::Bot, root: &str, depth: u32) -> Result<HashSet<String>> {
+    let mut pages = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([(normalize(root), 0)]);
+
+    while let Some((cat, level)) = queue.pop_front() {
+        if !seen.insert(cat.clone()) {
+            continue;
+        }
+        let cat = if cat.starts_with("Category:") {
+            cat
+        } else {
+            format!("Category:{cat}")
+        };
+        debug!("walking [[{cat}]] at depth {level}");
+        let batches = query_raw(
+            client,
+            ENWIKI_API,
+            &[
+                ("list", "categorymembers"),
+                ("cmtitle", &cat),
+                ("cmnamespace", "0|14"),
+                ("cmlimit", "max"),
+            ],
+        )
+        .await?;
+        for batch in batches {
+            for member in batch["categorymembers"].as_array().into_iter().flatten() {
+                let Some(title) = member["title"].as_str() else {
+                    continue;
+                };
+                if member["ns"] == 14 {
+                    if level < depth {
+                        queue.push_back((title.to_owned(), level + 1));
+                    }
+                } else {
+                    pages.insert(title.to_owned());
+                }
+            }
+        }
+    }
+
+    Ok(pages)
+}
+
+/// The banner's name along with every redirect to it.
+async fn banner_aliases(client: &wiki::Bot, banner: &str) -> Result<HashSet<String>> {
+    let target = format!("Template:{}", normalize(banner));
+    let batches = query_raw(
+        client,
+        ENWIKI_API,
+        &[
+            ("list", "backlinks"),
+            ("bltitle", &target),
+            ("blfilterredir", "redirects"),
+            ("blnamespace", "10"),
+            ("bllimit", "max"),
+        ],
+    )
+    .await?;
+    let mut aliases: HashSet<_> = batches
+        .iter()
+        .flat_map(|b| b["backlinks"].as_array().into_iter().flatten())
+        .filter_map(|l| l["title"].as_str())
+        .map(normalize)
+        .collect();
+    aliases.insert(normalize(banner));
+    Ok(aliases)
+}
+
+async fn candidates(client: &wiki::Bot, cfg: &ProjectCfg) -> Result<Vec<String>> {
+    let mut pages = HashSet::new();
+    for cat in &cfg.categories {
+        pages.extend(category_tree(client, cat, cfg.depth).await?);
+    }
+    for cat in &cfg.intersect {
+        let tree = category_tree(client, cat, cfg.depth).await?;
+        pages.retain(|p| tree.contains(p));
+    }
+    for cat in &cfg.exclude {
+        let tree = category_tree(client, cat, cfg.depth).await?;
+        pages.retain(|p| !tree.contains(p));
+    }
+    let mut pages: Vec<_> = pages.into_iter().collect();
+    pages.sort();
+    Ok(pages)
+}
+
+fn banner_wikitext(cfg: &ProjectCfg) -> String {
+    let mut s = format!("{{{{{}", cfg.banner);
+    for (k, v) in &cfg.params {
+        s.push_str(&format!("|{k}={v}"));
+    }
+    s.push_str("}}");
+    s
+}
+
+/// Banners go at the top of the talk page, so only the lead section is
+/// fetched and saved.
+pub async fn treat(
+    client: &Client,
+    site: &SiteInfo,
+    article: &str,
+    cfg: &ProjectCfg,
+    aliases: &HashSet<String>,
+) -> Result<()> {
+    let talk = site.talk_page(article);
+    let banner = banner_wikitext(cfg);
+    let summary = format!("Tagging for [[Template:{}|{}]]", cfg.banner, cfg.banner);
+
+    let Some(lead) = client.fetch_section(&talk, 0).await? else {
+        info!("creating [[{talk}]]");
+        client
+            .call(|bot| {
+                let (talk, banner, summary) = (talk.clone(), banner.clone(), summary.clone());
+                async move {
+                    let talk = PageSpec::Title(talk);
+                    save(&bot, talk, Some(""), banner, summary, None, &FLAGS).await?;
+                    Ok(())
+                }
+            })
+            .await?;
+        return Ok(());
+    };
+
+    if check_nobots_wikitext(&lead.text) {
+        return Ok(());
+    }
+    let templates = wikitext::parse_templates(&lead.text)?;
+
+    let shell_aliases = include_str!("banneralias.txt");
+    let shell = templates.iter().find(|t| {
+        let name = normalize(&t.name);
+        shell_aliases.lines().any(|alias| normalize(alias) == name)
+    });
+
+    // nested templates are parsed too, so this catches banners in the shell
+    if templates
+        .iter()
+        .any(|t| aliases.contains(&normalize(&t.name)))
+    {
+        debug!("[[{talk}]] already tagged");
+        return Ok(());
+    }
+
+    let text = if let Some(shell) = shell {
+        let inner = shell.param("1").map_or("", |p| p.value.as_str());
+        let edit = shell.set_param(&lead.text, "1", &format!("{inner}\n{banner}"));
+        wikitext::apply(&lead.text, vec![edit])
+    } else {
+        format!("{banner}\n{}", lead.text.trim_start())
+    };
+
+    client
+        .edit_section(&talk, 0, &lead, &text, &summary, &FLAGS)
+        .await?;
+    after_edit(&*client.bot().await, client.api_url(), &talk).await;
+    Ok(())
+}
+
+/// Configuration pages linked from [`INDEX`].
+async fn opted_in(client: &wiki::Bot) -> Result<Vec<String>> {
+    let index = fetch_content(client, ENWIKI_API, INDEX).await?;
+    Ok(index
+        .lines()
+        .filter_map(|l| l.split_once("[[")?.1.split_once("]]"))
+        .map(|(link, _)| link.split('|').next().unwrap().trim().to_owned())
+        .collect())
+}
+
+pub async fn main() -> Result<()> {
+    let client = accounts::client_for(ENWIKI_API, &FLAGS).await?;
+    let site = SiteInfo::fetch(&*client.bot().await, ENWIKI_API).await?;
+
+    let bot = client.bot().await;
+    run_lock::locked(&bot, ENWIKI_API, FLAGS.task, async {
+        let scope = selection::get().scope(&bot, ENWIKI_API).await?;
+        for page in opted_in(&*client.bot().await).await? {
+            info!("Running for [[{page}]]");
+            let cfg: ProjectCfg = match serde_json::from_str(&client.fetch_content(&page).await?) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    warn!("invalid configuration at [[{page}]]: {e}");
+                    continue;
+                }
+            };
+            if cfg.categories.is_empty() {
+                warn!("[[{page}]] has no categories");
+                continue;
+            }
+
+            let bot = client.bot().await;
+            let aliases = banner_aliases(&bot, &cfg.banner).await?;
+            let pages = candidates(&bot, &cfg).await?;
+            info!("{} candidates for {}", pages.len(), cfg.banner);
+
+            // either the article or its talk page may be given
+            let selected = pages
+                .into_iter()
+                .filter(|a| scope.selects(a) || scope.selects(&site.talk_page(a)));
+            for article in scope.window(selected) {
+                let talk = site.talk_page(&article);
+                if !scope.todo(&talk) {
+                    continue;
+                }
+                let treated = || treat(&client, &site, &article, &cfg, &aliases);
+                policy::page(FLAGS.task, &talk, treated).await?;
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,222 @@
+//! A small wikitext parser that only knows about template boundaries and
+//! parameters.
+//!
+//! This is for tasks that don't need the rest of what Parsoid understands and
+//! can save the round trips. Anything it isn't sure about — unbalanced braces,
+//! template parameters (`{{{1}}}`) — is an error, and callers should fall back
+//! to Parsoid.
+
+use std::ops::Range;
+
+use color_eyre::eyre::bail;
+
+use crate::Result;
+
+/// Tags whose contents aren't parsed for templates.
+const OPAQUE_TAGS: &[&str] = &["nowiki", "pre", "math", "syntaxhighlight", "source"];
+
+#[derive(Debug)]
+pub struct Param {
+    /// Trimmed name. Positional parameters are numbered from 1.
+    pub name: String,
+    /// Trimmed value.
+    pub value: String,
+    /// The untrimmed value.
+    pub value_range: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct Template {
+    /// From the opening `{{` to after the closing `}}`.
+    pub range: Range<usize>,
+    pub name: String,
+    pub params: Vec<Param>,
+    /// Whether the closing `}}` is on a line of its own.
+    multiline: bool,
+}
+
+/// A replacement of `range` in the original text.
+#[derive(Debug)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Template {
+    /// The name without the namespace, lowercased for comparisons.
+    pub fn normalized_name(&self) -> String {
+        self.name
+            .trim_start_matches("Template:")
+            .replace('_', " ")
+            .to_ascii_lowercase()
+    }
+
+    pub fn param(&self, name: &str) -> Option<&Param> {
+        self.params.iter().rev().find(|p| p.name == name)
+    }
+
+    /// An edit setting `name` to `value`, keeping the whitespace around an
+    /// existing value.
+    pub fn set_param(&self, text: &str, name: &str, value: &str) -> Edit {
+        if let Some(p) = self.param(name) {
+            let old = &text[p.value_range.clone()];
+            let start = p.value_range.start + (old.len() - old.trim_start().len());
+            let end = p.value_range.start + old.trim_end().len();
+            return Edit {
+                range: if old.trim().is_empty() {
+                    p.value_range.start..p.value_range.start
+                } else {
+                    start..end
+                },
+                text: value.to_owned(),
+            };
+        }
+        let at = self.range.end - 2;
+        Edit {
+            range: at..at,
+            text: if self.multiline {
+                format!("| {name} = {value}\n")
+            } else {
+                format!("|{name}={value}")
+            },
+        }
+    }
+}
+
+/// Applies non-overlapping `edits` to `text`.
+pub fn apply(text: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|e| std::cmp::Reverse((e.range.start, e.range.end)));
+    let mut text = text.to_owned();
+    for e in edits {
+        text.replace_range(e.range, &e.text);
+    }
+    text
+}
+
+enum Frame {
+    Template {
+        start: usize,
+        /// Positions of top-level `|`s, each with the first top-level `=`
+        /// after it.
+        seps: Vec<(usize, Option<usize>)>,
+    },
+    Link,
+}
+
+fn build(text: &str, start: usize, end: usize, seps: Vec<(usize, Option<usize>)>) -> Template {
+    let inner_end = end - 2;
+    let name_end = seps.first().map_or(inner_end, |&(i, _)| i);
+    let mut params = Vec::new();
+    let mut positional = 0;
+    for (n, &(sep, eq)) in seps.iter().enumerate() {
+        let seg_end = seps.get(n + 1).map_or(inner_end, |&(i, _)| i);
+        let (name, value_range) = match eq {
+            Some(eq) => (text[sep + 1..eq].trim().to_owned(), eq + 1..seg_end),
+            None => {
+                positional += 1;
+                (positional.to_string(), sep + 1..seg_end)
+            }
+        };
+        params.push(Param {
+            name,
+            value: text[value_range.clone()].trim().to_owned(),
+            value_range,
+        });
+    }
+    Template {
+        range: start..end,
+        name: text[start + 2..name_end].trim().to_owned(),
+        params,
+        multiline: text[start..inner_end]
+            .trim_end_matches([' ', '\t'])
+            .ends_with('\n'),
+    }
+}
+
+/// Every template in `text`, including nested ones, ordered by where they
+/// start.
+pub fn parse_templates(text: &str) -> Result<Vec<Template>> {
+    let bytes = text.as_bytes();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut templates = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &text[i..];
+        if rest.starts_with("<!--") {
+            i += rest.find("-->").map_or(rest.len(), |e| e + 3);
+            continue;
+        }
+        if rest.starts_with('<') {
+            let head = rest[1..]
+                .chars()
+                .take(16)
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if let Some(tag) = OPAQUE_TAGS.iter().find(|t| {
+                head.starts_with(*t)
+                    && head[t.len()..]
+                        .starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace())
+            }) {
+                let close = format!("</{tag}>");
+                let open_end = rest.find('>').map_or(rest.len(), |e| e + 1);
+                i += if rest[..open_end].ends_with("/>") {
+                    open_end
+                } else {
+                    let lower = rest.to_ascii_lowercase();
+                    lower.find(&close).map_or(rest.len(), |e| e + close.len())
+                };
+                continue;
+            }
+        }
+        if rest.starts_with("{{{") {
+            bail!("template parameter at byte {i}");
+        }
+        if rest.starts_with("{{") {
+            stack.push(Frame::Template {
+                start: i,
+                seps: Vec::new(),
+            });
+            i += 2;
+            continue;
+        }
+        if rest.starts_with("[[") {
+            stack.push(Frame::Link);
+            i += 2;
+            continue;
+        }
+        if rest.starts_with("}}") {
+            match stack.pop() {
+                Some(Frame::Template { start, seps }) => {
+                    templates.push(build(text, start, i + 2, seps));
+                }
+                _ => bail!("unbalanced braces at byte {i}"),
+            }
+            i += 2;
+            continue;
+        }
+        if rest.starts_with("]]") {
+            if let Some(Frame::Link) = stack.last() {
+                stack.pop();
+            }
+            i += 2;
+            continue;
+        }
+        if let Some(Frame::Template { seps, .. }) = stack.last_mut() {
+            match bytes[i] {
+                b'|' => seps.push((i, None)),
+                b'=' => {
+                    if let Some((_, eq @ None)) = seps.last_mut() {
+                        *eq = Some(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    if stack.iter().any(|f| matches!(f, Frame::Template { .. })) {
+        bail!("unclosed template");
+    }
+    templates.sort_by_key(|t| t.range.start);
+    Ok(templates)
+}
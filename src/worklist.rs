@@ -0,0 +1,173 @@
+//! `--list-only` runs, which find the pages a task would edit without editing
+//! them, so that they can be spot-checked before a real run.
+//!
+//! Instead of saving, [`edit::pre_save`](crate::edit::pre_save) adds the page
+//! being treated to the worklist, with the number of changes to it if the task
+//! passed the text it replaces. The worklist is printed as it grows and written
+//! to `worklist-<run-id>.txt` at the end, one title per line, so that a real
+//! run can be narrowed down to it with `--file`. Saves made outside of a page,
+//! like reports, are dropped.
+//!
+//! At the end of a list-only run, and of a [`dry_run`], a summary of the
+//! changes is printed for sizing up the run in a BRFA: how many bytes pages
+//! grow or shrink by and how many templates are on changed lines, for pages
+//! whose old text is known, and how often each rule was hit, for tasks that
+//! report [`hit`]s.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+
+use tracing::{info, warn};
+
+use crate::{audit, dry_run};
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Titles in the order they were found.
+static TITLES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Default)]
+struct Stats {
+    /// How many bytes each page would grow by.
+    bytes: Vec<i64>,
+    /// How many templates are on the changed lines of each page.
+    templates: Vec<usize>,
+    /// Hits by rule.
+    rules: BTreeMap<String, usize>,
+}
+
+static STATS: Mutex<Stats> = Mutex::new(Stats {
+    bytes: Vec::new(),
+    templates: Vec::new(),
+    rules: BTreeMap::new(),
+});
+
+/// Reads `--list-only` out of a command line. Only the first call has an
+/// effect.
+pub fn from_args(args: &[String]) {
+    let _ = ENABLED.set(args.iter().any(|a| a == "--list-only"));
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// Adds `title`, which would have been saved with `new`, replacing `old` if
+/// the task passed it.
+pub fn add(title: &str, old: Option<&str>, new: &str) {
+    let hunks = old.map(|old| audit::hunks(old, new));
+    match &hunks {
+        Some(hunks) => println!("[[{title}]]\t{}", hunks.len()),
+        None => println!("[[{title}]]"),
+    }
+    count(title, old.zip(hunks), new);
+}
+
+/// Counts an edit that would have saved `new` to `title`, replacing `old`,
+/// in a dry run.
+pub fn shown(title: &str, old: Option<&str>, new: &str) {
+    count(title, old.map(|old| (old, audit::hunks(old, new))), new);
+}
+
+/// Counts the edit of `title` towards the summary, with the text it replaces
+/// and the hunks replacing it if those are known. Only the first edit of a
+/// page counts.
+fn count(title: &str, old: Option<(&str, Vec<audit::Hunk>)>, new: &str) {
+    let mut titles = TITLES.lock().unwrap();
+    if titles.iter().any(|t| t == title) {
+        return;
+    }
+    titles.push(title.to_owned());
+    let Some((old, hunks)) = old else {
+        return;
+    };
+    let templates = |lines: &[String]| lines.iter().map(|l| l.matches("{{").count()).sum();
+    let mut stats = STATS.lock().unwrap();
+    stats.bytes.push(new.len() as i64 - old.len() as i64);
+    stats.templates.push(
+        hunks
+            .iter()
+            .map(|h| usize::max(templates(&h.removed), templates(&h.added)))
+            .sum(),
+    );
+}
+
+/// Counts `n` hits of `rule`, in a list-only or dry run.
+pub fn hit(rule: &str, n: usize) {
+    if enabled() || dry_run::enabled() {
+        *STATS
+            .lock()
+            .unwrap()
+            .rules
+            .entry(rule.to_owned())
+            .or_default() += n;
+    }
+}
+
+fn median<T: Copy + Ord>(values: &[T]) -> Option<T> {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+/// The summary of the changes, as a table.
+fn summary(pages: usize, stats: &Stats) -> String {
+    let mut rows = vec![("pages".to_owned(), pages.to_string())];
+    if let Some(median_bytes) = median(&stats.bytes) {
+        let changed: Vec<_> = stats.bytes.iter().map(|b| b.abs()).collect();
+        rows.push((
+            "bytes added".to_owned(),
+            format!(
+                "median {median_bytes}, total {}",
+                stats.bytes.iter().sum::<i64>()
+            ),
+        ));
+        rows.push((
+            "bytes changed".to_owned(),
+            format!(
+                "median {}, max {}",
+                median(&changed).unwrap_or_default(),
+                changed.iter().max().unwrap_or(&0)
+            ),
+        ));
+    }
+    if let Some(median_templates) = median(&stats.templates) {
+        rows.push((
+            "templates on changed lines".to_owned(),
+            format!(
+                "median {median_templates}, total {}",
+                stats.templates.iter().sum::<usize>()
+            ),
+        ));
+    }
+    let mut rules: Vec<_> = stats.rules.iter().collect();
+    rules.sort_by(|a, b| b.1.cmp(a.1));
+    rows.extend(
+        rules
+            .into_iter()
+            .map(|(rule, n)| (format!("hits of {rule}"), n.to_string())),
+    );
+    let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or_default();
+    rows.iter()
+        .map(|(k, v)| format!("{k:width$}  {v}\n"))
+        .collect()
+}
+
+/// Writes the worklist of the run `id`, if it was a list-only run, and prints
+/// the summary of the changes of a list-only or dry run.
+pub fn write(id: &str) {
+    if !enabled() && !dry_run::enabled() {
+        return;
+    }
+    let titles = TITLES.lock().unwrap();
+    if enabled() {
+        let path = format!("worklist-{id}.txt");
+        let list: String = titles.iter().map(|t| format!("{t}\n")).collect();
+        match fs::write(&path, list) {
+            Ok(()) => info!("{} page(s) would be edited, listed in {path}", titles.len()),
+            Err(e) => warn!("failed to write {path}: {e}"),
+        }
+    }
+    print!("{}", summary(titles.len(), &STATS.lock().unwrap()));
+}
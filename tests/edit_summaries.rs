@@ -0,0 +1,43 @@
+//! Snapshots of the edit summaries each wiki's tracker removal edits get, so
+//! that pluralization and BRFA links don't change unnoticed.
+
+use deadbeefbot::remove_twitter_trackers::{EditMessage, SiteCfg, ENWIKI, ZHWIKI};
+
+/// `(links_fixed, wayback_links_fixed)` pairs worth covering.
+const CASES: &[(usize, usize)] = &[(1, 0), (2, 0), (1, 1), (3, 2), (0, 1)];
+
+fn summaries(site: &SiteCfg) -> String {
+    CASES
+        .iter()
+        .map(|&(links_fixed, wayback_links_fixed)| {
+            let msg = EditMessage {
+                links_fixed,
+                wayback_links_fixed,
+            };
+            format!("{links_fixed} {wayback_links_fixed}: {}", site.format(msg))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn enwiki() {
+    insta::assert_snapshot!(summaries(&ENWIKI), @r"
+    1 0: Removing Twitter tracker params ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) (1 link fixed)
+    2 0: Removing Twitter tracker params ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) (2 links fixed)
+    1 1: Removing Twitter tracker params ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) (1 link fixed, 1 archive link fixed)
+    3 2: Removing Twitter tracker params ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) (3 links fixed, 2 archive links fixed)
+    0 1: Removing Twitter tracker params ([[Wikipedia:Bots/Requests for approval/DeadbeefBot 1|BRFA]]) (0 links fixed, 1 archive link fixed)
+    ");
+}
+
+#[test]
+fn zhwiki() {
+    insta::assert_snapshot!(summaries(&ZHWIKI), @r"
+    1 0: BOT：已从1个Twitter外链删除追踪参数 ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])
+    2 0: BOT：已从2个Twitter外链删除追踪参数 ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])
+    1 1: BOT：已从1个Twitter外链删除追踪参数，同时修改1个存档链接 ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])
+    3 2: BOT：已从3个Twitter外链删除追踪参数，同时修改2个存档链接 ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])
+    0 1: BOT：已从0个Twitter外链删除追踪参数，同时修改1个存档链接 ([[Wikipedia:机器人/申请/DeadbeefBot|BRFA]])
+    ");
+}
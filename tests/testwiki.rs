@@ -0,0 +1,143 @@
+//! End-to-end runs of tasks against sandbox pages on test.wikipedia.org, to
+//! catch API and Parsoid changes before they reach enwiki.
+//!
+//! These edit a live wiki, so they are ignored by default and skip
+//! themselves unless `TESTWIKI_TOKEN_FILE` points to an OAuth token for
+//! testwiki:
+//!
+//! ```sh
+//! TESTWIKI_TOKEN_FILE=./token-testwiki.secret cargo test --test testwiki -- --ignored
+//! ```
+//!
+//! Each test sets up its page under [`SANDBOX`] and blanks it afterwards.
+//! Tasks that only work on enwiki aren't covered.
+
+use std::env;
+
+use deadbeefbot::edit::{save, EditFlags, TaskFlags};
+use deadbeefbot::{
+    category_redirects, short_description, site_with_token, typos, Parsoid, ParsoidBackend, Result,
+};
+use wiki::req::PageSpec;
+
+const TESTWIKI_API: &str = "https://test.wikipedia.org/w/api.php";
+const SANDBOX: &str = "User:DeadbeefBot/testcases";
+const RULE: &str = r#"<Typo word="abandoned" find="\b(a)bandonned\b" replace="$1bandoned" />"#;
+const FLAGS: TaskFlags = TaskFlags::new("testwiki", EditFlags::NONE);
+
+struct Testwiki {
+    client: wiki::Bot,
+    parsoid: Parsoid,
+}
+
+impl Testwiki {
+    /// `None` when no token is configured.
+    async fn login() -> Result<Option<Testwiki>> {
+        let Ok(token_file) = env::var("TESTWIKI_TOKEN_FILE") else {
+            eprintln!("TESTWIKI_TOKEN_FILE is not set, skipping");
+            return Ok(None);
+        };
+        Ok(Some(Testwiki {
+            client: site_with_token(TESTWIKI_API, Some(&token_file)).await?,
+            parsoid: Parsoid::new(TESTWIKI_API, ParsoidBackend::RestBase)?,
+        }))
+    }
+
+    /// Creates the sandbox page for `task` with `text`, returning its title.
+    async fn set_up(&self, task: &str, text: &str) -> Result<String> {
+        let title = format!("{SANDBOX}/{task}");
+        let page = PageSpec::Title(title.clone());
+        save(
+            &self.client,
+            page,
+            None,
+            text,
+            "Setting up test",
+            None,
+            &FLAGS,
+        )
+        .await?;
+        Ok(title)
+    }
+
+    async fn content(&self, title: &str) -> Result<String> {
+        Ok(self.client.fetch_content(title).await?)
+    }
+
+    async fn tear_down(&self, title: &str) -> Result<()> {
+        let page = PageSpec::Title(title.to_owned());
+        save(
+            &self.client,
+            page,
+            None,
+            "",
+            "Cleaning up test",
+            None,
+            &FLAGS,
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+#[ignore = "edits test.wikipedia.org"]
+async fn adds_short_description() -> Result<()> {
+    let Some(wiki) = Testwiki::login().await? else {
+        return Ok(());
+    };
+    let title = wiki
+        .set_up("short_description", "'''Foo''' is a bar.")
+        .await?;
+    short_description::treat(&wiki.client, &wiki.parsoid, &title, "Bar").await?;
+    let text = wiki.content(&title).await?;
+    wiki.tear_down(&title).await?;
+    assert!(
+        text.starts_with("{{Short description|Bar}}\n'''Foo'''"),
+        "{text}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "edits test.wikipedia.org"]
+async fn moves_category_redirects() -> Result<()> {
+    let Some(wiki) = Testwiki::login().await? else {
+        return Ok(());
+    };
+    let title = wiki
+        .set_up(
+            "category_redirects",
+            "Text.\n[[Category:Old name|Key]]\n[[Category:Old name]]",
+        )
+        .await?;
+    let changed = category_redirects::treat(
+        &wiki.client,
+        &title,
+        "Category:Old name",
+        "Category:New name",
+        "Test",
+    )
+    .await?;
+    let text = wiki.content(&title).await?;
+    wiki.tear_down(&title).await?;
+    assert!(changed);
+    assert_eq!(text.trim_end(), "Text.\n[[Category:New name|Key]]");
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "edits test.wikipedia.org"]
+async fn fixes_typos() -> Result<()> {
+    let Some(wiki) = Testwiki::login().await? else {
+        return Ok(());
+    };
+    let rules = typos::parse_rules(RULE);
+    let title = wiki
+        .set_up("typos", "The town was abandonned. \"abandonned\"")
+        .await?;
+    typos::treat(&wiki.client, &wiki.parsoid, &rules, &title).await?;
+    let text = wiki.content(&title).await?;
+    wiki.tear_down(&title).await?;
+    assert_eq!(text.trim_end(), "The town was abandoned. \"abandonned\"");
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Properties of the URL rewriting in `remove_twitter_trackers`, which edits
+//! links in article content.
+
+use std::time::Instant;
+
+use deadbeefbot::remove_twitter_trackers::{find_urls, treat_url, BAD_PARAMS};
+use fancy_regex::Regex;
+use proptest::prelude::*;
+use url::Url;
+
+/// The lookbehind regex [`find_urls`] replaced, which it should agree with.
+const LOOKBEHIND: &str =
+    r"(?<!\?url=|/|cache:)https?://(?:mobile\.)?(?:twitter|x)\.com/\w+/status/\d+\?[^\s}<|]+";
+
+fn lookbehind_urls(text: &str) -> Vec<std::ops::Range<usize>> {
+    let re = Regex::new(LOOKBEHIND).unwrap();
+    re.find_iter(text).map(|m| m.unwrap().range()).collect()
+}
+
+fn param() -> impl Strategy<Value = String> {
+    let key = "[a-z_]{1,8}".prop_filter("tracker", |k| !BAD_PARAMS.contains(&k.as_str()));
+    (key, "[A-Za-z0-9._~+-]{0,10}|%[0-9A-F]{2}").prop_map(|(k, v)| format!("{k}={v}"))
+}
+
+fn tracker() -> impl Strategy<Value = String> {
+    (prop::sample::select(BAD_PARAMS), "[A-Za-z0-9]{0,10}").prop_map(|(k, v)| format!("{k}={v}"))
+}
+
+/// Any URL [`RE`](deadbeefbot::remove_twitter_trackers::RE) could match.
+const MATCHED: &str =
+    r"https?://(mobile\.)?(twitter|x)\.com/\w{1,10}/status/[0-9]{1,19}\?[^\s}<|]{1,40}";
+
+/// Wikitext with tweet URLs, some in archive links or after a `/`, and
+/// things that look like them.
+fn wikitext() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        "[a-z |=}{<]{0,12}",
+        MATCHED.prop_map(String::from),
+        MATCHED.prop_map(|url| format!("https://web.archive.org/web/2020/{url}")),
+        MATCHED.prop_map(|url| format!("https://a.example/?url={url}")),
+        MATCHED.prop_map(|url| format!("cache:{url}")),
+        Just("https://fox.com/a/status/1?s=1".to_owned()),
+        Just("http://mobile.x.com/".to_owned()),
+    ];
+    prop::collection::vec(piece, 0..8).prop_map(|pieces| pieces.concat())
+}
+
+/// A tweet URL with parameters and trackers mixed in its query, with the
+/// parameters it should end up with.
+fn tweet() -> impl Strategy<Value = (String, Vec<String>)> {
+    (
+        "(mobile\\.)?(twitter|x)",
+        "[A-Za-z0-9_]{1,15}",
+        1u64..u64::MAX,
+        prop::collection::vec(param(), 0..5),
+        prop::collection::vec(tracker(), 0..3),
+        "(#[a-z]{1,5})?",
+    )
+        .prop_flat_map(|(host, user, id, kept, trackers, fragment)| {
+            let all: Vec<_> = kept.iter().cloned().chain(trackers).collect();
+            (Just(kept), Just(all).prop_shuffle()).prop_map(move |(kept, all)| {
+                // shuffling may have reordered the kept params
+                let kept: Vec<_> = all.iter().filter(|p| kept.contains(p)).cloned().collect();
+                let url = format!(
+                    "https://{host}.com/{user}/status/{id}?{}{fragment}",
+                    all.join("&")
+                );
+                (url, kept)
+            })
+        })
+}
+
+proptest! {
+    #[test]
+    fn idempotent((url, _) in tweet()) {
+        let once = treat_url(&url).unwrap();
+        prop_assert_eq!(treat_url(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn keeps_other_params_in_order((url, kept) in tweet()) {
+        let treated = Url::parse(&treat_url(&url).unwrap()).unwrap();
+        let query = treated.query().unwrap_or_default();
+        let params: Vec<_> = query.split('&').filter(|p| !p.is_empty()).collect();
+        prop_assert_eq!(params, kept);
+    }
+
+    #[test]
+    fn removes_trackers((url, _) in tweet()) {
+        let treated = Url::parse(&treat_url(&url).unwrap()).unwrap();
+        for (key, _) in treated.query_pairs() {
+            prop_assert!(!BAD_PARAMS.contains(&&*key), "{key} left in {treated}");
+        }
+    }
+
+    #[test]
+    fn reparses(s in MATCHED) {
+        if let Ok(treated) = treat_url(&s) {
+            prop_assert!(Url::parse(&treated).is_ok(), "{treated}");
+        }
+    }
+
+    #[test]
+    fn finds_what_lookbehind_found(text in wikitext()) {
+        prop_assert_eq!(find_urls(&text), lookbehind_urls(&text));
+    }
+
+    #[test]
+    fn leaves_clean_urls_alone(s in "https://twitter\\.com/[a-z]{1,10}/status/[0-9]{1,19}") {
+        prop_assert_eq!(treat_url(&s).unwrap(), s);
+    }
+}
+
+/// Times [`find_urls`] against the lookbehind regex on a page of a few
+/// megabytes with a link every few hundred bytes. Run with `cargo test
+/// --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn large_page_timing() {
+    let mut text = String::new();
+    while text.len() < 4_000_000 {
+        let n = text.len();
+        text.push_str(&"Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(5));
+        text.push_str(&format!(
+            "<ref>{{{{cite tweet |url=https://twitter.com/u/status/{n}?s=20&t=x \
+            |archive-url=https://web.archive.org/web/2022/https://x.com/u/status/{n}?s=1\
+            }}}}</ref>\n"
+        ));
+    }
+    let start = Instant::now();
+    let found = find_urls(&text);
+    let fast = start.elapsed();
+    let start = Instant::now();
+    let expected = lookbehind_urls(&text);
+    let slow = start.elapsed();
+    assert_eq!(found, expected);
+    println!(
+        "{} URLs in {} bytes: {fast:?}, lookbehind {slow:?}",
+        found.len(),
+        text.len()
+    );
+}
@@ -0,0 +1,67 @@
+//! The template parser in `wikitext`, which tasks edit with instead of Parsoid
+//! and so has to leave everything it doesn't touch as it was.
+
+use deadbeefbot::wikitext::{apply, parse_templates};
+
+fn set(text: &str, name: &str, value: &str) -> String {
+    let templates = parse_templates(text).unwrap();
+    apply(text, vec![templates[0].set_param(text, name, value)])
+}
+
+#[test]
+fn nested() {
+    let text = "{{outer|a={{inner|x}}|b}}";
+    let templates = parse_templates(text).unwrap();
+    assert_eq!(templates.len(), 2);
+    let (outer, inner) = (&templates[0], &templates[1]);
+    assert_eq!(outer.name, "outer");
+    assert_eq!(outer.range, 0..text.len());
+    assert_eq!(outer.param("a").unwrap().value, "{{inner|x}}");
+    assert_eq!(outer.param("1").unwrap().value, "b");
+    assert_eq!(inner.name, "inner");
+    assert_eq!(&text[inner.range.clone()], "{{inner|x}}");
+    assert_eq!(inner.param("1").unwrap().value, "x");
+}
+
+#[test]
+fn template_parameters() {
+    assert!(parse_templates("{{foo|{{{1}}}}}").is_err());
+    assert!(parse_templates("{{{title|}}}").is_err());
+}
+
+#[test]
+fn unbalanced() {
+    assert!(parse_templates("{{foo|bar").is_err());
+    assert!(parse_templates("foo}}").is_err());
+    assert!(parse_templates("{{foo}}}}").is_err());
+}
+
+#[test]
+fn set_existing() {
+    assert_eq!(
+        set("{{foo| bar = old |baz}}", "bar", "new"),
+        "{{foo| bar = new |baz}}"
+    );
+    assert_eq!(set("{{foo|bar=}}", "bar", "new"), "{{foo|bar=new}}");
+}
+
+#[test]
+fn set_missing() {
+    assert_eq!(set("{{foo|a=1}}", "b", "2"), "{{foo|a=1|b=2}}");
+    assert_eq!(
+        set("{{foo\n| a = 1\n}}", "b", "2"),
+        "{{foo\n| a = 1\n| b = 2\n}}"
+    );
+}
+
+#[test]
+fn untouched() {
+    let text = "Intro <!-- {{not|this}} --> {{foo|a=1}} <nowiki>{{x}}</nowiki> [[a|b]] end";
+    assert_eq!(apply(text, Vec::new()), text);
+    let templates = parse_templates(text).unwrap();
+    assert_eq!(templates.len(), 1);
+    assert_eq!(
+        set(text, "a", "2"),
+        "Intro <!-- {{not|this}} --> {{foo|a=2}} <nowiki>{{x}}</nowiki> [[a|b]] end"
+    );
+}